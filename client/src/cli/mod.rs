@@ -1,15 +1,34 @@
+pub mod output;
 pub mod subcommands;
 
-use crate::core::{context::Context, service::client};
-use common::syncx::syncx_client::SyncxClient;
+use output::{progress, OutputFormat};
 use subcommands::*;
+use syncx_client::{
+    core::profile::Profiles, CommandError, DownloadOptions, FileSelection, Report, RestoreOptions,
+    SyncxSession, UploadOptions,
+};
 
 use clap::{Parser, Subcommand};
+use common::syncx::proof_request::Leaf;
+use serde::Serialize;
 use std::path::Path;
+use std::time::Duration;
 
 #[derive(Debug, Parser)]
 #[clap(name = "syncx client", author = "0xphen", version)]
 struct Arguments {
+    #[clap(long = "server", global = true, env = "SYNCX_SERVER")]
+    #[clap(help = "Address of the server. Defaults to the endpoint in the client config")]
+    server: Option<String>,
+
+    #[clap(long = "profile", global = true, env = "SYNCX_PROFILE")]
+    #[clap(help = "Profile to use. Defaults to the one chosen with `profile use`")]
+    profile: Option<String>,
+
+    #[clap(long = "output", global = true, value_enum, default_value_t = OutputFormat::Text)]
+    #[clap(help = "Print results as text, or as one JSON document per command")]
+    output: OutputFormat,
+
     #[clap(subcommand)]
     sub: Subcommands,
 }
@@ -26,31 +45,265 @@ enum Subcommands {
         about = "Create an account on the Syncx server"
     )]
     CreateAccount(CreateAccountArgs),
+
+    #[clap(
+        name = "login",
+        about = "Log into an account, or refresh the profile's auth token"
+    )]
+    Login(LoginArgs),
+
     #[clap(name = "upload", about = "Upload files to the Syncx server")]
     UploadFiles(UploadFilesArgs),
 
-    #[clap(name = "download", about = "Download a file from the Sync server")]
+    #[clap(
+        name = "watch",
+        about = "Sync a directory with the Syncx server whenever it changes"
+    )]
+    Watch(WatchArgs),
+
+    #[clap(
+        name = "sync",
+        about = "Sync a directory both ways with the Syncx server"
+    )]
+    Sync(SyncArgs),
+
+    #[clap(name = "download", about = "Download files from the Sync server")]
     DownloadFile(DownloadFileArgs),
 
+    #[clap(name = "list", about = "List the files the server holds in a batch")]
+    List(ListArgs),
+
     #[clap(name = "merkleroot", about = "View merkle root of uploaded files")]
     MerkleRoot,
+
+    #[clap(
+        name = "status",
+        about = "View the processing status of an uploaded batch"
+    )]
+    JobStatus(JobStatusArgs),
+
+    #[clap(
+        name = "proof",
+        about = "Verify an uploaded file without downloading it"
+    )]
+    Proof(ProofArgs),
+
+    #[clap(name = "serverroot", about = "View the merkle root held by the server")]
+    ServerRoot(ServerRootArgs),
+
+    #[clap(
+        name = "audit",
+        about = "Check that the server still holds a sample of an uploaded batch"
+    )]
+    Audit(AuditArgs),
+
+    #[clap(
+        name = "verify",
+        about = "Check local files against the merkle root recorded when they were uploaded"
+    )]
+    Verify(VerifyArgs),
+
+    #[clap(name = "restore", about = "Restore every file of an uploaded batch")]
+    Restore(RestoreArgs),
+
+    #[clap(name = "profile", about = "Manage client profiles")]
+    Profile(ProfileArgs),
 }
 
-pub async fn run(syncx_client: &mut SyncxClient<tonic::transport::Channel>, context: &mut Context) {
+impl Subcommands {
+    /// The name the command is reported under in JSON output.
+    fn name(&self) -> &'static str {
+        match self {
+            Subcommands::CreateAccount(_) => "create_account",
+            Subcommands::Login(_) => "login",
+            Subcommands::UploadFiles(_) => "upload",
+            Subcommands::Watch(_) => "watch",
+            Subcommands::Sync(_) => "sync",
+            Subcommands::DownloadFile(_) => "download",
+            Subcommands::List(_) => "list",
+            Subcommands::MerkleRoot => "merkleroot",
+            Subcommands::JobStatus(_) => "status",
+            Subcommands::Proof(_) => "proof",
+            Subcommands::ServerRoot(_) => "serverroot",
+            Subcommands::Audit(_) => "audit",
+            Subcommands::Verify(_) => "verify",
+            Subcommands::Restore(_) => "restore",
+            Subcommands::Profile(_) => "profile",
+        }
+    }
+}
+
+/// Runs the command given on the command line, prints its result and returns
+/// the process's exit code.
+pub async fn run() -> i32 {
     let args = Arguments::parse();
-    match args.sub {
+    output::set_format(args.output);
+
+    let command = args.sub.name();
+    execute(args)
+        .await
+        .unwrap_or_else(|e| output::fail(command, e))
+}
+
+async fn execute(args: Arguments) -> Result<i32, CommandError> {
+    let command = args.sub.name();
+    if let Subcommands::Profile(args) = args.sub {
+        return run_profile_command(&Profiles::open()?, args.command);
+    }
+
+    let mut builder = SyncxSession::builder().on_progress(output::progress_printer());
+    if let Some(profile) = &args.profile {
+        builder = builder.profile(profile);
+    }
+    if let Some(server) = &args.server {
+        builder = builder.server(server);
+    }
+    let session = &mut builder.open()?;
+
+    let code = match args.sub {
         Subcommands::CreateAccount(args) => {
-            client::register_client(syncx_client, args.password, context).await;
+            output::finish(command, session.register(args.password).await)
         }
+        Subcommands::Login(args) => output::finish(
+            command,
+            session.login(args.id.as_deref(), args.password).await,
+        ),
         Subcommands::UploadFiles(args) => {
-            client::upload_files(syncx_client, &args.directory, context).await
+            let options = UploadOptions {
+                wait: args.wait,
+                move_files: args.move_files,
+                full: args.full,
+            };
+            output::finish(command, session.upload_dir(&args.directory, options).await)
         }
+        Subcommands::Watch(args) => output::finish(
+            command,
+            session
+                .watch(&args.directory, Duration::from_millis(args.debounce_ms))
+                .await,
+        ),
+        Subcommands::Sync(args) => output::finish(
+            command,
+            session.sync(&args.directory, !args.no_metadata).await,
+        ),
         Subcommands::DownloadFile(args) => {
-            let path = Path::new(&args.directory).to_path_buf();
-            client::download_file(syncx_client, &args.filename, &path, context).await
+            let files = match args.glob {
+                Some(pattern) => FileSelection::Glob(pattern),
+                None => FileSelection::Names(args.filenames),
+            };
+            let options = DownloadOptions {
+                batch: args.batch,
+                apply_metadata: !args.no_metadata,
+            };
+            output::finish(
+                command,
+                session
+                    .download(files, Path::new(&args.directory), options)
+                    .await,
+            )
         }
-        Subcommands::MerkleRoot => {
-            println!("Merkle root: <{}>", context.app_config.merkle_tree_root)
+        Subcommands::List(args) => {
+            output::finish(command, session.list(args.batch.as_deref()).await)
         }
-    }
+        Subcommands::MerkleRoot => output::finish(command, Ok(session.merkle_roots())),
+        Subcommands::JobStatus(args) => {
+            output::finish(command, session.job_status(args.batch.as_deref()).await)
+        }
+        Subcommands::Proof(args) => {
+            let leaf = match (args.filename, args.hash) {
+                (Some(file_name), _) => Leaf::FileName(file_name),
+                (None, Some(hash)) => Leaf::ContentHash(hash),
+                (None, None) => unreachable!("clap requires a file name or hash"),
+            };
+            output::finish(command, session.proof(leaf, args.batch.as_deref()).await)
+        }
+        Subcommands::ServerRoot(args) => {
+            output::finish(command, session.server_root(args.batch.as_deref()).await)
+        }
+        Subcommands::Audit(args) => output::finish(
+            command,
+            session.audit(args.batch.as_deref(), args.fraction).await,
+        ),
+        Subcommands::Verify(args) => output::finish(
+            command,
+            session
+                .verify(&args.directory, args.batch.as_deref(), args.prove)
+                .await,
+        ),
+        Subcommands::Restore(args) => {
+            let directory = args.directory.map(|d| Path::new(&d).to_path_buf());
+            let options = RestoreOptions {
+                apply_metadata: !args.no_metadata,
+                overwrite: args.force,
+            };
+            output::finish(
+                command,
+                session
+                    .restore(args.batch.as_deref(), directory, options)
+                    .await,
+            )
+        }
+        Subcommands::Profile(_) => unreachable!("profile commands are handled above"),
+    };
+
+    Ok(code)
+}
+
+#[derive(Serialize, Debug)]
+struct ProfilesReport {
+    current: String,
+    profiles: Vec<ProfileEntry>,
+}
+
+#[derive(Serialize, Debug)]
+struct ProfileEntry {
+    name: String,
+    endpoint: Option<String>,
+}
+
+impl Report for ProfilesReport {}
+
+#[derive(Serialize, Debug)]
+struct ProfileReport {
+    profile: String,
+}
+
+impl Report for ProfileReport {}
+
+fn run_profile_command(profiles: &Profiles, command: ProfileCommands) -> Result<i32, CommandError> {
+    let code = match command {
+        ProfileCommands::List => {
+            let current = profiles.current();
+            let mut report = ProfilesReport {
+                current: current.to_string(),
+                profiles: Vec::new(),
+            };
+
+            for name in profiles.list() {
+                let marker = if name == current { "*" } else { " " };
+                let endpoint = profiles
+                    .read_config(&name)
+                    .map(|app_config| app_config.connection.endpoint);
+                match &endpoint {
+                    Some(endpoint) => progress!("{} {} ({})", marker, name, endpoint),
+                    None => progress!("{} {}", marker, name),
+                }
+                report.profiles.push(ProfileEntry { name, endpoint });
+            }
+
+            output::finish("profile list", Ok(report))
+        }
+        ProfileCommands::Use(args) => {
+            profiles.set_current(&args.name)?;
+            progress!("Now using profile {}", args.name);
+            output::finish("profile use", Ok(ProfileReport { profile: args.name }))
+        }
+        ProfileCommands::Remove(args) => {
+            profiles.remove(&args.name)?;
+            progress!("Removed profile {}", args.name);
+            output::finish("profile remove", Ok(ProfileReport { profile: args.name }))
+        }
+    };
+
+    Ok(code)
 }
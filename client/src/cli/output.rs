@@ -0,0 +1,141 @@
+use indicatif::{
+    HumanBytes, HumanDuration, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle,
+};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::IsTerminal;
+use std::sync::{Mutex, OnceLock};
+use syncx_client::{CommandError, Progress, Report, Stage};
+
+/// How a command reports its result.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutputFormat {
+    /// Human readable lines on stdout.
+    #[default]
+    Text,
+    /// One JSON document on stdout, with progress lines on stderr.
+    Json,
+}
+
+static FORMAT: OnceLock<OutputFormat> = OnceLock::new();
+
+pub fn set_format(format: OutputFormat) {
+    let _ = FORMAT.set(format);
+}
+
+pub fn format() -> OutputFormat {
+    FORMAT.get().copied().unwrap_or_default()
+}
+
+/// Prints a line of progress. In JSON mode it goes to stderr, so stdout only
+/// holds the result document.
+pub fn write_progress(args: fmt::Arguments) {
+    match format() {
+        OutputFormat::Text => println!("{}", args),
+        OutputFormat::Json => eprintln!("{}", args),
+    }
+}
+
+/// Returns a handler that prints the progress reported by a session. Transfers
+/// are drawn as progress bars on stdout, unless it isn't a terminal or JSON
+/// output is selected, in which case only messages are printed.
+pub fn progress_printer() -> impl Fn(&Progress) + Send + Sync {
+    let draw_bars = format() == OutputFormat::Text && std::io::stdout().is_terminal();
+    let bars = draw_bars.then(|| MultiProgress::with_draw_target(ProgressDrawTarget::stdout()));
+    let active = Mutex::new(HashMap::<Stage, ProgressBar>::new());
+
+    move |progress| match (progress, &bars) {
+        (Progress::Message(_), Some(bars)) => bars.suspend(|| println!("{}", progress)),
+        (Progress::Message(_), None) => write_progress(format_args!("{}", progress)),
+        (Progress::Transfer { stage, done, total }, Some(bars)) => {
+            let mut active = active.lock().unwrap();
+            let bar = active
+                .entry(*stage)
+                .or_insert_with(|| bars.add(transfer_bar(*stage)));
+            bar.set_length(*total);
+            bar.set_position(*done);
+        }
+        // A finished bar is replaced with a line that stays above the others.
+        (Progress::Finished(stage), Some(bars)) => {
+            if let Some(bar) = active.lock().unwrap().remove(stage) {
+                bar.finish_and_clear();
+                bars.remove(&bar);
+                bars.suspend(|| {
+                    println!(
+                        "{} {} of {} in {}",
+                        stage,
+                        HumanBytes(bar.position()),
+                        HumanBytes(bar.length().unwrap_or_default()),
+                        HumanDuration(bar.elapsed())
+                    )
+                });
+            }
+        }
+        _ => {}
+    }
+}
+
+fn transfer_bar(stage: Stage) -> ProgressBar {
+    let style = ProgressStyle::with_template(
+        "{msg:<11} [{bar:30}] {bytes}/{total_bytes} {bytes_per_sec} ETA {eta}",
+    )
+    .expect("valid progress bar template")
+    .progress_chars("=> ");
+
+    ProgressBar::new(0)
+        .with_style(style)
+        .with_message(stage.to_string())
+}
+
+macro_rules! progress {
+    ($($arg:tt)*) => {
+        $crate::cli::output::write_progress(format_args!($($arg)*))
+    };
+}
+pub(crate) use progress;
+
+/// What a command prints in JSON mode. Every field is always present.
+#[derive(Serialize)]
+struct Document<'a, T: Serialize> {
+    command: &'a str,
+    /// `ok` or `error`.
+    status: &'static str,
+    result: Option<&'a T>,
+    error: Option<&'a CommandError>,
+}
+
+/// Prints the outcome of `command` and returns the process's exit code.
+pub fn finish<T: Report>(command: &str, outcome: Result<T, CommandError>) -> i32 {
+    let (result, error) = match outcome {
+        Ok(report) => {
+            let failure = report.failure();
+            (Some(report), failure)
+        }
+        Err(e) => (None, Some(e)),
+    };
+
+    match format() {
+        OutputFormat::Json => {
+            let document = Document {
+                command,
+                status: if error.is_none() { "ok" } else { "error" },
+                result: result.as_ref(),
+                error: error.as_ref(),
+            };
+            println!("{}", serde_json::to_string(&document).unwrap());
+        }
+        OutputFormat::Text => {
+            if let Some(error) = &error {
+                eprintln!("error: {}", error);
+            }
+        }
+    }
+
+    error.map(|e| e.kind.exit_code()).unwrap_or(0)
+}
+
+/// Prints the error of a command that failed before it had anything to report.
+pub fn fail(command: &str, error: CommandError) -> i32 {
+    finish::<()>(command, Err(error))
+}
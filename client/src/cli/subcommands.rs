@@ -1,4 +1,4 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use derive_builder::Builder;
 
 #[derive(Debug, Clone, Parser, Builder)]
@@ -9,22 +9,203 @@ pub struct CreateAccountArgs {
     pub password: String,
 }
 
+#[derive(Debug, Clone, Parser, Builder)]
+#[clap(about = "Log into an account, or refresh the profile's auth token")]
+pub struct LoginArgs {
+    #[clap(long = "id")]
+    #[clap(help = "Account to log into. Defaults to the account the profile holds")]
+    pub id: Option<String>,
+
+    #[clap(required = true)]
+    #[clap(long = "password", short = 'p')]
+    pub password: String,
+}
+
 #[derive(Debug, Clone, Parser, Builder)]
 #[clap(about = "Upload a list of files to the Syncx server")]
 pub struct UploadFilesArgs {
     #[clap(required = true)]
     #[clap(long = "directory", short = 'd')]
     pub directory: String,
+
+    #[clap(long = "wait", short = 'w')]
+    #[clap(help = "Wait for the server to process the upload and print its merkle root")]
+    pub wait: bool,
+
+    #[clap(long = "move")]
+    #[clap(help = "Delete the uploaded files from the directory once the upload is committed")]
+    pub move_files: bool,
+
+    #[clap(long = "full")]
+    #[clap(help = "Upload every file, even those unchanged since the directory's last batch")]
+    pub full: bool,
+}
+
+#[derive(Debug, Clone, Parser, Builder)]
+#[clap(about = "Keep a directory synced with the Syncx server")]
+pub struct WatchArgs {
+    #[clap(required = true)]
+    #[clap(long = "directory", short = 'd')]
+    pub directory: String,
+
+    #[clap(long = "debounce", default_value_t = 2000)]
+    #[clap(help = "Milliseconds without further changes to wait for before syncing")]
+    pub debounce_ms: u64,
 }
 
 #[derive(Debug, Clone, Parser, Builder)]
-#[clap(about = "Download a file from the syncx server")]
+#[clap(about = "Sync a directory both ways with the Syncx server")]
+pub struct SyncArgs {
+    #[clap(required = true)]
+    #[clap(long = "directory", short = 'd')]
+    pub directory: String,
+
+    #[clap(long = "no-metadata")]
+    #[clap(help = "Don't apply the recorded permissions and modification times")]
+    pub no_metadata: bool,
+}
+
+#[derive(Debug, Clone, Parser, Builder)]
+#[clap(about = "Download files from the syncx server")]
+#[clap(group(clap::ArgGroup::new("files").required(true).args(&["filenames", "glob"])))]
 pub struct DownloadFileArgs {
+    #[clap(long = "filename", short = 'f', num_args = 1..)]
+    #[clap(help = "Names of the files to download")]
+    pub filenames: Vec<String>,
+
+    #[clap(long = "glob", short = 'g')]
+    #[clap(help = "Download every file of the batch whose name matches this pattern")]
+    pub glob: Option<String>,
+
     #[clap(required = true)]
+    #[clap(long = "directory", short = 'd')]
+    pub directory: String,
+
+    #[clap(long = "batch", short = 'b')]
+    #[clap(help = "Batch to download from. Defaults to the latest batch containing the file")]
+    pub batch: Option<String>,
+
+    #[clap(long = "no-metadata")]
+    #[clap(help = "Keep default modes and modification times instead of the uploaded ones")]
+    pub no_metadata: bool,
+}
+
+#[derive(Debug, Clone, Parser, Builder)]
+#[clap(about = "List the files the server holds in a batch")]
+pub struct ListArgs {
+    #[clap(long = "batch", short = 'b')]
+    #[clap(help = "Batch to list. Defaults to the latest uploaded batch")]
+    pub batch: Option<String>,
+}
+
+#[derive(Debug, Clone, Parser, Builder)]
+#[clap(about = "View the processing status of an uploaded batch")]
+pub struct JobStatusArgs {
+    #[clap(long = "batch", short = 'b')]
+    #[clap(help = "Batch to check. Defaults to the latest uploaded batch")]
+    pub batch: Option<String>,
+}
+
+#[derive(Debug, Clone, Parser, Builder)]
+#[clap(about = "Fetch and verify the merkle proof of an uploaded file")]
+#[clap(group(clap::ArgGroup::new("leaf").required(true).args(&["filename", "hash"])))]
+pub struct ProofArgs {
     #[clap(long = "filename", short = 'f')]
-    pub filename: String,
+    pub filename: Option<String>,
+
+    #[clap(long = "hash")]
+    #[clap(help = "Content hash of the file")]
+    pub hash: Option<String>,
 
+    #[clap(long = "batch", short = 'b')]
+    #[clap(help = "Batch to prove against. Defaults to the latest batch")]
+    pub batch: Option<String>,
+}
+
+#[derive(Debug, Clone, Parser, Builder)]
+#[clap(about = "View the merkle root held by the server")]
+pub struct ServerRootArgs {
+    #[clap(long = "batch", short = 'b')]
+    #[clap(help = "Batch to look up. Defaults to the latest uploaded batch")]
+    pub batch: Option<String>,
+}
+
+#[derive(Debug, Clone, Parser, Builder)]
+#[clap(about = "Check that the server still holds an uploaded batch")]
+pub struct AuditArgs {
+    #[clap(long = "batch", short = 'b')]
+    #[clap(help = "Batch to audit. Defaults to the latest uploaded batch")]
+    pub batch: Option<String>,
+
+    #[clap(long = "fraction", short = 'p', default_value_t = 0.1)]
+    #[clap(help = "Fraction of the batch's chunks to sample, between 0 and 1")]
+    pub fraction: f64,
+}
+
+#[derive(Debug, Clone, Parser, Builder)]
+#[clap(about = "Check local files against the merkle root recorded when they were uploaded")]
+pub struct VerifyArgs {
     #[clap(required = true)]
     #[clap(long = "directory", short = 'd')]
     pub directory: String,
+
+    #[clap(long = "batch", short = 'b')]
+    #[clap(
+        help = "Batch to verify against. Defaults to the latest batch uploaded from the directory"
+    )]
+    pub batch: Option<String>,
+
+    #[clap(long = "prove")]
+    #[clap(help = "Also ask the server to prove each recorded file hash")]
+    pub prove: bool,
+}
+
+#[derive(Debug, Clone, Parser, Builder)]
+#[clap(about = "Restore every file of an uploaded batch")]
+pub struct RestoreArgs {
+    #[clap(long = "batch", short = 'b')]
+    #[clap(help = "Batch to restore. Defaults to the latest uploaded batch")]
+    pub batch: Option<String>,
+
+    #[clap(long = "directory", short = 'd')]
+    #[clap(
+        help = "Directory to restore into. Defaults to the directory the batch was uploaded from"
+    )]
+    pub directory: Option<String>,
+
+    #[clap(long = "no-metadata")]
+    #[clap(help = "Keep default modes and modification times instead of the uploaded ones")]
+    pub no_metadata: bool,
+
+    #[clap(long = "force", short = 'f')]
+    #[clap(help = "Replace files that already exist in the directory")]
+    pub force: bool,
+}
+
+#[derive(Debug, Clone, Parser)]
+#[clap(about = "Manage client profiles")]
+pub struct ProfileArgs {
+    #[clap(subcommand)]
+    pub command: ProfileCommands,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum ProfileCommands {
+    #[clap(name = "list", about = "List profiles, marking the one in use")]
+    List,
+
+    #[clap(name = "use", about = "Use a profile when no --profile is given")]
+    Use(ProfileNameArgs),
+
+    #[clap(
+        name = "remove",
+        about = "Remove a profile and everything recorded in it"
+    )]
+    Remove(ProfileNameArgs),
+}
+
+#[derive(Debug, Clone, Parser, Builder)]
+pub struct ProfileNameArgs {
+    #[clap(required = true)]
+    pub name: String,
 }
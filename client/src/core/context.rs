@@ -1,29 +1,247 @@
+use common::common::MAX_REQUEST_SIZE;
 use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
 use serde_json;
-use std::fs::{File, OpenOptions};
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
-use super::errors::SynxClientError;
+use super::{
+    credentials::{restrict_permissions, write_private_file, CredentialStore, Credentials},
+    errors::SynxClientError,
+    manifest::{Manifest, ManifestEntry},
+};
 
 #[derive(Debug)]
 pub struct Context {
     pub app_config: AppConfig,
     pub path: PathBuf,
+    pub credentials: CredentialStore,
 }
 
 impl Context {
-    pub fn new(app_config: AppConfig, path: PathBuf) -> Self {
-        Self { app_config, path }
+    pub fn new(app_config: AppConfig, path: PathBuf, credentials: CredentialStore) -> Self {
+        Self {
+            app_config,
+            path,
+            credentials,
+        }
+    }
+
+    /// Reads the account's credentials from the credential store into the config.
+    pub fn load_credentials(&mut self) -> Result<(), SynxClientError> {
+        if let Some(credentials) = self.credentials.load()? {
+            self.app_config.set_credentials(credentials);
+        }
+
+        Ok(())
+    }
+
+    /// Path of the manifest of the files uploaded with this profile.
+    pub fn manifest_path(&self) -> PathBuf {
+        Manifest::path(&self.path)
+    }
+
+    /// Saves the credentials held in the config to the credential store.
+    pub fn save_credentials(&self) -> Result<(), SynxClientError> {
+        self.credentials.save(&self.app_config.credentials())
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+/// The config of one profile. The password and JWT are never written to it; they
+/// are only read from configs written before they moved to the credential store.
+#[derive(Serialize, Deserialize, PartialEq)]
 pub struct AppConfig {
     id: String,
+    #[serde(default, skip_serializing)]
     password: String,
+    #[serde(default, skip_serializing)]
     pub jwt: String,
     pub merkle_tree_root: String,
+    #[serde(default)]
+    pub pending_upload: Option<PendingUpload>,
+    #[serde(default)]
+    pub batches: Vec<BatchRecord>,
+    #[serde(default)]
+    pub synced: Vec<SyncState>,
+    #[serde(default)]
+    pub tls: TlsSettings,
+    #[serde(default)]
+    pub connection: ConnectionSettings,
+}
+
+/// Where the server is and how the client talks to it.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(default)]
+pub struct ConnectionSettings {
+    /// Address of the server, e.g. `[::1]:10000`. Without a scheme, `https` is
+    /// used when TLS is configured and `http` otherwise.
+    pub endpoint: String,
+    pub connect_timeout_secs: u64,
+    /// Deadline of each request. Unset by default, as large transfers can take a while.
+    pub request_timeout_secs: Option<u64>,
+    /// Interval of HTTP/2 keepalive pings, which detect dead connections during
+    /// long transfers. Pings are disabled when unset.
+    pub keepalive_interval_secs: Option<u64>,
+    pub keepalive_timeout_secs: u64,
+    /// Largest message, in bytes, the client sends or accepts.
+    pub max_message_size: usize,
+    /// Bytes of the archive sent in each upload message. Must leave room for the
+    /// rest of the message within the server's message size limit.
+    pub upload_chunk_size: usize,
+    /// How many times an interrupted upload or download is attempted in total.
+    pub max_attempts: u64,
+    /// Delay before the first retry, growing linearly with each further attempt.
+    pub retry_backoff_secs: u64,
+}
+
+impl Default for ConnectionSettings {
+    fn default() -> Self {
+        ConnectionSettings {
+            endpoint: "[::1]:10000".to_string(),
+            connect_timeout_secs: 10,
+            request_timeout_secs: None,
+            keepalive_interval_secs: Some(30),
+            keepalive_timeout_secs: 20,
+            max_message_size: 4 * 1024 * 1024,
+            upload_chunk_size: 1024 * 1024,
+            max_attempts: 5,
+            retry_backoff_secs: 1,
+        }
+    }
+}
+
+/// Room left in each upload message for the fields sent along with the chunk.
+const UPLOAD_MESSAGE_OVERHEAD: usize = 16 * 1024;
+
+impl ConnectionSettings {
+    /// Rejects an `upload_chunk_size` that is zero or leaves no room for the rest
+    /// of the upload message within this client's or the server's message limit.
+    pub fn validate(&self) -> Result<(), SynxClientError> {
+        let limit = self.max_message_size.min(MAX_REQUEST_SIZE);
+        let largest = limit.saturating_sub(UPLOAD_MESSAGE_OVERHEAD);
+
+        if self.upload_chunk_size == 0 || self.upload_chunk_size > largest {
+            return Err(SynxClientError::InvalidUploadChunkSize(
+                self.upload_chunk_size,
+                largest,
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// TLS settings for the connection to the server. TLS is used once `ca_cert` is set.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct TlsSettings {
+    /// PEM bundle of the CAs trusted to sign the server's certificate.
+    #[serde(default)]
+    pub ca_cert: Option<PathBuf>,
+    /// PEM certificate and key presented to servers that require mutual TLS.
+    #[serde(default)]
+    pub client_cert: Option<PathBuf>,
+    #[serde(default)]
+    pub client_key: Option<PathBuf>,
+    /// Name to verify the server's certificate against, when it differs from the
+    /// host the client connects to.
+    #[serde(default)]
+    pub domain_name: Option<String>,
+}
+
+/// The merkle root of the files uploaded in batch `id`, oldest batch first.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct BatchRecord {
+    pub id: String,
+    pub merkle_root: String,
+    #[serde(default)]
+    pub files: Vec<FileRecord>,
+    /// Directory the batch was uploaded from, which `restore` writes it back to.
+    #[serde(default)]
+    pub directory: Option<PathBuf>,
+    /// Whether the root was reported by the server instead of computed from the
+    /// uploaded files, as for batches another client uploaded. Such a root only
+    /// shows that files match what the server claims to hold.
+    #[serde(default)]
+    pub server_reported: bool,
+    /// Lineage the batch continues: the line of batches uploaded from one synced
+    /// directory, named after its first batch. Empty in records written before
+    /// lineages were kept.
+    #[serde(default)]
+    pub lineage: String,
+}
+
+/// The root of the chunk tree of an uploaded file, used to check the server's
+/// answers to retrievability challenges.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct FileRecord {
+    pub name: String,
+    pub chunk_root: String,
+    pub chunk_count: u64,
+    /// Hash of the file's content, which is its leaf in the batch's merkle tree.
+    /// Empty in records written before it was kept.
+    #[serde(default)]
+    pub content_hash: String,
+}
+
+/// The files a directory and the server agreed on after its last `sync`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct SyncState {
+    pub directory: PathBuf,
+    pub batch_id: String,
+    /// Content hash of each file of the batch, by name.
+    #[serde(default)]
+    pub files: BTreeMap<String, String>,
+    /// Lineage of the batch, which later syncs of the directory follow.
+    #[serde(default)]
+    pub lineage: String,
+}
+
+/// An upload that has been started on the server but not yet committed.
+/// Kept in the config so an interrupted `upload` can pick up where it stopped.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct PendingUpload {
+    pub upload_id: String,
+    pub directory: PathBuf,
+    /// Size and modification time of the archive when the upload started, which
+    /// tell whether it changed before the upload is resumed.
+    #[serde(default)]
+    pub archive_size: u64,
+    #[serde(default)]
+    pub archive_modified: Option<SystemTime>,
+    pub merkle_root: String,
+    #[serde(default)]
+    pub files: Vec<FileRecord>,
+    /// Manifest entries of the files in the batch, recorded once it is committed.
+    #[serde(default)]
+    pub manifest: Vec<ManifestEntry>,
+    /// Batch an incremental upload builds on, and the files it keeps from it
+    /// instead of sending them again.
+    #[serde(default)]
+    pub base_batch_id: Option<String>,
+    #[serde(default)]
+    pub unchanged_files: Vec<String>,
+    /// Lineage the batch continues. Empty when the upload starts a new one.
+    #[serde(default)]
+    pub lineage: String,
+}
+
+impl fmt::Debug for AppConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AppConfig")
+            .field("id", &self.id)
+            .field("password", &"<redacted>")
+            .field("jwt", &"<redacted>")
+            .field("merkle_tree_root", &self.merkle_tree_root)
+            .field("pending_upload", &self.pending_upload)
+            .field("batches", &self.batches)
+            .field("synced", &self.synced)
+            .field("tls", &self.tls)
+            .field("connection", &self.connection)
+            .finish()
+    }
 }
 
 impl Default for AppConfig {
@@ -33,42 +251,64 @@ impl Default for AppConfig {
             id: String::new(),
             jwt: String::new(),
             password: String::new(),
+            pending_upload: None,
+            batches: Vec::new(),
+            synced: Vec::new(),
+            tls: TlsSettings::default(),
+            connection: ConnectionSettings::default(),
         }
     }
 }
 
 impl AppConfig {
-    pub fn get_config_path() -> Result<PathBuf, SynxClientError> {
+    pub fn get_config_dir() -> Result<PathBuf, SynxClientError> {
         if let Some(proj_dirs) = ProjectDirs::from("com", "Synx", "Client") {
             let config_dir = proj_dirs.config_dir();
 
             std::fs::create_dir_all(config_dir)
                 .map_err(|_| SynxClientError::ConfigDirectoryCreationError)?;
 
-            Ok(config_dir.join("config.json"))
+            Ok(config_dir.to_path_buf())
         } else {
             Err(SynxClientError::ConfigDirectoryCreationError)
         }
     }
 
-    pub fn write(&self, path: &PathBuf) -> Result<(), SynxClientError> {
-        let file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(path)
-            .map_err(|_| SynxClientError::ConfigFileWriteError)?;
+    pub fn get_config_path() -> Result<PathBuf, SynxClientError> {
+        Ok(Self::get_config_dir()?.join("config.json"))
+    }
 
-        serde_json::to_writer(file, self).map_err(|_| SynxClientError::ConfigFileWriteError)?;
+    pub fn write(&self, path: &Path) -> Result<(), SynxClientError> {
+        let contents =
+            serde_json::to_vec(self).map_err(|_| SynxClientError::ConfigFileWriteError)?;
 
-        Ok(())
+        write_private_file(path, &contents)
     }
 
     pub fn read(path: &PathBuf) -> std::io::Result<AppConfig> {
         let file = File::open(path)?;
         let config = serde_json::from_reader(file)?;
+        let _ = restrict_permissions(path);
         Ok(config)
     }
 
+    pub fn credentials(&self) -> Credentials {
+        Credentials {
+            password: self.password.clone(),
+            jwt: self.jwt.clone(),
+        }
+    }
+
+    pub fn set_credentials(&mut self, credentials: Credentials) {
+        self.password = credentials.password;
+        self.jwt = credentials.jwt;
+    }
+
+    /// Whether the config was read from a file that still holds credentials.
+    pub fn has_credentials(&self) -> bool {
+        !self.password.is_empty() || !self.jwt.is_empty()
+    }
+
     pub fn set_merkle_root(&mut self, root: String) {
         self.merkle_tree_root = root;
     }
@@ -77,6 +317,10 @@ impl AppConfig {
         self.jwt = jwt;
     }
 
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
     pub fn set_id(&mut self, id: String) {
         self.id = id;
     }
@@ -84,6 +328,58 @@ impl AppConfig {
     pub fn set_password(&mut self, password: String) {
         self.password = password;
     }
+
+    pub fn add_batch(&mut self, batch: BatchRecord) {
+        self.set_merkle_root(batch.merkle_root.clone());
+        self.batches.push(batch);
+    }
+
+    /// Returns the record of batch `id`.
+    pub fn batch(&self, id: &str) -> Option<&BatchRecord> {
+        self.batches.iter().find(|batch| batch.id == id)
+    }
+
+    /// Returns the merkle root recorded for batch `id`.
+    pub fn batch_root(&self, id: &str) -> Option<&str> {
+        self.batch(id).map(|batch| batch.merkle_root.as_str())
+    }
+
+    pub fn set_pending_upload(&mut self, pending_upload: Option<PendingUpload>) {
+        self.pending_upload = pending_upload;
+    }
+
+    /// Returns the state `directory` was left in by its last sync.
+    pub fn sync_state(&self, directory: &Path) -> Option<&SyncState> {
+        self.synced
+            .iter()
+            .find(|state| state.directory == directory)
+    }
+
+    /// The lineage of batches `directory` continues: the one its last sync followed,
+    /// or else the one of the latest batch uploaded from it. A record written
+    /// before lineages were kept stands for a lineage named after its batch.
+    pub fn directory_lineage(&self, directory: &Path) -> Option<String> {
+        let (lineage, batch_id) = match self.sync_state(directory) {
+            Some(state) => (&state.lineage, &state.batch_id),
+            None => self
+                .batches
+                .iter()
+                .rev()
+                .find(|batch| batch.directory.as_deref() == Some(directory))
+                .map(|batch| (&batch.lineage, &batch.id))?,
+        };
+
+        match lineage.is_empty() {
+            true => Some(batch_id.to_string()),
+            false => Some(lineage.to_string()),
+        }
+    }
+
+    pub fn set_sync_state(&mut self, state: SyncState) {
+        self.synced
+            .retain(|synced| synced.directory != state.directory);
+        self.synced.push(state);
+    }
 }
 
 #[cfg(test)]
@@ -110,6 +406,26 @@ mod tests {
         app_config.write(&path);
         config = AppConfig::read(&path).unwrap();
 
+        // Credentials are never written to the config file
+        assert!(!config.has_credentials());
+        config.set_credentials(app_config.credentials());
         assert!(config == app_config);
     }
+
+    #[test]
+    fn upload_chunk_size_test() {
+        let mut settings = ConnectionSettings::default();
+        assert!(settings.validate().is_ok());
+
+        settings.upload_chunk_size = 0;
+        assert!(settings.validate().is_err());
+
+        settings.upload_chunk_size = settings.max_message_size;
+        assert!(settings.validate().is_err());
+
+        // The server's limit applies even when the client allows larger messages.
+        settings.max_message_size = 2 * MAX_REQUEST_SIZE;
+        settings.upload_chunk_size = MAX_REQUEST_SIZE;
+        assert!(settings.validate().is_err());
+    }
 }
@@ -0,0 +1,290 @@
+use argon2::Argon2;
+use chacha20poly1305::{aead::Aead, ChaCha20Poly1305, Key, KeyInit, Nonce};
+use keyring::Entry;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use super::{errors::SynxClientError, output::progress};
+
+const KEYRING_SERVICE: &str = "syncx";
+const PASSPHRASE_ENV: &str = "SYNCX_PASSPHRASE";
+const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 12;
+
+/// The secrets of an account, kept out of `config.json`.
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq)]
+pub struct Credentials {
+    pub password: String,
+    pub jwt: String,
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("password", &"<redacted>")
+            .field("jwt", &"<redacted>")
+            .finish()
+    }
+}
+
+/// `Credentials` sealed with a key derived from the user's passphrase.
+#[derive(Serialize, Deserialize)]
+struct EncryptedCredentials {
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Keeps the credentials of one profile in the OS keyring (the Secret Service on
+/// Linux). Where no keyring is available, they go to a file next to the
+/// profile's config instead, encrypted with a passphrase read from
+/// `SYNCX_PASSPHRASE` or prompted for.
+#[derive(Debug)]
+pub struct CredentialStore {
+    profile: String,
+    fallback_path: PathBuf,
+}
+
+impl CredentialStore {
+    pub fn new(profile: &str, config_path: &Path) -> Self {
+        Self {
+            profile: profile.to_string(),
+            fallback_path: config_path.with_extension("credentials"),
+        }
+    }
+
+    /// Returns the stored credentials, or `None` if none were saved.
+    pub fn load(&self) -> Result<Option<Credentials>, SynxClientError> {
+        if let Ok(secret) = self.entry().and_then(|entry| entry.get_password()) {
+            let credentials = serde_json::from_str(&secret)
+                .map_err(|e| SynxClientError::CredentialStoreError(e.to_string()))?;
+            return Ok(Some(credentials));
+        }
+
+        if !self.fallback_path.exists() {
+            return Ok(None);
+        }
+
+        let encrypted = fs::read(&self.fallback_path)
+            .map_err(|e| SynxClientError::CredentialStoreError(e.to_string()))?;
+        let encrypted = serde_json::from_slice(&encrypted)
+            .map_err(|e| SynxClientError::CredentialStoreError(e.to_string()))?;
+
+        let passphrase = read_passphrase(false)?;
+        decrypt(&encrypted, &passphrase).map(Some)
+    }
+
+    pub fn save(&self, credentials: &Credentials) -> Result<(), SynxClientError> {
+        let secret = serde_json::to_string(credentials)
+            .map_err(|e| SynxClientError::CredentialStoreError(e.to_string()))?;
+
+        match self.entry().and_then(|entry| entry.set_password(&secret)) {
+            Ok(()) => {
+                // Drop any copy left from a time the keyring was unavailable.
+                let _ = fs::remove_file(&self.fallback_path);
+                Ok(())
+            }
+            Err(e) => {
+                progress!(
+                    "OS keyring unavailable ({}). Storing credentials in {:?}, encrypted with a passphrase",
+                    e, self.fallback_path
+                );
+                let passphrase = read_passphrase(true)?;
+                let encrypted = encrypt(credentials, &passphrase)?;
+                let encrypted = serde_json::to_vec(&encrypted)
+                    .map_err(|e| SynxClientError::CredentialStoreError(e.to_string()))?;
+                write_private_file(&self.fallback_path, &encrypted)
+            }
+        }
+    }
+
+    /// Removes the credentials from both the keyring and the fallback file.
+    pub fn delete(&self) -> Result<(), SynxClientError> {
+        match self.entry().and_then(|entry| entry.delete_credential()) {
+            Ok(()) | Err(keyring::Error::NoEntry) => {}
+            Err(e) if !self.fallback_path.exists() => {
+                return Err(SynxClientError::CredentialStoreError(e.to_string()))
+            }
+            Err(_) => {}
+        }
+
+        match fs::remove_file(&self.fallback_path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(SynxClientError::CredentialStoreError(e.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn entry(&self) -> keyring::Result<Entry> {
+        Entry::new(KEYRING_SERVICE, &self.profile)
+    }
+}
+
+/// Writes `contents` to `path`, readable and writable by the owner only.
+pub fn write_private_file(path: &Path, contents: &[u8]) -> Result<(), SynxClientError> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options
+        .open(path)
+        .map_err(|_| SynxClientError::ConfigFileWriteError)?;
+    restrict_permissions(path)?;
+    file.write_all(contents)
+        .map_err(|_| SynxClientError::ConfigFileWriteError)
+}
+
+/// Creates directory `path` and its parents, accessible by the owner only.
+pub fn create_private_dir(path: &Path) -> std::io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(path)?;
+
+    // An existing directory keeps its mode, so it is restricted here too.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o700))?;
+    }
+
+    Ok(())
+}
+
+/// Makes an existing file private to its owner, as `mode` only applies to new files.
+pub fn restrict_permissions(path: &Path) -> Result<(), SynxClientError> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+            .map_err(|_| SynxClientError::ConfigFileWriteError)?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+
+    Ok(())
+}
+
+fn read_passphrase(confirm: bool) -> Result<String, SynxClientError> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+
+    let read = |prompt: &str| {
+        rpassword::prompt_password(prompt)
+            .map_err(|e| SynxClientError::CredentialStoreError(e.to_string()))
+    };
+
+    let passphrase = read("Passphrase for syncx credentials: ")?;
+    if confirm && read("Repeat passphrase: ")? != passphrase {
+        return Err(SynxClientError::PassphraseMismatch);
+    }
+
+    Ok(passphrase)
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Key, SynxClientError> {
+    let mut key = Key::default();
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| SynxClientError::CredentialStoreError(e.to_string()))?;
+
+    Ok(key)
+}
+
+fn encrypt(
+    credentials: &Credentials,
+    passphrase: &str,
+) -> Result<EncryptedCredentials, SynxClientError> {
+    let mut salt = [0u8; SALT_SIZE];
+    let mut nonce = [0u8; NONCE_SIZE];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let plaintext = serde_json::to_vec(credentials)
+        .map_err(|e| SynxClientError::CredentialStoreError(e.to_string()))?;
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &salt)?);
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), plaintext.as_slice())
+        .map_err(|e| SynxClientError::CredentialStoreError(e.to_string()))?;
+
+    Ok(EncryptedCredentials {
+        salt: hex::encode(salt),
+        nonce: hex::encode(nonce),
+        ciphertext: hex::encode(ciphertext),
+    })
+}
+
+fn decrypt(
+    encrypted: &EncryptedCredentials,
+    passphrase: &str,
+) -> Result<Credentials, SynxClientError> {
+    let decode = |value: &str| {
+        hex::decode(value).map_err(|e| SynxClientError::CredentialStoreError(e.to_string()))
+    };
+
+    let salt = decode(&encrypted.salt)?;
+    let nonce = decode(&encrypted.nonce)?;
+    if nonce.len() != NONCE_SIZE {
+        return Err(SynxClientError::CredentialStoreError(
+            "invalid nonce".to_string(),
+        ));
+    }
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &salt)?);
+    let plaintext = cipher
+        .decrypt(
+            Nonce::from_slice(&nonce),
+            decode(&encrypted.ciphertext)?.as_slice(),
+        )
+        .map_err(|_| SynxClientError::WrongPassphrase)?;
+
+    serde_json::from_slice(&plaintext)
+        .map_err(|e| SynxClientError::CredentialStoreError(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypted_credentials_test() {
+        let credentials = Credentials {
+            password: "password".to_string(),
+            jwt: "jwt".to_string(),
+        };
+
+        let encrypted = encrypt(&credentials, "passphrase").unwrap();
+        assert!(!encrypted.ciphertext.contains(&hex::encode("password")));
+        assert_eq!(decrypt(&encrypted, "passphrase").unwrap(), credentials);
+        assert!(matches!(
+            decrypt(&encrypted, "wrong passphrase"),
+            Err(SynxClientError::WrongPassphrase)
+        ));
+    }
+
+    #[test]
+    fn redacted_credentials_test() {
+        let credentials = Credentials {
+            password: "secret-password".to_string(),
+            jwt: "secret-jwt".to_string(),
+        };
+
+        let debug = format!("{:?}", credentials);
+        assert!(!debug.contains("secret-password"));
+        assert!(!debug.contains("secret-jwt"));
+    }
+}
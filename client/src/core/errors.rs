@@ -5,4 +5,34 @@ pub enum SynxClientError {
 
     #[error("Failed to write to config file")]
     ConfigFileWriteError,
+
+    #[error("Failed to read TLS certificate {0}")]
+    TlsCertificateReadError(String),
+
+    #[error("Both client_cert and client_key must be set for mutual TLS")]
+    IncompleteClientIdentity,
+
+    #[error("Invalid profile name {0}. Use letters, digits, '-' and '_'")]
+    InvalidProfileName(String),
+
+    #[error("Profile {0} does not exist")]
+    ProfileNotFound(String),
+
+    #[error("Profile {0} is in use. Switch to another profile before removing it")]
+    ActiveProfileRemoval(String),
+
+    #[error("Failed to access stored credentials: {0}")]
+    CredentialStoreError(String),
+
+    #[error("Wrong passphrase for the stored credentials")]
+    WrongPassphrase,
+
+    #[error("Passphrases do not match")]
+    PassphraseMismatch,
+
+    #[error("Failed to watch directory {0}")]
+    WatchError(String),
+
+    #[error("Invalid upload_chunk_size {0}. Use between 1 and {1} bytes")]
+    InvalidUploadChunkSize(usize, usize),
 }
@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use common::common::{file_to_bytes, read_file_metadata};
+use merkle_tree::utils::hash_bytes;
+
+use super::{credentials::write_private_file, errors::SynxClientError};
+
+/// A local file as it was when it was uploaded, and the batch holding that version.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ManifestEntry {
+    /// Absolute path of the file on this machine.
+    pub path: PathBuf,
+    /// Name of the file in its batch, relative to the uploaded directory.
+    pub name: String,
+    pub size: u64,
+    pub mtime_seconds: i64,
+    pub mtime_nanos: u32,
+    /// Hash of the file's content, which is also its leaf in the batch's merkle tree.
+    pub content_hash: String,
+    pub batch_id: String,
+}
+
+impl ManifestEntry {
+    /// Describes the file at `path`, uploaded from `base_dir` in batch `batch_id`.
+    /// The content hash of `previous` is reused if the file's size and modification
+    /// time haven't changed since, so unchanged files aren't read again.
+    pub fn new(
+        base_dir: &Path,
+        path: &Path,
+        batch_id: &str,
+        previous: Option<&ManifestEntry>,
+    ) -> std::io::Result<Self> {
+        let metadata = read_file_metadata(base_dir, path)?;
+        let size = fs::metadata(path)?.len();
+
+        let content_hash = match previous {
+            Some(previous)
+                if previous.size == size
+                    && previous.mtime_seconds == metadata.mtime_seconds
+                    && previous.mtime_nanos == metadata.mtime_nanos =>
+            {
+                previous.content_hash.to_string()
+            }
+            _ => hash_bytes(&file_to_bytes(path)?),
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            name: metadata.path,
+            size,
+            mtime_seconds: metadata.mtime_seconds,
+            mtime_nanos: metadata.mtime_nanos,
+            content_hash,
+            batch_id: batch_id.to_string(),
+        })
+    }
+}
+
+/// Every local file uploaded with a profile, by absolute path. Only the latest
+/// upload of a file is kept. Stored next to the profile's config.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Manifest {
+    #[serde(default)]
+    pub files: BTreeMap<String, ManifestEntry>,
+}
+
+impl Manifest {
+    /// Path of the manifest of the profile whose config is at `config_path`.
+    pub fn path(config_path: &Path) -> PathBuf {
+        config_path.with_extension("manifest.json")
+    }
+
+    /// Reads the manifest at `path`, or returns an empty one if there is none yet.
+    pub fn read(path: &Path) -> Manifest {
+        fs::read(path)
+            .ok()
+            .and_then(|contents| serde_json::from_slice(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), SynxClientError> {
+        let contents =
+            serde_json::to_vec(self).map_err(|_| SynxClientError::ConfigFileWriteError)?;
+
+        write_private_file(path, &contents)
+    }
+
+    pub fn record(&mut self, entries: impl IntoIterator<Item = ManifestEntry>) {
+        for entry in entries {
+            self.files
+                .insert(entry.path.to_string_lossy().to_string(), entry);
+        }
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&ManifestEntry> {
+        self.files.get(path.to_string_lossy().as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("notes.txt");
+        fs::write(&file_path, b"notes").unwrap();
+
+        let entry = ManifestEntry::new(dir.path(), &file_path, "batch", None).unwrap();
+        assert_eq!(entry.name, "notes.txt");
+        assert_eq!(entry.size, 5);
+        assert_eq!(entry.content_hash, hash_bytes(b"notes"));
+
+        // An unchanged file keeps the recorded hash instead of being read again.
+        let stale = ManifestEntry {
+            content_hash: "recorded".to_string(),
+            ..entry.clone()
+        };
+        let reused = ManifestEntry::new(dir.path(), &file_path, "next", Some(&stale)).unwrap();
+        assert_eq!(reused.content_hash, "recorded");
+        assert_eq!(reused.batch_id, "next");
+
+        let mut manifest = Manifest::default();
+        manifest.record(vec![entry.clone()]);
+
+        let manifest_path = dir.path().join("config.manifest.json");
+        manifest.write(&manifest_path).unwrap();
+        let manifest = Manifest::read(&manifest_path);
+        assert_eq!(manifest.get(&file_path), Some(&entry));
+    }
+}
@@ -1,3 +1,12 @@
 pub mod context;
+pub mod credentials;
 pub mod errors;
+pub mod manifest;
+pub mod output;
+pub mod profile;
 pub mod service;
+pub mod session;
+pub mod sync;
+pub mod transport;
+pub mod verify;
+pub mod watch;
@@ -0,0 +1,296 @@
+use serde::Serialize;
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use tonic::Code;
+
+use super::errors::SynxClientError;
+
+/// What the client reports while it works.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Progress {
+    /// A line describing the current step.
+    Message(String),
+    /// `done` of the `total` bytes of `stage` have been processed. The total
+    /// grows when a download learns the size of its next file.
+    Transfer { stage: Stage, done: u64, total: u64 },
+    /// `stage` ended, whether or not all of its bytes were processed.
+    Finished(Stage),
+}
+
+/// A step of an upload or download that works through a known number of bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Stage {
+    /// Hashing files to build the merkle tree and chunk records of an upload.
+    Hashing,
+    /// Packing the files of an upload into an archive.
+    Zipping,
+    Uploading,
+    Downloading,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Stage::Hashing => "Hashing",
+            Stage::Zipping => "Zipping",
+            Stage::Uploading => "Uploading",
+            Stage::Downloading => "Downloading",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Progress::Message(message) => write!(f, "{}", message),
+            Progress::Transfer { stage, done, total } => {
+                write!(f, "{}: {} of {} bytes", stage, done, total)
+            }
+            Progress::Finished(stage) => write!(f, "{}: done", stage),
+        }
+    }
+}
+
+pub type ProgressHandler = Arc<dyn Fn(&Progress) + Send + Sync>;
+
+tokio::task_local! {
+    static PROGRESS: ProgressHandler;
+}
+
+/// Runs `future` with its progress sent to `handler`.
+pub async fn with_progress<F: Future>(handler: ProgressHandler, future: F) -> F::Output {
+    PROGRESS.scope(handler, future).await
+}
+
+/// Runs `f` with its progress sent to `handler`.
+pub fn with_progress_sync<R>(handler: ProgressHandler, f: impl FnOnce() -> R) -> R {
+    PROGRESS.sync_scope(handler, f)
+}
+
+/// Sends `progress` to the handler of the running operation. Progress made
+/// outside `with_progress` is dropped.
+pub fn report_progress(progress: Progress) {
+    let _ = PROGRESS.try_with(|handler| handler(&progress));
+}
+
+pub fn write_progress(args: fmt::Arguments) {
+    report_progress(Progress::Message(args.to_string()));
+}
+
+/// Reports the bytes processed by a stage, and that the stage finished once it
+/// is dropped. It keeps the handler of the operation that started it, so it
+/// can be moved into a stream polled by another task.
+pub struct Transfer {
+    stage: Stage,
+    done: u64,
+    total: u64,
+    handler: Option<ProgressHandler>,
+}
+
+impl Transfer {
+    pub fn start(stage: Stage, total: u64) -> Self {
+        let transfer = Self {
+            stage,
+            done: 0,
+            total,
+            handler: PROGRESS.try_with(|handler| handler.clone()).ok(),
+        };
+        transfer.report();
+        transfer
+    }
+
+    /// Adds `bytes` to the bytes processed so far.
+    pub fn advance(&mut self, bytes: u64) {
+        self.set_position(self.done + bytes);
+    }
+
+    pub fn set_position(&mut self, done: u64) {
+        self.done = done;
+        self.report();
+    }
+
+    pub fn set_total(&mut self, total: u64) {
+        self.total = total;
+        self.report();
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    fn report(&self) {
+        if let Some(handler) = &self.handler {
+            handler(&Progress::Transfer {
+                stage: self.stage,
+                done: self.done,
+                total: self.total,
+            });
+        }
+    }
+}
+
+impl Drop for Transfer {
+    fn drop(&mut self) {
+        if let Some(handler) = &self.handler {
+            handler(&Progress::Finished(self.stage));
+        }
+    }
+}
+
+macro_rules! progress {
+    ($($arg:tt)*) => {
+        $crate::core::output::write_progress(format_args!($($arg)*))
+    };
+}
+pub(crate) use progress;
+
+/// Why a command failed, which decides the CLI's exit code. Usage errors exit
+/// with 2, as reported by clap.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// The local config, credentials or files couldn't be used.
+    Local,
+    /// The server couldn't be reached, or the connection dropped.
+    Network,
+    /// The server refused the request or failed to handle it.
+    Server,
+    /// Data didn't match the merkle root it was checked against.
+    Verification,
+}
+
+impl ErrorKind {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Verification => 3,
+            ErrorKind::Network => 4,
+            ErrorKind::Server => 5,
+            ErrorKind::Local => 6,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct CommandError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl CommandError {
+    pub fn new(kind: ErrorKind, message: impl fmt::Display) -> Self {
+        Self {
+            kind,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn local(message: impl fmt::Display) -> Self {
+        Self::new(ErrorKind::Local, message)
+    }
+
+    pub fn verification(message: impl fmt::Display) -> Self {
+        Self::new(ErrorKind::Verification, message)
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+impl From<tonic::Status> for CommandError {
+    fn from(status: tonic::Status) -> Self {
+        let kind = match status.code() {
+            Code::Unavailable | Code::DeadlineExceeded | Code::Cancelled | Code::Aborted => {
+                ErrorKind::Network
+            }
+            _ => ErrorKind::Server,
+        };
+
+        Self::new(kind, status.message())
+    }
+}
+
+impl From<SynxClientError> for CommandError {
+    fn from(e: SynxClientError) -> Self {
+        Self::local(e)
+    }
+}
+
+impl From<std::io::Error> for CommandError {
+    fn from(e: std::io::Error) -> Self {
+        Self::local(e)
+    }
+}
+
+impl From<Box<dyn std::error::Error>> for CommandError {
+    fn from(e: Box<dyn std::error::Error>) -> Self {
+        Self::local(e)
+    }
+}
+
+/// The result of a command. A report of a failed check, such as a download that
+/// didn't verify, is still printed in full but makes the command fail.
+pub trait Report: Serialize {
+    fn failure(&self) -> Option<CommandError> {
+        None
+    }
+}
+
+impl Report for () {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_error_test() {
+        let error = CommandError::from(tonic::Status::unavailable("connection refused"));
+        assert_eq!(error.kind, ErrorKind::Network);
+        assert_eq!(error.kind.exit_code(), 4);
+
+        let error = CommandError::from(tonic::Status::not_found("Batch not found"));
+        assert_eq!(error.kind, ErrorKind::Server);
+
+        assert_eq!(
+            serde_json::to_value(CommandError::verification("Root mismatch")).unwrap(),
+            serde_json::json!({ "kind": "verification", "message": "Root mismatch" })
+        );
+    }
+
+    #[tokio::test]
+    async fn progress_handler_test() {
+        let received = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = received.clone();
+        let handler: ProgressHandler =
+            Arc::new(move |progress| sink.lock().unwrap().push(progress.clone()));
+
+        progress!("dropped");
+        with_progress(handler, async {
+            progress!("Uploading {} files", 2);
+            let mut transfer = Transfer::start(Stage::Uploading, 10);
+            transfer.advance(4);
+        })
+        .await;
+
+        let transfer = |done| Progress::Transfer {
+            stage: Stage::Uploading,
+            done,
+            total: 10,
+        };
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![
+                Progress::Message("Uploading 2 files".to_string()),
+                transfer(0),
+                transfer(4),
+                Progress::Finished(Stage::Uploading),
+            ]
+        );
+    }
+}
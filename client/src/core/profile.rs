@@ -0,0 +1,204 @@
+use std::fs;
+use std::path::PathBuf;
+
+use super::{
+    context::{AppConfig, Context},
+    credentials::CredentialStore,
+    errors::SynxClientError,
+    manifest::Manifest,
+    output::progress,
+};
+
+pub const DEFAULT_PROFILE: &str = "default";
+const PROFILES_DIR: &str = "profiles";
+const CURRENT_PROFILE_FILE: &str = "current_profile";
+
+/// Named client configs, each with its own server endpoint, credentials and
+/// batches. The `default` profile lives in the original `config.json`, so
+/// configs written before profiles existed keep working; every other profile
+/// is stored as `profiles/<name>.json` next to it.
+pub struct Profiles {
+    dir: PathBuf,
+}
+
+impl Profiles {
+    pub fn open() -> Result<Self, SynxClientError> {
+        let dir = AppConfig::get_config_dir()?;
+        fs::create_dir_all(dir.join(PROFILES_DIR))
+            .map_err(|_| SynxClientError::ConfigDirectoryCreationError)?;
+
+        Ok(Self { dir })
+    }
+
+    /// Path of the config file of profile `name`.
+    pub fn config_path(&self, name: &str) -> Result<PathBuf, SynxClientError> {
+        if name == DEFAULT_PROFILE {
+            return AppConfig::get_config_path();
+        }
+
+        if !is_valid_name(name) {
+            return Err(SynxClientError::InvalidProfileName(name.to_string()));
+        }
+
+        Ok(self.dir.join(PROFILES_DIR).join(format!("{}.json", name)))
+    }
+
+    /// Loads the config of profile `name`, creating it if it does not exist yet.
+    /// Credentials are only read from the credential store on request, with
+    /// `Context::load_credentials`.
+    pub fn load(&self, name: &str) -> Result<Context, SynxClientError> {
+        let path = self.config_path(name)?;
+        let credentials = CredentialStore::new(name, &path);
+        let app_config = match AppConfig::read(&path) {
+            Ok(app_config) => app_config,
+            Err(_) => {
+                progress!("Client config not found. Creating new config...");
+                progress!("Config saved to {:?}", path);
+
+                let app_config = AppConfig::default();
+                let _ = app_config.write(&path);
+                app_config
+            }
+        };
+
+        app_config.connection.validate()?;
+
+        let context = Context::new(app_config, path, credentials);
+        if context.app_config.has_credentials() {
+            // Written before credentials moved out of the config file.
+            progress!(
+                "Moving credentials of profile {} out of {:?}",
+                name,
+                context.path
+            );
+            context.save_credentials()?;
+            context.app_config.write(&context.path)?;
+        }
+
+        Ok(context)
+    }
+
+    /// Reads the stored config of profile `name`. Unlike `load`, nothing is created,
+    /// migrated or read from the credential store.
+    pub fn read_config(&self, name: &str) -> Option<AppConfig> {
+        let contents = fs::read(self.config_path(name).ok()?).ok()?;
+        serde_json::from_slice(&contents).ok()
+    }
+
+    pub fn exists(&self, name: &str) -> bool {
+        self.config_path(name)
+            .map(|path| path.exists())
+            .unwrap_or(false)
+    }
+
+    /// The profile used when no `--profile` is given.
+    pub fn current(&self) -> String {
+        fs::read_to_string(self.dir.join(CURRENT_PROFILE_FILE))
+            .map(|name| name.trim().to_string())
+            .ok()
+            .filter(|name| self.exists(name))
+            .unwrap_or_else(|| DEFAULT_PROFILE.to_string())
+    }
+
+    pub fn set_current(&self, name: &str) -> Result<(), SynxClientError> {
+        if !self.exists(name) {
+            return Err(SynxClientError::ProfileNotFound(name.to_string()));
+        }
+
+        fs::write(self.dir.join(CURRENT_PROFILE_FILE), name)
+            .map_err(|_| SynxClientError::ConfigFileWriteError)
+    }
+
+    /// Names of all profiles, in alphabetical order.
+    pub fn list(&self) -> Vec<String> {
+        let mut names = fs::read_dir(self.dir.join(PROFILES_DIR))
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .filter_map(|entry| {
+                        let path = entry.path();
+                        match path.extension() {
+                            Some(extension) if extension == "json" => path
+                                .file_stem()
+                                .map(|name| name.to_string_lossy().to_string()),
+                            _ => None,
+                        }
+                    })
+                    .filter(|name| is_valid_name(name))
+                    .collect::<Vec<String>>()
+            })
+            .unwrap_or_default();
+
+        if self.exists(DEFAULT_PROFILE) {
+            names.push(DEFAULT_PROFILE.to_string());
+        }
+
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Deletes profile `name`. The profile in use can't be removed.
+    pub fn remove(&self, name: &str) -> Result<(), SynxClientError> {
+        if !self.exists(name) {
+            return Err(SynxClientError::ProfileNotFound(name.to_string()));
+        }
+
+        if self.current() == name {
+            return Err(SynxClientError::ActiveProfileRemoval(name.to_string()));
+        }
+
+        let path = self.config_path(name)?;
+        CredentialStore::new(name, &path).delete()?;
+        let _ = fs::remove_file(Manifest::path(&path));
+        fs::remove_file(path).map_err(|_| SynxClientError::ConfigFileWriteError)
+    }
+}
+
+/// Profile names become file names, so only letters, digits, `-` and `_` are allowed.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profile_name_test() {
+        assert!(is_valid_name("work"));
+        assert!(is_valid_name("shared_backup-2"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name("../config"));
+        assert!(!is_valid_name("a/b"));
+    }
+
+    #[test]
+    fn read_config_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let profiles = Profiles {
+            dir: dir.path().to_path_buf(),
+        };
+        fs::create_dir_all(dir.path().join(PROFILES_DIR)).unwrap();
+
+        let mut app_config = AppConfig::default();
+        app_config.connection.endpoint = "backup.example.com:443".to_string();
+        app_config
+            .write(&profiles.config_path("work").unwrap())
+            .unwrap();
+
+        assert_eq!(
+            profiles
+                .read_config("work")
+                .map(|config| config.connection.endpoint),
+            Some("backup.example.com:443".to_string())
+        );
+
+        // Reading a profile that does not exist leaves it that way.
+        assert!(profiles.read_config("missing").is_none());
+        assert!(!profiles.exists("missing"));
+    }
+}
@@ -1,164 +1,1988 @@
 pub mod client {
     extern crate common;
 
-    use crate::core::context::Context;
+    use crate::core::{
+        context::{BatchRecord, Context, FileRecord, PendingUpload},
+        credentials::create_private_dir,
+        manifest::{Manifest, ManifestEntry},
+        output::{progress, CommandError, ErrorKind, Report, Stage, Transfer},
+    };
     use common::{
         common::*,
         syncx::{
-            syncx_client::SyncxClient, CreateClientRequest, CreateClientResponse,
-            FileDownloadRequest, FileUploadRequest, MerkleProof, MerkleProofNode,
+            files_download_response::Frame, proof_request::Leaf, restore_response,
+            syncx_client::SyncxClient, BeginUploadRequest, ChallengeRequest, ChunkChallenge,
+            ChunkResponse, CommitUploadRequest, CreateClientRequest, CreateClientResponse,
+            FileDownloadRequest, FileMetadata, FileUploadRequest, FilesDownloadRequest, JobState,
+            JobStatus, JobStatusRequest, ListFilesRequest, LoginRequest, MerkleMultiProof,
+            MerkleProof, MerkleProofNode, ProofRequest, QueryUploadRequest, RestoreRequest,
+            RootRequest,
         },
     };
-    use merkle_tree::{merkle_tree::MerkleTree, utils::hash_bytes};
+    use directories::ProjectDirs;
+    use merkle_tree::{
+        merkle_tree::{MerkleTree, MultiProof},
+        utils::hash_bytes,
+    };
+    use rand::{seq::index::sample, Rng};
+    use serde::Serialize;
+    use sha2::{Digest, Sha256};
+    use std::collections::{HashMap, HashSet};
     use std::fs;
     use std::io::Write;
     use std::path::{Path, PathBuf};
+    use std::time::{Duration, SystemTime};
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+    use tokio::sync::mpsc;
+    use tokio_stream::{wrappers::ReceiverStream, StreamExt};
+    use tonic::Code;
+
+    const UPLOAD_DIR: &str = "syncx-uploads";
+    /// Upload messages read ahead of the connection, which bounds the memory an
+    /// upload uses to this many chunks.
+    const UPLOAD_QUEUE_LENGTH: usize = 4;
+
+    #[derive(Serialize, Debug)]
+    pub struct AccountReport {
+        pub id: String,
+    }
 
-    const DEFAULT_ZIP_FILE: &str = "uploads.zip";
+    impl Report for AccountReport {}
 
     pub async fn register_client(
         syncx_client: &mut SyncxClient<tonic::transport::Channel>,
         password: String,
         context: &mut Context,
-    ) {
-        println!("Registering new client on syncx server...");
-        let response = syncx_client
+    ) -> Result<AccountReport, CommandError> {
+        if !context.app_config.id().is_empty() {
+            return Err(CommandError::local(format!(
+                "This profile already holds account {}. Use --profile <name> to create another account",
+                context.app_config.id()
+            )));
+        }
+
+        progress!("Registering new client on syncx server...");
+        let CreateClientResponse { id, jwt_token } = syncx_client
             .register_client(CreateClientRequest {
                 password: password.to_string(),
             })
-            .await;
+            .await?
+            .into_inner();
 
-        match response {
-            Ok(res) => {
-                let CreateClientResponse { id, jwt_token } = res.into_inner();
+        context.app_config.set_id(id.to_string());
+        context.app_config.set_password(password);
+        context.app_config.set_jwt(jwt_token);
+        context.save_credentials()?;
+        context.app_config.write(&context.path)?;
 
-                context.app_config.set_id(id);
-                context.app_config.set_password(password);
-                context.app_config.set_jwt(jwt_token);
-                context
-                    .app_config
-                    .write(&context.path)
-                    .unwrap_or_else(|e| panic!("Failed to update app state: {}", e));
+        progress!("{:?}", context.app_config);
+        Ok(AccountReport { id })
+    }
 
-                println!("{:?}", context.app_config);
+    /// Logs into account `id`, or the account the profile already holds, and
+    /// stores the new auth token. Used to refresh an expired token, or to use an
+    /// existing account from another machine.
+    pub async fn login(
+        syncx_client: &mut SyncxClient<tonic::transport::Channel>,
+        id: Option<&str>,
+        password: String,
+        context: &mut Context,
+    ) -> Result<AccountReport, CommandError> {
+        let current_id = context.app_config.id().to_string();
+        let id = match id {
+            Some(id) if !current_id.is_empty() && id != current_id => {
+                return Err(CommandError::local(format!(
+                    "This profile holds account {}. Use --profile <name> to log into another account",
+                    current_id
+                )))
             }
-            Err(e) => {
-                panic!("Failed to create user account {:?}", e);
+            Some(id) => id.to_string(),
+            None if current_id.is_empty() => {
+                return Err(CommandError::local(
+                    "This profile holds no account. Pass the id of the account to log into",
+                ))
             }
+            None => current_id,
+        };
+
+        progress!("Logging into account {}...", id);
+        let jwt_token = syncx_client
+            .login(LoginRequest {
+                id: id.to_string(),
+                password: password.to_string(),
+            })
+            .await?
+            .into_inner()
+            .jwt_token;
+
+        context.app_config.set_id(id.to_string());
+        context.app_config.set_password(password);
+        context.app_config.set_jwt(jwt_token);
+        context.save_credentials()?;
+        context.app_config.write(&context.path)?;
+
+        progress!("Logged into account {}", id);
+        Ok(AccountReport { id })
+    }
+
+    #[derive(Serialize, Debug)]
+    pub struct ListReport {
+        pub batch_id: String,
+        pub files: Vec<ListedFile>,
+    }
+
+    #[derive(Serialize, Debug)]
+    pub struct ListedFile {
+        pub name: String,
+        pub content_hash: String,
+    }
+
+    impl Report for ListReport {}
+
+    /// Lists the files of `batch_id`, or of the latest batch when `None`, as held
+    /// by the server.
+    pub async fn list_files(
+        syncx_client: &mut SyncxClient<tonic::transport::Channel>,
+        batch_id: Option<&str>,
+        context: &Context,
+    ) -> Result<ListReport, CommandError> {
+        let response = syncx_client
+            .list_files(ListFilesRequest {
+                jwt: context.app_config.jwt.to_string(),
+                batch_id: batch_id.unwrap_or_default().to_string(),
+                lineage: String::new(),
+            })
+            .await?
+            .into_inner();
+
+        progress!(
+            "Batch {} ({} files):",
+            response.batch_id,
+            response.files.len()
+        );
+        let files = response
+            .files
+            .into_iter()
+            .map(|file| {
+                progress!("{}  {}", file.content_hash, file.file_name);
+                ListedFile {
+                    name: file.file_name,
+                    content_hash: file.content_hash,
+                }
+            })
+            .collect();
+
+        Ok(ListReport {
+            batch_id: response.batch_id,
+            files,
+        })
+    }
+
+    #[derive(Serialize, Debug)]
+    pub struct MerkleRootReport {
+        pub merkle_root: String,
+        pub batches: Vec<BatchRoot>,
+    }
+
+    #[derive(Serialize, Debug)]
+    pub struct BatchRoot {
+        pub batch_id: String,
+        pub merkle_root: String,
+        /// The root was reported by the server rather than computed on upload.
+        pub server_reported: bool,
+    }
+
+    impl Report for MerkleRootReport {}
+
+    /// Returns the merkle roots recorded locally for the uploaded batches. Works
+    /// offline.
+    pub fn merkle_roots(context: &Context) -> MerkleRootReport {
+        progress!("Merkle root: <{}>", context.app_config.merkle_tree_root);
+        for batch in &context.app_config.batches {
+            match batch.server_reported {
+                true => progress!(
+                    "Batch {}: <{}> (reported by the server)",
+                    batch.id,
+                    batch.merkle_root
+                ),
+                false => progress!("Batch {}: <{}>", batch.id, batch.merkle_root),
+            }
+        }
+
+        MerkleRootReport {
+            merkle_root: context.app_config.merkle_tree_root.to_string(),
+            batches: context
+                .app_config
+                .batches
+                .iter()
+                .map(|batch| BatchRoot {
+                    batch_id: batch.id.to_string(),
+                    merkle_root: batch.merkle_root.to_string(),
+                    server_reported: batch.server_reported,
+                })
+                .collect(),
+        }
+    }
+
+    #[derive(Serialize, Debug, Default)]
+    pub struct UploadReport {
+        pub directory: PathBuf,
+        /// The new batch, unless the directory was already up to date.
+        pub batch_id: Option<String>,
+        /// Merkle root computed locally for the new batch.
+        pub merkle_root: Option<String>,
+        pub files: usize,
+        /// Files carried over from the previous batch instead of being sent again.
+        pub unchanged_files: usize,
+        pub up_to_date: bool,
+        /// How the server processed the batch, when waiting for it.
+        pub job: Option<JobReport>,
+    }
+
+    impl Report for UploadReport {
+        fn failure(&self) -> Option<CommandError> {
+            self.job.as_ref().and_then(|job| job.failure())
         }
     }
 
+    /// Uploads the files under `path` as a new batch and records them in the
+    /// profile's manifest. Only new and changed files are sent unless `full` is set.
+    /// The files are left in place unless `move_files` is set.
     pub async fn upload_files(
         syncx_client: &mut SyncxClient<tonic::transport::Channel>,
         path: &str,
+        wait: bool,
+        move_files: bool,
+        full: bool,
         context: &mut Context,
-    ) {
-        let path = PathBuf::from(path);
-        let files = list_files_in_dir(&path).unwrap();
-        let merkle_tree = generate_merkle_tree(&files).unwrap();
+    ) -> Result<UploadReport, CommandError> {
+        let path = fs::canonicalize(path).map_err(|e| {
+            CommandError::local(format!("Failed to resolve directory {}: {}", path, e))
+        })?;
+        let zip_path = upload_zip_path(&path, context)?;
 
-        let zip_path = PathBuf::from(&path).join(DEFAULT_ZIP_FILE);
-        let _ = zip_files(&files, &zip_path);
+        let upload = match resume_upload(syncx_client, &path, &zip_path, context).await {
+            Some(upload) => upload,
+            None => match begin_upload(syncx_client, &path, &zip_path, full, context).await? {
+                Some(upload) => upload,
+                None => {
+                    return Ok(UploadReport {
+                        directory: path,
+                        up_to_date: true,
+                        ..Default::default()
+                    })
+                }
+            },
+        };
 
-        context
-            .app_config
-            .set_merkle_root(merkle_tree.root().to_string());
+        let max_attempts = context.app_config.connection.max_attempts;
+        let retry_backoff_secs = context.app_config.connection.retry_backoff_secs;
+        let mut attempt = 1;
+        loop {
+            match send_chunks(syncx_client, &upload, &zip_path, context).await {
+                Ok(()) => break,
+                Err(e) if attempt < max_attempts => {
+                    progress!(
+                        "Upload interrupted ({}). Resuming, attempt {} of {}...",
+                        e.message(),
+                        attempt + 1,
+                        max_attempts
+                    );
+                    tokio::time::sleep(Duration::from_secs(retry_backoff_secs * attempt)).await;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    progress!("Run the upload again to resume it");
+                    return Err(e.into());
+                }
+            }
+        }
 
+        let request = CommitUploadRequest {
+            jwt: context.app_config.jwt.to_string(),
+            upload_id: upload.upload_id.to_string(),
+        };
+
+        let response = syncx_client.commit_upload(request).await?.into_inner();
+        progress!("{:?}", response);
+
+        let _ = fs::remove_file(&zip_path);
+
+        // Only the files that are part of the batch are removed.
+        if move_files {
+            for file in &upload.files {
+                if let Some(file_path) = join_relative_path(&path, &file.name) {
+                    let _ = fs::remove_file(file_path);
+                }
+            }
+        }
+
+        let manifest_path = context.manifest_path();
+        let mut manifest = Manifest::read(&manifest_path);
+        manifest.record(upload.manifest);
+        if let Err(e) = manifest.write(&manifest_path) {
+            progress!("Failed to update the upload manifest: {}", e);
+        }
+
+        let mut report = UploadReport {
+            directory: path,
+            batch_id: Some(response.batch_id.to_string()),
+            merkle_root: Some(upload.merkle_root.to_string()),
+            files: upload.files.len(),
+            unchanged_files: upload.unchanged_files.len(),
+            up_to_date: false,
+            job: None,
+        };
+
+        context.app_config.add_batch(BatchRecord {
+            id: response.batch_id.to_string(),
+            merkle_root: upload.merkle_root,
+            files: upload.files,
+            directory: Some(upload.directory.clone()),
+            server_reported: false,
+            lineage: upload.lineage.clone(),
+        });
+        context.app_config.set_pending_upload(None);
         let _ = context.app_config.write(&context.path);
 
-        let file_contents = tokio::fs::read(&zip_path).await.unwrap();
-        let checksum = hash_bytes(&file_contents);
+        if wait {
+            report.job = Some(wait_for_job(syncx_client, &response.batch_id, context).await?);
+        }
+
+        Ok(report)
+    }
+
+    /// How the server has processed an uploaded batch.
+    #[derive(Serialize, Debug)]
+    pub struct JobReport {
+        pub batch_id: String,
+        /// One of `queued`, `unpacking`, `uploading`, `done` or `failed`.
+        pub state: String,
+        pub merkle_root: String,
+        pub reason: String,
+        /// Whether the server's merkle root matches the local one, once the batch is done.
+        pub root_matches: Option<bool>,
+    }
+
+    impl Report for JobReport {
+        fn failure(&self) -> Option<CommandError> {
+            if self.state == "failed" {
+                return Some(CommandError::new(ErrorKind::Server, &self.reason));
+            }
+
+            match self.root_matches {
+                Some(false) => Some(CommandError::verification(format!(
+                    "Server merkle root of batch {} does not match the local one",
+                    self.batch_id
+                ))),
+                _ => None,
+            }
+        }
+    }
+
+    /// Prints the processing status of batch `batch_id`, or of the latest batch if none is given.
+    pub async fn job_status(
+        syncx_client: &mut SyncxClient<tonic::transport::Channel>,
+        batch_id: Option<&str>,
+        context: &mut Context,
+    ) -> Result<JobReport, CommandError> {
+        let batch_id = match batch_id {
+            Some(batch_id) => batch_id.to_string(),
+            None => match context.app_config.batches.last() {
+                Some(batch) => batch.id.to_string(),
+                None => return Err(CommandError::local("No uploaded batches found")),
+            },
+        };
+
+        let request = JobStatusRequest {
+            jwt: context.app_config.jwt.to_string(),
+            batch_id,
+        };
+
+        let status = syncx_client.get_job_status(request).await?.into_inner();
+        Ok(job_report(&status, context))
+    }
+
+    /// Follows the server's processing of batch `batch_id` until it is done or has failed.
+    async fn wait_for_job(
+        syncx_client: &mut SyncxClient<tonic::transport::Channel>,
+        batch_id: &str,
+        context: &Context,
+    ) -> Result<JobReport, CommandError> {
+        progress!("Waiting for the server to process batch {}...", batch_id);
+
+        let request = JobStatusRequest {
+            jwt: context.app_config.jwt.to_string(),
+            batch_id: batch_id.to_string(),
+        };
+
+        let mut stream = syncx_client.watch_job(request).await?.into_inner();
+
+        let mut report = None;
+        while let Some(status) = stream.message().await? {
+            report = Some(job_report(&status, context));
+        }
+
+        report.ok_or_else(|| CommandError::new(ErrorKind::Server, "Server sent no job status"))
+    }
+
+    /// Prints a job status and describes it for the command's report.
+    fn job_report(status: &JobStatus, context: &Context) -> JobReport {
+        let mut root_matches = None;
+        match status.state() {
+            JobState::Done => {
+                progress!(
+                    "Batch {} is ready. Server merkle root: <{}>",
+                    status.batch_id,
+                    status.merkle_root
+                );
+
+                if let Some(merkle_root) = context.app_config.batch_root(&status.batch_id) {
+                    let matches = merkle_root == status.merkle_root;
+                    progress!("Server merkle root matches local merkle root <{}>", matches);
+                    root_matches = Some(matches);
+                }
+            }
+            JobState::Failed => {
+                progress!("Batch {} failed: {}", status.batch_id, status.reason)
+            }
+            state => progress!("Batch {}: {:?}", status.batch_id, state),
+        }
+
+        JobReport {
+            batch_id: status.batch_id.to_string(),
+            state: status.state().as_str_name().to_lowercase(),
+            merkle_root: status.merkle_root.to_string(),
+            reason: status.reason.to_string(),
+            root_matches,
+        }
+    }
+
+    /// Where the archive of an upload of `path` is built. It lives outside `path`,
+    /// in a cache directory only the user can open, and stays put until the upload
+    /// is committed so an interrupted upload can be resumed from it.
+    fn upload_zip_path(path: &Path, context: &Context) -> Result<PathBuf, CommandError> {
+        let upload_dir = ProjectDirs::from("com", "Synx", "Client")
+            .map(|dirs| dirs.cache_dir().join(UPLOAD_DIR))
+            .ok_or_else(|| CommandError::local("Failed to find the cache directory"))?;
+        create_private_dir(&upload_dir).map_err(|e| {
+            CommandError::local(format!("Failed to create {:?}: {}", upload_dir, e))
+        })?;
+
+        let key = format!("{}:{}", context.path.display(), path.display());
+        Ok(upload_dir.join(format!("{}.zip", hash_bytes(key.as_bytes()))))
+    }
+
+    /// Returns the pending upload of `path` if the server still has it and the
+    /// archive it was started from is unchanged.
+    async fn resume_upload(
+        syncx_client: &mut SyncxClient<tonic::transport::Channel>,
+        path: &Path,
+        zip_path: &Path,
+        context: &mut Context,
+    ) -> Option<PendingUpload> {
+        let upload = context.app_config.pending_upload.clone()?;
+        if upload.directory != path {
+            return None;
+        }
+
+        let (size, modified) = archive_stamp(zip_path).ok()?;
+        if size != upload.archive_size || modified != upload.archive_modified {
+            return None;
+        }
+
+        let response = syncx_client
+            .query_upload(QueryUploadRequest {
+                jwt: context.app_config.jwt.to_string(),
+                upload_id: upload.upload_id.to_string(),
+            })
+            .await
+            .ok()?
+            .into_inner();
+
+        progress!(
+            "Resuming upload {} ({} of {} bytes already received)",
+            upload.upload_id,
+            response.bytes_received,
+            response.size
+        );
+
+        Some(upload)
+    }
+
+    /// Zips the new and changed files in `path`, along with the modes, modification
+    /// times and symbolic links found under it, and starts a new upload session for
+    /// the archive. Unless `full` is set, files whose content the server already
+    /// holds in the directory's previous batch are carried over from it instead of
+    /// being sent again. Returns `None` if nothing changed since that batch.
+    async fn begin_upload(
+        syncx_client: &mut SyncxClient<tonic::transport::Channel>,
+        path: &Path,
+        zip_path: &Path,
+        full: bool,
+        context: &mut Context,
+    ) -> Result<Option<PendingUpload>, CommandError> {
+        let files = list_files_recursive(path)?
+            .into_iter()
+            .filter(|file| *file != path.join(METADATA_MANIFEST))
+            .collect::<Vec<PathBuf>>();
+        if files.is_empty() {
+            return Err(CommandError::local(format!(
+                "{} has no files to upload",
+                path.display()
+            )));
+        }
+
+        let symlinks = list_symlinks_recursive(path)?;
+        let metadata = files
+            .iter()
+            .chain(symlinks.iter())
+            .map(|file| read_file_metadata(path, file))
+            .collect::<std::io::Result<Vec<FileMetadata>>>()?;
+
+        // Hashes recorded by earlier uploads are reused for files that haven't been touched.
+        let manifest = Manifest::read(&context.manifest_path());
+        let mut hashing = Transfer::start(Stage::Hashing, total_size(&files));
+        let mut entries = files
+            .iter()
+            .map(|file| {
+                let entry = ManifestEntry::new(path, file, "", manifest.get(file))?;
+                hashing.advance(entry.size);
+                Ok(entry)
+            })
+            .collect::<std::io::Result<Vec<ManifestEntry>>>()?;
+
+        let merkle_tree = MerkleTree::from_leaf_hashes(
+            entries
+                .iter()
+                .map(|entry| entry.content_hash.to_string())
+                .collect(),
+        );
+
+        let base = match full {
+            true => None,
+            false => base_batch(syncx_client, path, context).await,
+        };
+
+        let (base_batch_id, server_files) = base.unwrap_or_default();
+        let unchanged_files = entries
+            .iter()
+            .filter(|entry| server_files.get(&entry.name) == Some(&entry.content_hash))
+            .map(|entry| entry.name.to_string())
+            .collect::<HashSet<String>>();
+
+        if !base_batch_id.is_empty()
+            && unchanged_files.len() == entries.len()
+            && unchanged_files.len() == server_files.len()
+        {
+            progress!(
+                "{} is up to date with batch {}",
+                path.display(),
+                base_batch_id
+            );
+            return Ok(None);
+        }
+
+        let changed_files = files
+            .iter()
+            .zip(entries.iter())
+            .filter(|(_, entry)| !unchanged_files.contains(&entry.name))
+            .map(|(file, _)| file.to_path_buf())
+            .collect::<Vec<PathBuf>>();
+
+        progress!(
+            "Uploading {} new or changed files, keeping {} unchanged files",
+            changed_files.len(),
+            unchanged_files.len()
+        );
 
-        let requests = file_contents
-            .chunks(4096)
-            .map(|chunk| FileUploadRequest {
+        // Chunk roots of unchanged files are taken from the base batch's records,
+        // while changed files are read again to compute theirs.
+        let changed_size = total_size(&changed_files);
+        hashing.set_total(hashing.total() + changed_size);
+        let base_records = context
+            .app_config
+            .batch(&base_batch_id)
+            .map(|batch| batch.files.clone())
+            .unwrap_or_default();
+        let file_records = files
+            .iter()
+            .zip(entries.iter())
+            .map(|(file, entry)| {
+                let base_record = base_records
+                    .iter()
+                    .find(|record| record.name == entry.name)
+                    .filter(|_| unchanged_files.contains(&entry.name));
+
+                match base_record {
+                    Some(record) => Ok(FileRecord {
+                        content_hash: entry.content_hash.to_string(),
+                        ..record.clone()
+                    }),
+                    None => {
+                        let record = file_record(path, file)?;
+                        hashing.advance(entry.size);
+                        Ok(record)
+                    }
+                }
+            })
+            .collect::<std::io::Result<Vec<FileRecord>>>()?;
+        drop(hashing);
+
+        // The archive is always created afresh, so drop one left by an earlier upload.
+        match fs::remove_file(zip_path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+
+        let mut zipping = Transfer::start(Stage::Zipping, changed_size);
+        zip_files_relative(path, &changed_files, zip_path, &metadata, |bytes| {
+            zipping.advance(bytes)
+        })?;
+        drop(zipping);
+
+        // The archive is hashed as it is sent, in `read_chunks`.
+        let (size, modified) = archive_stamp(zip_path)?;
+
+        let base_batch_id = match unchanged_files.is_empty() {
+            true => None,
+            false => Some(base_batch_id),
+        };
+        let unchanged_files = unchanged_files.into_iter().collect::<Vec<String>>();
+        let lineage = context.app_config.directory_lineage(path);
+
+        let response = syncx_client
+            .begin_upload(BeginUploadRequest {
                 jwt: context.app_config.jwt.to_string(),
-                content: chunk.to_vec(),
+                size,
+                base_batch_id: base_batch_id.clone().unwrap_or_default(),
+                unchanged_files: unchanged_files.clone(),
+                lineage: lineage.clone().unwrap_or_default(),
             })
-            .collect::<Vec<FileUploadRequest>>();
+            .await?
+            .into_inner();
+
+        // The batch id is the id of the upload session.
+        for entry in entries.iter_mut() {
+            entry.batch_id = response.upload_id.to_string();
+        }
 
-        let mut request = tonic::Request::new(tokio_stream::iter(requests));
-        request
-            .metadata_mut()
-            .insert("checksum", checksum.parse().unwrap());
+        // The first upload of a directory starts a lineage named after it.
+        let upload = PendingUpload {
+            lineage: lineage.unwrap_or_else(|| response.upload_id.to_string()),
+            upload_id: response.upload_id,
+            directory: path.to_path_buf(),
+            archive_size: size,
+            archive_modified: modified,
+            merkle_root: merkle_tree.root().to_string(),
+            files: file_records,
+            manifest: entries,
+            base_batch_id,
+            unchanged_files,
+        };
 
-        match syncx_client.upload_files(request).await {
+        context.app_config.set_pending_upload(Some(upload.clone()));
+        let _ = context.app_config.write(&context.path);
+
+        Ok(Some(upload))
+    }
+
+    /// Size and modification time of the archive at `zip_path`.
+    fn archive_stamp(zip_path: &Path) -> std::io::Result<(u64, Option<SystemTime>)> {
+        let metadata = fs::metadata(zip_path)?;
+        Ok((metadata.len(), metadata.modified().ok()))
+    }
+
+    /// Returns the latest batch uploaded from `path` that the server has processed,
+    /// with the content hash of each of its files by name.
+    async fn base_batch(
+        syncx_client: &mut SyncxClient<tonic::transport::Channel>,
+        path: &Path,
+        context: &Context,
+    ) -> Option<(String, HashMap<String, String>)> {
+        let batch = context
+            .app_config
+            .batches
+            .iter()
+            .rev()
+            .find(|batch| batch.directory.as_deref() == Some(path))?;
+
+        let response = syncx_client
+            .list_files(ListFilesRequest {
+                jwt: context.app_config.jwt.to_string(),
+                batch_id: batch.id.to_string(),
+                lineage: String::new(),
+            })
+            .await;
+
+        match response {
             Ok(response) => {
-                println!("{:?}", response.into_inner());
-                let _ = delete_files_in_directory(&path);
+                let response = response.into_inner();
+                let files = response
+                    .files
+                    .into_iter()
+                    .map(|file| (file.file_name, file.content_hash))
+                    .collect::<HashMap<String, String>>();
+
+                Some((response.batch_id, files))
+            }
+            Err(e) => {
+                progress!(
+                    "Batch {} can't be built on ({}). Uploading every file",
+                    batch.id,
+                    e.message()
+                );
+                None
+            }
+        }
+    }
+
+    /// Sum of the sizes of `files`, skipping those that can't be read.
+    fn total_size(files: &[PathBuf]) -> u64 {
+        files
+            .iter()
+            .filter_map(|file| fs::metadata(file).ok())
+            .map(|metadata| metadata.len())
+            .sum()
+    }
+
+    /// Records the chunk tree root of `path`, so the server can later be challenged
+    /// to prove it still holds any of the file's chunks.
+    fn file_record(base_dir: &Path, path: &Path) -> std::io::Result<FileRecord> {
+        let content = file_to_bytes(path)?;
+        let name = relative_path_name(base_dir, path).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid file name")
+        })?;
+
+        Ok(FileRecord {
+            name,
+            chunk_root: generate_chunk_merkle_tree(&content).root().to_string(),
+            chunk_count: file_chunks(&content).len() as u64,
+            content_hash: hash_bytes(&content),
+        })
+    }
+
+    /// Streams the part of the archive at `zip_path` the server has not received
+    /// yet. The archive is read one chunk at a time as the connection takes them.
+    async fn send_chunks(
+        syncx_client: &mut SyncxClient<tonic::transport::Channel>,
+        upload: &PendingUpload,
+        zip_path: &Path,
+        context: &Context,
+    ) -> Result<(), tonic::Status> {
+        let jwt = context.app_config.jwt.to_string();
+        let size = fs::metadata(zip_path)?.len();
+
+        let offset = syncx_client
+            .query_upload(QueryUploadRequest {
+                jwt: jwt.clone(),
+                upload_id: upload.upload_id.to_string(),
+            })
+            .await?
+            .into_inner()
+            .bytes_received;
+
+        if offset >= size {
+            return Ok(());
+        }
+
+        let (tx, rx) = mpsc::channel(UPLOAD_QUEUE_LENGTH);
+        let template = FileUploadRequest {
+            jwt,
+            upload_id: upload.upload_id.to_string(),
+            ..Default::default()
+        };
+        let reader = tokio::spawn(read_chunks(
+            zip_path.to_path_buf(),
+            offset,
+            context.app_config.connection.upload_chunk_size,
+            template,
+            tx,
+        ));
+
+        // Progress follows the chunks as they are handed to the connection.
+        let mut uploading = Transfer::start(Stage::Uploading, size);
+        uploading.set_position(offset);
+        let requests = ReceiverStream::new(rx).map(move |request| {
+            uploading.advance(request.content.len() as u64);
+            request
+        });
+
+        let response = syncx_client.upload_files(requests).await;
+
+        // A failed read ends the stream early, which explains a short upload
+        // better than the server's answer does.
+        reader
+            .await
+            .map_err(|e| tonic::Status::internal(e.to_string()))??;
+
+        let bytes_received = response?.into_inner().bytes_received;
+        if bytes_received != size {
+            return Err(tonic::Status::aborted(format!(
+                "Upload stopped at byte {} of {}",
+                bytes_received, size
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Reads the archive at `path` from `offset` in chunks of `chunk_size` bytes,
+    /// and sends each as a copy of `template` holding the chunk. The archive is
+    /// hashed as it is read, and the chunk that completes it carries the digest.
+    /// Stops early once the receiver is gone.
+    async fn read_chunks(
+        path: PathBuf,
+        offset: u64,
+        chunk_size: usize,
+        template: FileUploadRequest,
+        tx: mpsc::Sender<FileUploadRequest>,
+    ) -> std::io::Result<()> {
+        let mut file = tokio::fs::File::open(&path).await?;
+        let size = file.metadata().await?.len();
+
+        // When resuming, the part the server already holds is hashed first. It
+        // is read once more, but never sent again.
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0; chunk_size];
+        let mut prefix = (&mut file).take(offset);
+        loop {
+            let read = prefix.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+        file.seek(std::io::SeekFrom::Start(offset)).await?;
+
+        let mut offset = offset;
+        loop {
+            let mut content = Vec::with_capacity(chunk_size);
+            (&mut file)
+                .take(chunk_size as u64)
+                .read_to_end(&mut content)
+                .await?;
+            if content.is_empty() {
+                return Ok(());
+            }
+
+            hasher.update(&content);
+            let length = content.len() as u64;
+            let checksum = match offset + length == size {
+                true => hex::encode(hasher.finalize_reset()),
+                false => String::new(),
+            };
+            let request = FileUploadRequest {
+                content,
+                offset,
+                checksum,
+                ..template.clone()
+            };
+            if tx.send(request).await.is_err() {
+                return Ok(());
+            }
+            offset += length;
+        }
+    }
+
+    #[derive(Serialize, Debug)]
+    pub struct DownloadReport {
+        pub batch_id: String,
+        /// Files saved, which is none of them unless they all verified.
+        pub files: Vec<PathBuf>,
+        pub valid: bool,
+        /// Merkle root computed from the downloaded files and their proof.
+        pub merkle_root: String,
+    }
+
+    impl Report for DownloadReport {
+        fn failure(&self) -> Option<CommandError> {
+            match self.valid {
+                true => None,
+                false => Some(CommandError::verification(format!(
+                    "Files downloaded from batch {} failed verification and were discarded",
+                    self.batch_id
+                ))),
             }
-            Err(e) => println!("something went wrong: {:?}", e),
         }
     }
 
     pub async fn download_file(
         syncx_client: &mut SyncxClient<tonic::transport::Channel>,
         file_name: &str,
+        batch_id: Option<&str>,
         download_dir: &PathBuf,
+        apply_metadata: bool,
         context: &mut Context,
-    ) {
+    ) -> Result<DownloadReport, CommandError> {
+        let download_dir = Path::new(download_dir);
+        let _ = fs::create_dir_all(download_dir);
+
+        // Bytes are collected in a `.part` file, which only replaces `output_path`
+        // once its merkle proof checks out.
+        let (output_path, part_path) = download_paths(download_dir, file_name)
+            .ok_or_else(|| CommandError::local(format!("Invalid file path {}", file_name)))?;
+
+        let max_attempts = context.app_config.connection.max_attempts;
+        let retry_backoff_secs = context.app_config.connection.retry_backoff_secs;
+        let mut attempt = 1;
+        let (merkle_proof, batch_id, metadata) = loop {
+            match download_chunks(syncx_client, file_name, batch_id, &part_path, context).await {
+                Ok(downloaded) => break downloaded,
+                Err(e) if attempt < max_attempts => {
+                    if e.code() == Code::OutOfRange {
+                        // The partial file is longer than the file on the server,
+                        // so it can't be a prefix of it.
+                        progress!("Discarding stale partial download {:?}", part_path);
+                        let _ = fs::remove_file(&part_path);
+                    } else {
+                        progress!(
+                            "Download interrupted ({}). Resuming, attempt {} of {}...",
+                            e.message(),
+                            attempt + 1,
+                            max_attempts
+                        );
+                        tokio::time::sleep(Duration::from_secs(retry_backoff_secs * attempt)).await;
+                    }
+                    attempt += 1;
+                }
+                Err(e) => {
+                    progress!("Run the download again to resume it");
+                    return Err(e.into());
+                }
+            }
+        };
+
+        progress!("Merkle proof: {:?}", &merkle_proof.nodes);
+        progress!("Verifying file validity against batch {}...", batch_id);
+
+        let merkle_root = match recorded_root(context, &batch_id) {
+            Ok(merkle_root) => merkle_root,
+            Err(e) => {
+                let _ = fs::remove_file(&part_path);
+                return Err(e);
+            }
+        };
+
+        let (valid, root) = verify_download(&part_path, merkle_root, &merkle_proof.nodes)?;
+
+        let mut files = Vec::new();
+        if valid {
+            fs::rename(&part_path, &output_path)?;
+            restore_metadata(&output_path, metadata.filter(|_| apply_metadata).as_ref());
+            progress!("File {:?} downloaded", output_path);
+            files.push(output_path);
+        } else {
+            let _ = fs::remove_file(&part_path);
+            progress!("Downloaded file failed verification and was discarded");
+        }
+
+        progress!(
+            "File is valid <{}>. Computed merkle root: {:?}",
+            valid,
+            root
+        );
+        progress!("View your merkle root on your client to confirm [cargo run merkleroot]");
+
+        Ok(DownloadReport {
+            batch_id,
+            files,
+            valid,
+            merkle_root: root,
+        })
+    }
+
+    /// Downloads several files of one batch over a single stream, named explicitly or
+    /// matched by `pattern`, and verifies them together with the batch's multiproof.
+    pub async fn download_files(
+        syncx_client: &mut SyncxClient<tonic::transport::Channel>,
+        file_names: Vec<String>,
+        pattern: Option<String>,
+        batch_id: Option<&str>,
+        download_dir: &PathBuf,
+        apply_metadata: bool,
+        context: &mut Context,
+    ) -> Result<DownloadReport, CommandError> {
         let download_dir = Path::new(download_dir);
         let _ = fs::create_dir_all(download_dir);
 
-        let output_path = download_dir.join(file_name);
+        let request = FilesDownloadRequest {
+            jwt: context.app_config.jwt.to_string(),
+            file_names,
+            pattern: pattern.unwrap_or_default(),
+            batch_id: batch_id.unwrap_or_default().to_string(),
+        };
+
+        // Every file goes to a `.part` file first, and none of them replaces its
+        // target until the proof over all of them checks out.
+        let mut part_files = Vec::new();
+        let received = receive_files(syncx_client, request, download_dir, &mut part_files).await;
+
+        let (merkle_proof, batch_id) = match received {
+            Ok(received) => received,
+            Err(e) => {
+                part_files.iter().for_each(|(_, part_path, _)| {
+                    let _ = fs::remove_file(part_path);
+                });
+                return Err(e.into());
+            }
+        };
+
+        progress!(
+            "Verifying {} files against batch {}...",
+            part_files.len(),
+            batch_id
+        );
+
+        let merkle_root = match recorded_root(context, &batch_id) {
+            Ok(merkle_root) => merkle_root,
+            Err(e) => {
+                part_files.iter().for_each(|(_, part_path, _)| {
+                    let _ = fs::remove_file(part_path);
+                });
+                return Err(e);
+            }
+        };
+
+        let leaves = part_files
+            .iter()
+            .map(|(_, part_path, _)| file_to_bytes(part_path).map(|bytes| hash_bytes(&bytes)))
+            .collect::<std::io::Result<Vec<String>>>()?;
+        let (valid, root) = MerkleTree::verify_multiproof(&leaves, &merkle_proof, merkle_root);
+
+        let mut files = Vec::new();
+        for (output_path, part_path, metadata) in part_files {
+            if valid {
+                fs::rename(&part_path, &output_path)?;
+                restore_metadata(&output_path, metadata.as_ref().filter(|_| apply_metadata));
+                progress!("File {:?} downloaded", output_path);
+                files.push(output_path);
+            } else {
+                let _ = fs::remove_file(part_path);
+            }
+        }
+
+        if !valid {
+            progress!("Downloaded files failed verification and were discarded");
+        }
+
+        progress!(
+            "Files are valid <{}>. Computed merkle root: {:?}",
+            valid,
+            root
+        );
+
+        Ok(DownloadReport {
+            batch_id,
+            files,
+            valid,
+            merkle_root: root,
+        })
+    }
+
+    /// Writes the files framed in a `DownloadFiles` stream to `.part` files in
+    /// `download_dir`, recording each one in `part_files`, and returns the
+    /// multiproof that closes the stream along with the batch it belongs to.
+    pub(crate) async fn receive_files(
+        syncx_client: &mut SyncxClient<tonic::transport::Channel>,
+        request: FilesDownloadRequest,
+        download_dir: &Path,
+        part_files: &mut Vec<(PathBuf, PathBuf, Option<FileMetadata>)>,
+    ) -> Result<(MultiProof, String), tonic::Status> {
+        let mut stream = syncx_client.download_files(request).await?.into_inner();
+
+        let mut current: Option<(fs::File, u64)> = None;
+        let mut merkle_proof = None;
+        let mut batch_id = String::new();
+        let mut downloading = Transfer::start(Stage::Downloading, 0);
+        while let Some(response) = stream.message().await? {
+            batch_id = response.batch_id;
+
+            match response.frame {
+                Some(Frame::Header(header)) => {
+                    check_file_complete(&current)?;
+
+                    // Names come from the server, so never let them leave `download_dir`.
+                    let (output_path, part_path) = download_paths(download_dir, &header.file_name)
+                        .ok_or_else(|| {
+                            tonic::Status::data_loss(format!(
+                                "Invalid file name {}",
+                                header.file_name
+                            ))
+                        })?;
+
+                    let file = fs::File::create(&part_path)?;
+                    part_files.push((output_path, part_path, header.metadata));
+                    downloading.set_total(downloading.total() + header.size);
+                    current = Some((file, header.size));
+                }
+                Some(Frame::Content(content)) => {
+                    let Some((file, remaining)) = current.as_mut() else {
+                        return Err(tonic::Status::data_loss(
+                            "Content sent before a file header",
+                        ));
+                    };
+
+                    if content.len() as u64 > *remaining {
+                        return Err(tonic::Status::data_loss("File is longer than its header"));
+                    }
+
+                    file.write_all(&content)?;
+                    *remaining -= content.len() as u64;
+                    downloading.advance(content.len() as u64);
+                }
+                Some(Frame::MerkleProof(proof)) => merkle_proof = Some(multiproof(proof)),
+                None => {}
+            }
+        }
+
+        check_file_complete(&current)?;
+
+        let merkle_proof =
+            merkle_proof.ok_or_else(|| tonic::Status::data_loss("Server sent no merkle proof"))?;
+
+        Ok((merkle_proof, batch_id))
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn check_file_complete(current: &Option<(fs::File, u64)>) -> Result<(), tonic::Status> {
+        match current {
+            Some((_, remaining)) if *remaining > 0 => Err(tonic::Status::aborted(format!(
+                "Download stopped {} bytes before the end of a file",
+                remaining
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn multiproof(proof: MerkleMultiProof) -> MultiProof {
+        MultiProof {
+            leaf_count: proof.leaf_count as usize,
+            indices: proof.indices.iter().map(|index| *index as usize).collect(),
+            nodes: proof
+                .nodes
+                .into_iter()
+                .map(|node| (node.level as usize, node.index as usize, node.hash))
+                .collect(),
+        }
+    }
+
+    /// Streams back every file of batch `batch_id`, or of the latest batch if none is
+    /// given, into the directory it was uploaded from unless `restore_dir` is set.
+    /// Each file is verified on its own, and only verified files are kept.
+    pub async fn restore(
+        syncx_client: &mut SyncxClient<tonic::transport::Channel>,
+        batch_id: Option<&str>,
+        restore_dir: Option<PathBuf>,
+        apply_metadata: bool,
+        overwrite: bool,
+        context: &mut Context,
+    ) -> Result<RestoreReport, CommandError> {
+        // The batch is resolved here rather than by the server, so the files are
+        // always checked against the root recorded for the batch they come from.
+        let batch_id = match batch_id {
+            Some(batch_id) => batch_id.to_string(),
+            None => context
+                .app_config
+                .batches
+                .last()
+                .map(|batch| batch.id.clone())
+                .ok_or_else(|| {
+                    CommandError::local("No uploaded batch recorded. Pass one with -b")
+                })?,
+        };
+        let merkle_root = recorded_root(context, &batch_id)?.to_string();
+        let batch = context.app_config.batch(&batch_id);
+
+        let restore_dir = restore_dir
+            .or_else(|| batch.and_then(|b| b.directory.clone()))
+            .ok_or_else(|| {
+                CommandError::local("No directory recorded for the batch. Pass one with -d")
+            })?;
+        let _ = fs::create_dir_all(&restore_dir);
+
+        let request = RestoreRequest {
+            jwt: context.app_config.jwt.to_string(),
+            batch_id: batch_id.clone(),
+        };
+
+        let mut report = RestoreReport {
+            batch_id,
+            directory: restore_dir.clone(),
+            apply_metadata,
+            overwrite,
+            complete: true,
+            ..Default::default()
+        };
+        let mut symlinks = Vec::new();
+        let received = receive_restore(
+            syncx_client,
+            request,
+            &restore_dir,
+            &merkle_root,
+            &mut report,
+            &mut symlinks,
+        )
+        .await;
+
+        if let Err(e) = received {
+            progress!("Restore stopped early. The report below is incomplete");
+            report.complete = false;
+            report.error = Some(e.into());
+        }
+
+        // Links are only created once every file is in place, so no file is ever
+        // written through one.
+        for symlink in symlinks {
+            let exists = join_relative_path(&restore_dir, &symlink.path)
+                .is_some_and(|path| fs::symlink_metadata(path).is_ok());
+            if exists && !report.overwrite {
+                report.existing.push(symlink.path);
+                continue;
+            }
+
+            match restore_symlink(&restore_dir, &symlink) {
+                Ok(()) => {
+                    if exists {
+                        report.replaced.push(symlink.path.clone());
+                    }
+                    report.symlinks.push(symlink.path)
+                }
+                Err(e) => {
+                    progress!("Failed to create symbolic link {}: {}", symlink.path, e);
+                    report.failed.push(symlink.path);
+                }
+            }
+        }
+
+        // Files the client uploaded but the server did not send at all are missing too.
+        if let Some(batch) = context.app_config.batch(&report.batch_id) {
+            for file in &batch.files {
+                let sent = report.verified.contains(&file.name)
+                    || report.existing.contains(&file.name)
+                    || report.failed.contains(&file.name)
+                    || report.missing.contains(&file.name);
+                if !sent {
+                    report.missing.push(file.name.to_string());
+                }
+            }
+        }
+
+        progress!("Restored batch {} into {:?}", report.batch_id, restore_dir);
+        progress!(
+            "Verified ({}): {:?}",
+            report.verified.len(),
+            report.verified
+        );
+        progress!("Failed ({}): {:?}", report.failed.len(), report.failed);
+        progress!("Missing ({}): {:?}", report.missing.len(), report.missing);
+        if !report.existing.is_empty() {
+            progress!(
+                "Kept existing ({}): {:?}. Pass --force to replace them",
+                report.existing.len(),
+                report.existing
+            );
+        }
+        if !report.replaced.is_empty() {
+            progress!(
+                "Replaced ({}): {:?}",
+                report.replaced.len(),
+                report.replaced
+            );
+        }
+        progress!(
+            "Symbolic links ({}): {:?}",
+            report.symlinks.len(),
+            report.symlinks
+        );
+
+        Ok(report)
+    }
+
+    #[derive(Serialize, Debug, Default)]
+    pub struct RestoreReport {
+        pub batch_id: String,
+        pub directory: PathBuf,
+        #[serde(skip)]
+        apply_metadata: bool,
+        #[serde(skip)]
+        overwrite: bool,
+        pub verified: Vec<String>,
+        pub failed: Vec<String>,
+        pub missing: Vec<String>,
+        pub symlinks: Vec<String>,
+        /// Verified files left alone because a file already exists at their path.
+        pub existing: Vec<String>,
+        /// Existing files that were overwritten by verified ones.
+        pub replaced: Vec<String>,
+        /// False if the stream stopped before every file was sent.
+        pub complete: bool,
+        #[serde(skip)]
+        error: Option<CommandError>,
+    }
+
+    impl Report for RestoreReport {
+        fn failure(&self) -> Option<CommandError> {
+            if let Some(error) = &self.error {
+                return Some(error.clone());
+            }
+
+            match self.failed.is_empty() && self.missing.is_empty() {
+                true => None,
+                false => Some(CommandError::verification(format!(
+                    "{} files of batch {} failed verification or are missing",
+                    self.failed.len() + self.missing.len(),
+                    self.batch_id
+                ))),
+            }
+        }
+    }
+
+    /// A file of a restore stream that is still being received.
+    struct RestoringFile {
+        name: String,
+        output_path: PathBuf,
+        part_path: PathBuf,
+        file: fs::File,
+        remaining: u64,
+        merkle_proof: Vec<MerkleProofNode>,
+        metadata: Option<FileMetadata>,
+    }
+
+    async fn receive_restore(
+        syncx_client: &mut SyncxClient<tonic::transport::Channel>,
+        request: RestoreRequest,
+        restore_dir: &Path,
+        merkle_root: &str,
+        report: &mut RestoreReport,
+        symlinks: &mut Vec<FileMetadata>,
+    ) -> Result<(), tonic::Status> {
+        let mut stream = syncx_client.restore_batch(request).await?.into_inner();
+
+        let mut current: Option<RestoringFile> = None;
+        let mut downloading = Transfer::start(Stage::Downloading, 0);
+        while let Some(response) = stream.message().await? {
+            if response.batch_id != report.batch_id {
+                return Err(tonic::Status::data_loss(format!(
+                    "Server sent batch {} instead of {}",
+                    response.batch_id, report.batch_id
+                )));
+            }
+
+            match response.frame {
+                Some(restore_response::Frame::Header(header)) => {
+                    if let Some(restored) = current.take() {
+                        finish_restored_file(restored, merkle_root, report);
+                    }
+
+                    // Names come from the server, so never let them leave `restore_dir`.
+                    let (output_path, part_path) = download_paths(restore_dir, &header.file_name)
+                        .ok_or_else(|| {
+                        tonic::Status::data_loss(format!("Invalid file name {}", header.file_name))
+                    })?;
+
+                    downloading.set_total(downloading.total() + header.size);
+                    current = Some(RestoringFile {
+                        file: fs::File::create(&part_path)?,
+                        name: header.file_name,
+                        output_path,
+                        part_path,
+                        remaining: header.size,
+                        merkle_proof: header
+                            .merkle_proof
+                            .map(|proof| proof.nodes)
+                            .unwrap_or_default(),
+                        metadata: header.metadata.filter(|_| report.apply_metadata),
+                    });
+                }
+                Some(restore_response::Frame::Content(content)) => {
+                    let Some(restoring) = current.as_mut() else {
+                        return Err(tonic::Status::data_loss(
+                            "Content sent before a file header",
+                        ));
+                    };
+
+                    if content.len() as u64 > restoring.remaining {
+                        return Err(tonic::Status::data_loss("File is longer than its header"));
+                    }
+
+                    restoring.file.write_all(&content)?;
+                    restoring.remaining -= content.len() as u64;
+                    downloading.advance(content.len() as u64);
+                }
+                Some(restore_response::Frame::Missing(file_name)) => {
+                    if let Some(restored) = current.take() {
+                        finish_restored_file(restored, merkle_root, report);
+                    }
+                    report.missing.push(file_name);
+                }
+                Some(restore_response::Frame::Symlink(symlink)) => symlinks.push(symlink),
+                None => {}
+            }
+        }
+
+        if let Some(restored) = current.take() {
+            finish_restored_file(restored, merkle_root, report);
+        }
+
+        Ok(())
+    }
+
+    /// Returns where `file_name` is saved in `dir` and the `.part` file it is downloaded
+    /// into, creating the directories on its relative path. Returns `None` for names
+    /// that would escape `dir`.
+    fn download_paths(dir: &Path, file_name: &str) -> Option<(PathBuf, PathBuf)> {
+        let output_path = join_relative_path(dir, file_name)?;
+        if let Some(parent) = output_path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+
+        let mut part_path = output_path.clone().into_os_string();
+        part_path.push(".part");
+
+        Some((output_path, PathBuf::from(part_path)))
+    }
+
+    /// Applies the recorded `metadata` of a downloaded file, if there is any.
+    pub(crate) fn restore_metadata(path: &Path, metadata: Option<&FileMetadata>) {
+        if let Some(metadata) = metadata {
+            if let Err(e) = apply_file_metadata(path, metadata) {
+                progress!("Failed to restore metadata of {:?}: {}", path, e);
+            }
+        }
+    }
+
+    /// Recreates a symbolic link of a restored batch. An existing link is replaced,
+    /// but any other existing file is left alone. Links whose target lies outside
+    /// `restore_dir` are refused.
+    fn restore_symlink(restore_dir: &Path, symlink: &FileMetadata) -> std::io::Result<()> {
+        let path = join_relative_path(restore_dir, &symlink.path).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid file name")
+        })?;
+
+        if resolve_link_target(&symlink.path, &symlink.symlink_target).is_none() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "Target {} points outside the restored directory",
+                    symlink.symlink_target
+                ),
+            ));
+        }
+
+        // A link restored earlier may stand in for a directory on the path and point
+        // anywhere, so nothing is ever created beneath one.
+        let mut ancestor = path.parent();
+        while let Some(dir) = ancestor.filter(|dir| *dir != restore_dir) {
+            if fs::symlink_metadata(dir).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("{:?} is a symbolic link", dir),
+                ));
+            }
+            ancestor = dir.parent();
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        if let Ok(existing) = fs::symlink_metadata(&path) {
+            if !existing.file_type().is_symlink() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::AlreadyExists,
+                    "A file already exists at its path",
+                ));
+            }
+            fs::remove_file(&path)?;
+        }
+
+        create_symlink(&path, &symlink.symlink_target)
+    }
+
+    /// Verifies a fully received file and moves it into place, or discards it. An
+    /// existing file is only replaced when the restore may overwrite files.
+    fn finish_restored_file(
+        restored: RestoringFile,
+        merkle_root: &str,
+        report: &mut RestoreReport,
+    ) {
+        let valid = restored.remaining == 0
+            && verify_download(&restored.part_path, merkle_root, &restored.merkle_proof)
+                .is_ok_and(|(valid, _)| valid);
+
+        let exists = fs::symlink_metadata(&restored.output_path).is_ok();
+        if valid && exists && !report.overwrite {
+            let _ = fs::remove_file(&restored.part_path);
+            report.existing.push(restored.name);
+        } else if valid {
+            let output_path = restored.output_path;
+            match fs::rename(&restored.part_path, &output_path) {
+                Ok(()) => {
+                    restore_metadata(&output_path, restored.metadata.as_ref());
+                    if exists {
+                        report.replaced.push(restored.name.clone());
+                    }
+                    report.verified.push(restored.name)
+                }
+                Err(e) => {
+                    progress!("Failed to save {:?}: {}", output_path, e);
+                    report.failed.push(restored.name);
+                }
+            }
+        } else {
+            let _ = fs::remove_file(&restored.part_path);
+            report.failed.push(restored.name);
+        }
+    }
+
+    /// Downloads the rest of `file_name` into `part_path`, starting after the bytes
+    /// it already holds, and returns the merkle proof sent by the server along with
+    /// the batch it belongs to and the file's metadata.
+    async fn download_chunks(
+        syncx_client: &mut SyncxClient<tonic::transport::Channel>,
+        file_name: &str,
+        batch_id: Option<&str>,
+        part_path: &Path,
+        context: &Context,
+    ) -> Result<(MerkleProof, String, Option<FileMetadata>), tonic::Status> {
+        let offset = fs::metadata(part_path).map(|m| m.len()).unwrap_or(0);
 
         let request = tonic::Request::new(FileDownloadRequest {
             jwt: context.app_config.jwt.to_string(),
             file_name: file_name.to_string(),
+            offset,
+            length: 0,
+            batch_id: batch_id.unwrap_or_default().to_string(),
         });
 
-        let mut stream = syncx_client
-            .download_file(request)
-            .await
-            .unwrap()
-            .into_inner();
+        let mut stream = syncx_client.download_file(request).await?.into_inner();
 
         let mut file = fs::OpenOptions::new()
             .append(true)
             .create(true)
-            .open(&output_path)
-            .unwrap();
+            .open(part_path)?;
 
         let mut merkle_proof: Option<MerkleProof> = None;
-        while let Some(response) = stream.message().await.unwrap() {
+        let mut metadata = None;
+        let mut position = offset;
+        let mut file_size = 0;
+        let mut batch_id = String::new();
+        let mut downloading = Transfer::start(Stage::Downloading, 0);
+        while let Some(response) = stream.message().await? {
+            if downloading.total() != response.file_size {
+                downloading.set_total(response.file_size);
+            }
+
+            if response.offset != position {
+                return Err(tonic::Status::data_loss(format!(
+                    "Expected chunk at offset {}, got {}",
+                    position, response.offset
+                )));
+            }
+
             if merkle_proof.is_none() {
                 merkle_proof = response.merkle_proof;
             }
 
-            let chunk = response.content;
-            file.write_all(&chunk).unwrap();
+            if metadata.is_none() {
+                metadata = response.metadata;
+            }
+
+            file.write_all(&response.content)?;
+            position += response.content.len() as u64;
+            downloading.set_position(position);
+            file_size = response.file_size;
+            batch_id = response.batch_id;
         }
 
-        println!("File {:?} dowmloaded", output_path);
-        println!("Merkle proof: {:?}", &merkle_proof.clone().unwrap().nodes);
-        println!("Verifying file validity...",);
+        if position != file_size {
+            return Err(tonic::Status::aborted(format!(
+                "Download stopped at byte {} of {}",
+                position, file_size
+            )));
+        }
+
+        let merkle_proof =
+            merkle_proof.ok_or_else(|| tonic::Status::data_loss("Server sent no merkle proof"))?;
+
+        Ok((merkle_proof, batch_id, metadata))
+    }
 
-        let (valid, root) = verify_download(
-            &output_path,
-            &context.app_config.merkle_tree_root,
-            &merkle_proof.unwrap().nodes,
+    /// Fetches the merkle proof of a file, identified by name or content hash, and
+    /// checks it against the locally recorded merkle root without downloading the file.
+    pub async fn get_proof(
+        syncx_client: &mut SyncxClient<tonic::transport::Channel>,
+        leaf: Leaf,
+        batch_id: Option<&str>,
+        context: &mut Context,
+    ) -> Result<ProofReport, CommandError> {
+        let request = ProofRequest {
+            jwt: context.app_config.jwt.to_string(),
+            leaf: Some(leaf),
+            batch_id: batch_id.unwrap_or_default().to_string(),
+        };
+
+        let response = syncx_client.get_proof(request).await?.into_inner();
+
+        let nodes = response
+            .merkle_proof
+            .map(|merkle_proof| merkle_proof.nodes)
+            .unwrap_or_default();
+        progress!("Merkle proof: {:?}", &nodes);
+
+        let merkle_root = recorded_root(context, &response.batch_id)?;
+
+        let (valid, root) = MerkleTree::verify(&response.leaf, proof_nodes(&nodes), merkle_root);
+        progress!(
+            "Leaf {} of batch {} is valid <{}>. Computed merkle root: {:?}",
+            response.leaf,
+            response.batch_id,
+            valid,
+            root
         );
 
-        println!(
-            "File is valid <{}>. Computed merkle root: {:?}",
-            valid, root
+        Ok(ProofReport {
+            batch_id: response.batch_id,
+            leaf: response.leaf,
+            valid,
+            merkle_root: root,
+        })
+    }
+
+    #[derive(Serialize, Debug)]
+    pub struct ProofReport {
+        pub batch_id: String,
+        pub leaf: String,
+        pub valid: bool,
+        /// Merkle root computed from the leaf and its proof.
+        pub merkle_root: String,
+    }
+
+    impl Report for ProofReport {
+        fn failure(&self) -> Option<CommandError> {
+            match self.valid {
+                true => None,
+                false => Some(CommandError::verification(format!(
+                    "Leaf {} does not match the merkle root of batch {}",
+                    self.leaf, self.batch_id
+                ))),
+            }
+        }
+    }
+
+    /// Prints the merkle root the server holds for batch `batch_id`, or for the
+    /// latest batch if none is given, and compares it with the local one.
+    pub async fn server_root(
+        syncx_client: &mut SyncxClient<tonic::transport::Channel>,
+        batch_id: Option<&str>,
+        context: &mut Context,
+    ) -> Result<RootReport, CommandError> {
+        let request = RootRequest {
+            jwt: context.app_config.jwt.to_string(),
+            batch_id: batch_id.unwrap_or_default().to_string(),
+        };
+
+        let response = syncx_client.get_root(request).await?.into_inner();
+
+        progress!(
+            "Batch {}: server merkle root <{}>",
+            response.batch_id,
+            response.merkle_root
         );
-        println!("View your merkle root on your client to confirm [cargo run merkleroot]")
+
+        let local_root = context
+            .app_config
+            .batch_root(&response.batch_id)
+            .map(|merkle_root| merkle_root.to_string());
+        match &local_root {
+            Some(merkle_root) => progress!(
+                "Server merkle root matches local merkle root <{}>",
+                *merkle_root == response.merkle_root
+            ),
+            None => progress!(
+                "No local merkle root recorded for batch {}",
+                response.batch_id
+            ),
+        }
+
+        Ok(RootReport {
+            matches: local_root
+                .as_ref()
+                .map(|merkle_root| *merkle_root == response.merkle_root),
+            batch_id: response.batch_id,
+            server_root: response.merkle_root,
+            local_root,
+        })
+    }
+
+    #[derive(Serialize, Debug)]
+    pub struct RootReport {
+        pub batch_id: String,
+        pub server_root: String,
+        /// The root recorded when the batch was uploaded, if it was uploaded here.
+        pub local_root: Option<String>,
+        pub matches: Option<bool>,
+    }
+
+    impl Report for RootReport {
+        fn failure(&self) -> Option<CommandError> {
+            match self.matches {
+                Some(false) => Some(CommandError::verification(format!(
+                    "Server merkle root of batch {} does not match the local one",
+                    self.batch_id
+                ))),
+                _ => None,
+            }
+        }
+    }
+
+    /// Challenges the server to prove it still holds a random `fraction` of the
+    /// chunks of batch `batch_id`, or of the latest batch if none is given.
+    pub async fn audit(
+        syncx_client: &mut SyncxClient<tonic::transport::Channel>,
+        batch_id: Option<&str>,
+        fraction: f64,
+        context: &mut Context,
+    ) -> Result<AuditReport, CommandError> {
+        if !(fraction > 0.0 && fraction <= 1.0) {
+            return Err(CommandError::local(
+                "Fraction must be greater than 0 and at most 1",
+            ));
+        }
+
+        let batch = match batch_id {
+            Some(batch_id) => context.app_config.batch(batch_id),
+            None => context.app_config.batches.last(),
+        };
+
+        let batch = batch
+            .cloned()
+            .ok_or_else(|| CommandError::local("No uploaded batches found"))?;
+
+        if batch.files.is_empty() {
+            return Err(CommandError::local(format!(
+                "Batch {} has no chunk records to audit",
+                batch.id
+            )));
+        }
+
+        // Every chunk of the batch, as (file, chunk index).
+        let chunks = batch
+            .files
+            .iter()
+            .flat_map(|file| (0..file.chunk_count).map(move |index| (file, index)))
+            .collect::<Vec<(&FileRecord, u64)>>();
+
+        let sample_size = ((chunks.len() as f64 * fraction).ceil() as usize).clamp(1, chunks.len());
+        let mut rng = rand::thread_rng();
+        let sampled = sample(&mut rng, chunks.len(), sample_size)
+            .into_iter()
+            .map(|index| chunks[index])
+            .collect::<Vec<(&FileRecord, u64)>>();
+
+        progress!(
+            "Auditing {} of {} chunks in batch {}...",
+            sample_size,
+            chunks.len(),
+            batch.id
+        );
+
+        let mut failed = 0;
+        for challenged in sampled.chunks(MAX_CHALLENGE_CHUNKS) {
+            let nonce = rng.gen::<[u8; 32]>().to_vec();
+            let request = ChallengeRequest {
+                jwt: context.app_config.jwt.to_string(),
+                batch_id: batch.id.to_string(),
+                nonce: nonce.clone(),
+                chunks: challenged
+                    .iter()
+                    .map(|(file, index)| ChunkChallenge {
+                        file_name: file.name.to_string(),
+                        chunk_index: *index,
+                    })
+                    .collect(),
+            };
+
+            let responses = syncx_client.challenge(request).await?.into_inner().chunks;
+
+            if responses.len() != challenged.len() {
+                progress!(
+                    "Server answered {} of {} challenged chunks",
+                    responses.len(),
+                    challenged.len()
+                );
+                failed += challenged.len();
+                continue;
+            }
+
+            for ((file, index), response) in challenged.iter().zip(responses.iter()) {
+                if let Err(reason) =
+                    verify_chunk(file, *index, &nonce, &batch.merkle_root, response)
+                {
+                    progress!("Chunk {} of {} failed: {}", index, file.name, reason);
+                    failed += 1;
+                }
+            }
+        }
+
+        progress!(
+            "Audit of batch {} passed <{}>. {} of {} sampled chunks failed",
+            batch.id,
+            failed == 0,
+            failed,
+            sample_size
+        );
+
+        Ok(AuditReport {
+            batch_id: batch.id,
+            sampled: sample_size,
+            failed,
+            passed: failed == 0,
+        })
+    }
+
+    #[derive(Serialize, Debug)]
+    pub struct AuditReport {
+        pub batch_id: String,
+        /// Number of chunks challenged.
+        pub sampled: usize,
+        pub failed: usize,
+        pub passed: bool,
+    }
+
+    impl Report for AuditReport {
+        fn failure(&self) -> Option<CommandError> {
+            match self.passed {
+                true => None,
+                false => Some(CommandError::verification(format!(
+                    "{} of {} sampled chunks of batch {} failed",
+                    self.failed, self.sampled, self.batch_id
+                ))),
+            }
+        }
+    }
+
+    /// Checks the server's answer for chunk `index` of `file` against the locally
+    /// recorded chunk root and the batch's merkle root.
+    fn verify_chunk(
+        file: &FileRecord,
+        index: u64,
+        nonce: &[u8],
+        merkle_root: &str,
+        response: &ChunkResponse,
+    ) -> Result<(), &'static str> {
+        if response.file_name != file.name || response.chunk_index != index {
+            return Err("answer is for a different chunk");
+        }
+
+        if hash_bytes(&[nonce, response.content.as_slice()].concat()) != response.nonce_hash {
+            return Err("nonce hash does not match the chunk");
+        }
+
+        let chunk_leaf = hash_bytes(&chunk_leaf_bytes(index, &response.content));
+        let chunk_proof = response
+            .chunk_proof
+            .as_ref()
+            .map(|proof| proof_nodes(&proof.nodes))
+            .unwrap_or_default();
+        if !MerkleTree::verify(&chunk_leaf, chunk_proof, &file.chunk_root).0 {
+            return Err("chunk proof does not match the recorded chunk root");
+        }
+
+        let file_proof = response
+            .file_proof
+            .as_ref()
+            .map(|proof| proof_nodes(&proof.nodes))
+            .unwrap_or_default();
+        if !MerkleTree::verify(&response.file_leaf, file_proof, merkle_root).0 {
+            return Err("file proof does not match the batch merkle root");
+        }
+
+        Ok(())
+    }
+
+    /// The merkle root recorded when batch `batch_id` was uploaded. Files are only
+    /// ever verified against the root of the batch they were fetched from.
+    pub(crate) fn recorded_root<'a>(
+        context: &'a Context,
+        batch_id: &str,
+    ) -> Result<&'a str, CommandError> {
+        context.app_config.batch_root(batch_id).ok_or_else(|| {
+            CommandError::verification(format!("No merkle root recorded for batch {}", batch_id))
+        })
     }
 
     fn verify_download(
         file_path: &Path,
         root_leaf: &str,
-        merkle_proof: &Vec<MerkleProofNode>,
-    ) -> (bool, String) {
-        let merkle_proof = merkle_proof
+        merkle_proof: &[MerkleProofNode],
+    ) -> std::io::Result<(bool, String)> {
+        let file_as_bytes = file_to_bytes(file_path)?;
+        let file_hash = hash_bytes(&file_as_bytes);
+
+        Ok(MerkleTree::verify(
+            &file_hash,
+            proof_nodes(merkle_proof),
+            root_leaf,
+        ))
+    }
+
+    pub(crate) fn proof_nodes(merkle_proof: &[MerkleProofNode]) -> Vec<(String, u8)> {
+        merkle_proof
             .iter()
             .map(|node| (node.hash.clone(), node.flag as u8))
-            .collect::<Vec<(String, u8)>>();
+            .collect()
+    }
 
-        let file_as_bytes = file_to_bytes(file_path).unwrap();
-        let file_hash = hash_bytes(&file_as_bytes);
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[tokio::test]
+        async fn read_chunks_test() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("upload.zip");
+            let archive = (0..100u8).collect::<Vec<u8>>();
+            fs::write(&path, &archive).unwrap();
 
-        MerkleTree::verify(&file_hash, merkle_proof, root_leaf)
+            let template = FileUploadRequest {
+                jwt: "jwt".to_string(),
+                upload_id: "upload".to_string(),
+                ..Default::default()
+            };
+            let (tx, rx) = mpsc::channel(UPLOAD_QUEUE_LENGTH);
+            let reader = tokio::spawn(read_chunks(path, 10, 40, template, tx));
+            let requests = ReceiverStream::new(rx).collect::<Vec<_>>().await;
+            reader.await.unwrap().unwrap();
+
+            // A resumed upload starts at the offset the server has reached.
+            assert_eq!(
+                requests
+                    .iter()
+                    .map(|request| (request.offset, request.content.len()))
+                    .collect::<Vec<_>>(),
+                vec![(10, 40), (50, 40), (90, 10)]
+            );
+            assert!(requests
+                .iter()
+                .all(|request| request.jwt == "jwt" && request.upload_id == "upload"));
+
+            // Only the last chunk carries the digest, which covers the whole archive.
+            assert_eq!(
+                requests
+                    .iter()
+                    .map(|request| request.checksum.as_str())
+                    .collect::<Vec<_>>(),
+                vec!["", "", hash_bytes(&archive).as_str()]
+            );
+            assert_eq!(
+                requests
+                    .into_iter()
+                    .flat_map(|request| request.content)
+                    .collect::<Vec<u8>>(),
+                archive[10..]
+            );
+        }
     }
 }
@@ -0,0 +1,417 @@
+use common::syncx::{proof_request::Leaf, syncx_client::SyncxClient};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tonic::transport::Channel;
+
+use super::{
+    context::Context,
+    output::{with_progress, with_progress_sync, CommandError, Progress, ProgressHandler},
+    profile::Profiles,
+    service::client::{
+        self, AccountReport, AuditReport, DownloadReport, JobReport, ListReport, MerkleRootReport,
+        ProofReport, RestoreReport, RootReport, UploadReport,
+    },
+    sync::{self, SyncReport},
+    transport,
+    verify::{self, VerifyReport},
+    watch::{self, WatchReport},
+};
+
+/// Options of `SyncxSession::upload_dir`.
+#[derive(Clone, Debug, Default)]
+pub struct UploadOptions {
+    /// Wait for the server to process the batch and check its merkle root.
+    pub wait: bool,
+    /// Delete the uploaded files from the directory once the upload is committed.
+    pub move_files: bool,
+    /// Upload every file, even those unchanged since the directory's last batch.
+    pub full: bool,
+}
+
+/// Which files of a batch `SyncxSession::download` fetches.
+#[derive(Clone, Debug)]
+pub enum FileSelection {
+    Names(Vec<String>),
+    /// Every file whose name matches a glob pattern.
+    Glob(String),
+}
+
+/// Options of `SyncxSession::download`.
+#[derive(Clone, Debug)]
+pub struct DownloadOptions {
+    /// Batch to download from. Defaults to the latest batch holding the files.
+    pub batch: Option<String>,
+    /// Apply the uploaded modes and modification times to the downloaded files.
+    pub apply_metadata: bool,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            batch: None,
+            apply_metadata: true,
+        }
+    }
+}
+
+/// Options of `SyncxSession::restore`.
+#[derive(Clone, Debug)]
+pub struct RestoreOptions {
+    /// Apply the uploaded modes and modification times to the restored files.
+    pub apply_metadata: bool,
+    /// Replace files that already exist in the directory. They are kept otherwise.
+    pub overwrite: bool,
+}
+
+impl Default for RestoreOptions {
+    fn default() -> Self {
+        Self {
+            apply_metadata: true,
+            overwrite: false,
+        }
+    }
+}
+
+/// Opens a `SyncxSession`.
+#[derive(Default)]
+pub struct SessionBuilder {
+    profile: Option<String>,
+    server: Option<String>,
+    progress: Option<ProgressHandler>,
+}
+
+impl SessionBuilder {
+    /// Profile to use. Defaults to the one chosen with `profile use`.
+    pub fn profile(mut self, profile: &str) -> Self {
+        self.profile = Some(profile.to_string());
+        self
+    }
+
+    /// Address of the server. Defaults to the endpoint in the profile's config.
+    pub fn server(mut self, server: &str) -> Self {
+        self.server = Some(server.to_string());
+        self
+    }
+
+    /// Sends what the session is doing to `handler`. Progress is dropped otherwise.
+    pub fn on_progress(mut self, handler: impl Fn(&Progress) + Send + Sync + 'static) -> Self {
+        self.progress = Some(Arc::new(handler));
+        self
+    }
+
+    /// Loads the profile, creating it if it does not exist yet. The connection to
+    /// the server is only opened by the first request that needs it.
+    pub fn open(self) -> Result<SyncxSession, CommandError> {
+        let progress = self.progress.unwrap_or_else(|| Arc::new(|_| {}));
+
+        let context = with_progress_sync(progress.clone(), || {
+            let profiles = Profiles::open()?;
+            let profile = self.profile.unwrap_or_else(|| profiles.current());
+            profiles.load(&profile)
+        })?;
+        let syncx_client = transport::connect(&context.app_config, self.server.as_deref())?;
+
+        Ok(SyncxSession {
+            syncx_client,
+            context,
+            progress,
+            credentials_loaded: false,
+        })
+    }
+}
+
+/// A client profile and its connection to the syncx server. Every operation
+/// records what it did in the profile's config, as the CLI does, so sessions
+/// and CLI commands using the same profile can be mixed.
+pub struct SyncxSession {
+    syncx_client: SyncxClient<Channel>,
+    context: Context,
+    progress: ProgressHandler,
+    credentials_loaded: bool,
+}
+
+impl SyncxSession {
+    pub fn builder() -> SessionBuilder {
+        SessionBuilder::default()
+    }
+
+    /// The profile's config and paths.
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+
+    /// Reads the account's credentials on first use, so offline operations never
+    /// touch the credential store.
+    fn load_credentials(&mut self) -> Result<(), CommandError> {
+        if !self.credentials_loaded {
+            self.context.load_credentials()?;
+            self.credentials_loaded = true;
+        }
+
+        Ok(())
+    }
+
+    /// Creates an account on the server and stores it in the profile.
+    pub async fn register(&mut self, password: String) -> Result<AccountReport, CommandError> {
+        let progress = self.progress.clone();
+        with_progress(
+            progress,
+            client::register_client(&mut self.syncx_client, password, &mut self.context),
+        )
+        .await
+    }
+
+    /// Logs into account `id`, or the account the profile holds when `None`.
+    pub async fn login(
+        &mut self,
+        id: Option<&str>,
+        password: String,
+    ) -> Result<AccountReport, CommandError> {
+        self.load_credentials()?;
+        let progress = self.progress.clone();
+        with_progress(
+            progress,
+            client::login(&mut self.syncx_client, id, password, &mut self.context),
+        )
+        .await
+    }
+
+    /// Uploads the files of `directory` as a new batch.
+    pub async fn upload_dir(
+        &mut self,
+        directory: &str,
+        options: UploadOptions,
+    ) -> Result<UploadReport, CommandError> {
+        self.load_credentials()?;
+        let progress = self.progress.clone();
+        with_progress(
+            progress,
+            client::upload_files(
+                &mut self.syncx_client,
+                directory,
+                options.wait,
+                options.move_files,
+                options.full,
+                &mut self.context,
+            ),
+        )
+        .await
+    }
+
+    /// Downloads `files` into `directory`, keeping them only if they verify
+    /// against the batch's merkle root.
+    pub async fn download(
+        &mut self,
+        files: FileSelection,
+        directory: &Path,
+        options: DownloadOptions,
+    ) -> Result<DownloadReport, CommandError> {
+        self.load_credentials()?;
+        let progress = self.progress.clone();
+        let directory = directory.to_path_buf();
+        let batch = options.batch.as_deref();
+
+        let download = async {
+            match files {
+                FileSelection::Names(names) if names.len() == 1 => {
+                    client::download_file(
+                        &mut self.syncx_client,
+                        &names[0],
+                        batch,
+                        &directory,
+                        options.apply_metadata,
+                        &mut self.context,
+                    )
+                    .await
+                }
+                FileSelection::Names(names) => {
+                    client::download_files(
+                        &mut self.syncx_client,
+                        names,
+                        None,
+                        batch,
+                        &directory,
+                        options.apply_metadata,
+                        &mut self.context,
+                    )
+                    .await
+                }
+                FileSelection::Glob(pattern) => {
+                    client::download_files(
+                        &mut self.syncx_client,
+                        Vec::new(),
+                        Some(pattern),
+                        batch,
+                        &directory,
+                        options.apply_metadata,
+                        &mut self.context,
+                    )
+                    .await
+                }
+            }
+        };
+
+        with_progress(progress, download).await
+    }
+
+    /// Lists the files the server holds in `batch`, or in the latest batch.
+    pub async fn list(&mut self, batch: Option<&str>) -> Result<ListReport, CommandError> {
+        self.load_credentials()?;
+        let progress = self.progress.clone();
+        with_progress(
+            progress,
+            client::list_files(&mut self.syncx_client, batch, &self.context),
+        )
+        .await
+    }
+
+    /// Checks the files of `directory` against the merkle root recorded for
+    /// `batch`, or for the directory's latest batch. With `prove`, the server is
+    /// also asked to prove each recorded file hash.
+    pub async fn verify(
+        &mut self,
+        directory: &str,
+        batch: Option<&str>,
+        prove: bool,
+    ) -> Result<VerifyReport, CommandError> {
+        if prove {
+            self.load_credentials()?;
+        }
+        let progress = self.progress.clone();
+        with_progress(
+            progress,
+            verify::verify(
+                &mut self.syncx_client,
+                directory,
+                batch,
+                prove,
+                &mut self.context,
+            ),
+        )
+        .await
+    }
+
+    /// Syncs `directory` both ways with the server's latest batch.
+    pub async fn sync(
+        &mut self,
+        directory: &str,
+        apply_metadata: bool,
+    ) -> Result<SyncReport, CommandError> {
+        self.load_credentials()?;
+        let progress = self.progress.clone();
+        with_progress(
+            progress,
+            sync::sync(
+                &mut self.syncx_client,
+                directory,
+                apply_metadata,
+                &mut self.context,
+            ),
+        )
+        .await
+    }
+
+    /// Uploads `directory` whenever it changes, until the process gets Ctrl-C.
+    pub async fn watch(
+        &mut self,
+        directory: &str,
+        debounce: Duration,
+    ) -> Result<WatchReport, CommandError> {
+        self.load_credentials()?;
+        let progress = self.progress.clone();
+        with_progress(
+            progress,
+            watch::watch(
+                &mut self.syncx_client,
+                directory,
+                debounce,
+                &mut self.context,
+            ),
+        )
+        .await
+    }
+
+    /// Returns the processing status of `batch`, or of the latest batch.
+    pub async fn job_status(&mut self, batch: Option<&str>) -> Result<JobReport, CommandError> {
+        self.load_credentials()?;
+        let progress = self.progress.clone();
+        with_progress(
+            progress,
+            client::job_status(&mut self.syncx_client, batch, &mut self.context),
+        )
+        .await
+    }
+
+    /// Fetches and verifies the merkle proof of `leaf`.
+    pub async fn proof(
+        &mut self,
+        leaf: Leaf,
+        batch: Option<&str>,
+    ) -> Result<ProofReport, CommandError> {
+        self.load_credentials()?;
+        let progress = self.progress.clone();
+        with_progress(
+            progress,
+            client::get_proof(&mut self.syncx_client, leaf, batch, &mut self.context),
+        )
+        .await
+    }
+
+    /// Compares the server's merkle root of `batch` with the local record.
+    pub async fn server_root(&mut self, batch: Option<&str>) -> Result<RootReport, CommandError> {
+        self.load_credentials()?;
+        let progress = self.progress.clone();
+        with_progress(
+            progress,
+            client::server_root(&mut self.syncx_client, batch, &mut self.context),
+        )
+        .await
+    }
+
+    /// Challenges the server to prove it holds a `fraction` of the chunks of `batch`.
+    pub async fn audit(
+        &mut self,
+        batch: Option<&str>,
+        fraction: f64,
+    ) -> Result<AuditReport, CommandError> {
+        self.load_credentials()?;
+        let progress = self.progress.clone();
+        with_progress(
+            progress,
+            client::audit(&mut self.syncx_client, batch, fraction, &mut self.context),
+        )
+        .await
+    }
+
+    /// Restores every file of `batch`, or of the latest uploaded batch, into
+    /// `directory`, or into the directory it was uploaded from.
+    pub async fn restore(
+        &mut self,
+        batch: Option<&str>,
+        directory: Option<PathBuf>,
+        options: RestoreOptions,
+    ) -> Result<RestoreReport, CommandError> {
+        self.load_credentials()?;
+        let progress = self.progress.clone();
+        with_progress(
+            progress,
+            client::restore(
+                &mut self.syncx_client,
+                batch,
+                directory,
+                options.apply_metadata,
+                options.overwrite,
+                &mut self.context,
+            ),
+        )
+        .await
+    }
+
+    /// The merkle roots recorded for the uploaded batches. Works offline.
+    pub fn merkle_roots(&self) -> MerkleRootReport {
+        with_progress_sync(self.progress.clone(), || {
+            client::merkle_roots(&self.context)
+        })
+    }
+}
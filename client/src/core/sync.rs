@@ -0,0 +1,699 @@
+use common::{
+    common::{
+        file_to_bytes, join_relative_path, list_files_recursive, relative_path_name,
+        METADATA_MANIFEST,
+    },
+    syncx::{syncx_client::SyncxClient, FilesDownloadRequest, ListFilesRequest, RootRequest},
+};
+use merkle_tree::{merkle_tree::MerkleTree, utils::hash_bytes};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tonic::{transport::Channel, Code};
+
+use super::{
+    context::{AppConfig, BatchRecord, Context, SyncState},
+    manifest::{Manifest, ManifestEntry},
+    output::{progress, CommandError, ErrorKind, Report},
+    service::client,
+};
+
+/// Content hash of each file, by name relative to the synced directory.
+type FileHashes = BTreeMap<String, String>;
+
+/// A batch held by the server, with the lineage it continues.
+#[derive(Debug, Default)]
+struct RemoteBatch {
+    batch_id: String,
+    lineage: String,
+    files: FileHashes,
+}
+
+/// What a sync does, from comparing the local directory and the server's latest
+/// batch with the state both were in after the last sync.
+#[derive(Debug, Default, PartialEq)]
+struct SyncPlan {
+    /// Files changed only on the server, to download.
+    pull: Vec<String>,
+    /// Files deleted only on the server, to delete locally.
+    delete: Vec<String>,
+    /// Files changed on both sides. The server's copy is saved next to the local one.
+    conflicts: Vec<String>,
+    /// Whether anything changed locally, so a new batch has to be uploaded.
+    push: bool,
+}
+
+impl SyncPlan {
+    fn new(local: &FileHashes, remote: &FileHashes, synced: &FileHashes) -> Self {
+        let names = local
+            .keys()
+            .chain(remote.keys())
+            .chain(synced.keys())
+            .collect::<BTreeSet<&String>>();
+
+        let mut plan = SyncPlan::default();
+        for name in names {
+            let (local, remote, synced) = (local.get(name), remote.get(name), synced.get(name));
+
+            if local == remote {
+                continue;
+            }
+
+            if local == synced {
+                match remote {
+                    Some(_) => plan.pull.push(name.to_string()),
+                    None => plan.delete.push(name.to_string()),
+                }
+            } else if remote == synced {
+                plan.push = true;
+            } else if local.is_none() {
+                // Deleted here but changed on the server: the change wins.
+                plan.pull.push(name.to_string());
+            } else if remote.is_none() {
+                // Changed here but deleted on the server: the change wins.
+                plan.push = true;
+            } else {
+                plan.conflicts.push(name.to_string());
+                plan.push = true;
+            }
+        }
+
+        plan
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pull.is_empty() && self.delete.is_empty() && !self.push
+    }
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct SyncReport {
+    pub directory: PathBuf,
+    /// The batch the directory was synced with.
+    pub batch_id: String,
+    pub downloaded: Vec<String>,
+    pub deleted: Vec<String>,
+    /// Files changed on both sides, with the name the server's copy was saved as.
+    pub conflicts: BTreeMap<String, String>,
+    /// The batch local changes were uploaded as, if there were any.
+    pub uploaded_batch_id: Option<String>,
+    pub local_root: String,
+    pub server_root: String,
+    pub root_matches: bool,
+    /// Whether the batch's recorded root was reported by the server rather than
+    /// computed when it was uploaded from this profile. Matching such a root shows
+    /// the directory agrees with the server, not that the server kept the files intact.
+    pub server_reported_root: bool,
+}
+
+impl Report for SyncReport {
+    fn failure(&self) -> Option<CommandError> {
+        match self.root_matches || self.batch_id.is_empty() {
+            true => None,
+            false => Some(CommandError::verification(format!(
+                "{} does not match batch {}",
+                self.directory.display(),
+                self.batch_id
+            ))),
+        }
+    }
+}
+
+/// Syncs `directory` both ways with the latest batch of its lineage: changes made
+/// on the server since the last sync are downloaded, local changes are uploaded
+/// as a new batch, and files changed on both sides are kept side by side. The
+/// directory's merkle root is then checked against the server's.
+///
+/// Each directory follows its own lineage, the line of batches uploaded from it
+/// by any machine, so batches of other directories on the account are never
+/// pulled into it. A directory synced for the first time takes up the account's
+/// latest batch, unless that batch is known to come from another directory.
+///
+/// Downloads are verified against the root recorded for the batch. A batch this
+/// profile uploaded has a root computed from its own files, so a server that
+/// altered them is caught. A batch uploaded by another client only has the root
+/// the server reports for it, which a dishonest server can forge along with the
+/// files, so such batches are recorded and reported as server-reported.
+pub async fn sync(
+    syncx_client: &mut SyncxClient<Channel>,
+    directory: &str,
+    apply_metadata: bool,
+    context: &mut Context,
+) -> Result<SyncReport, CommandError> {
+    let path = fs::canonicalize(directory)
+        .map_err(|e| CommandError::local(format!("{}: {}", directory, e)))?;
+
+    let local = local_files(&path, context)?;
+    let state = context.app_config.sync_state(&path).cloned();
+    let lineage = context.app_config.directory_lineage(&path);
+
+    let remote = match &lineage {
+        Some(lineage) => match remote_files(syncx_client, "", lineage, context).await? {
+            Some(remote) => Some(remote),
+            // The lineage was started before lineages were recorded, so only the
+            // batch of the last sync is known to belong to it.
+            None => match &state {
+                Some(state) => remote_files(syncx_client, &state.batch_id, "", context).await?,
+                None => None,
+            },
+        },
+        None => remote_files(syncx_client, "", "", context)
+            .await?
+            .filter(|remote| !uploaded_elsewhere(&context.app_config, &path, remote)),
+    };
+    let synced = state
+        .as_ref()
+        .map(|state| state.files.clone())
+        .unwrap_or_default();
+
+    let RemoteBatch {
+        batch_id,
+        lineage: remote_lineage,
+        files: remote,
+    } = remote.unwrap_or_default();
+    let mut plan = SyncPlan::new(&local, &remote, &synced);
+
+    // Files missing from a batch only count as deleted if the batch continues
+    // the ones the directory was synced with.
+    if !plan.delete.is_empty() && !continues_sync(state.as_ref(), &batch_id, &remote_lineage) {
+        progress!(
+            "Batch {} does not continue the last sync of {}. Nothing is deleted",
+            batch_id,
+            path.display()
+        );
+        plan.delete.clear();
+    }
+    progress!(
+        "{} to download, {} to delete, {} conflicts, local changes to upload <{}>",
+        plan.pull.len(),
+        plan.delete.len(),
+        plan.conflicts.len(),
+        plan.push
+    );
+
+    let mut report = SyncReport {
+        directory: path.clone(),
+        deleted: plan.delete.clone(),
+        conflicts: plan
+            .conflicts
+            .iter()
+            .map(|name| (name.to_string(), conflict_name(name, &batch_id)))
+            .collect(),
+        ..Default::default()
+    };
+
+    if !batch_id.is_empty() {
+        record_batch(syncx_client, &batch_id, &remote_lineage, &path, context).await?;
+        pull(
+            syncx_client,
+            &batch_id,
+            &remote,
+            &plan,
+            &path,
+            apply_metadata,
+            context,
+        )
+        .await?;
+        report.downloaded = plan.pull.clone();
+
+        // A batch uploaded before lineages were recorded starts one of its own.
+        let lineage = match remote_lineage.is_empty() {
+            true => lineage.unwrap_or_else(|| batch_id.to_string()),
+            false => remote_lineage,
+        };
+
+        // Local changes not uploaded yet still differ from this state, so an
+        // interrupted sync picks them up next time.
+        context.app_config.set_sync_state(SyncState {
+            directory: path.clone(),
+            batch_id: batch_id.to_string(),
+            files: remote,
+            lineage,
+        });
+        context.app_config.write(&context.path)?;
+    }
+
+    report.batch_id = batch_id;
+    if plan.push {
+        let directory = path.to_string_lossy().to_string();
+        let upload =
+            client::upload_files(syncx_client, &directory, true, false, false, context).await?;
+        if let Some(failure) = upload.failure() {
+            return Err(failure);
+        }
+
+        if let Some(pushed) = upload.batch_id {
+            let pushed = remote_files(syncx_client, &pushed, "", context)
+                .await?
+                .ok_or_else(|| {
+                    CommandError::new(ErrorKind::Server, format!("Batch {} not found", pushed))
+                })?;
+            let lineage = context
+                .app_config
+                .batch(&pushed.batch_id)
+                .map_or(pushed.lineage, |batch| batch.lineage.to_string());
+            context.app_config.set_sync_state(SyncState {
+                directory: path.clone(),
+                batch_id: pushed.batch_id.to_string(),
+                files: pushed.files,
+                lineage,
+            });
+            context.app_config.write(&context.path)?;
+
+            report.batch_id = pushed.batch_id.to_string();
+            report.uploaded_batch_id = Some(pushed.batch_id);
+        }
+    }
+
+    if report.batch_id.is_empty() {
+        progress!("Nothing to sync");
+        return Ok(report);
+    }
+
+    report.server_reported_root = context
+        .app_config
+        .batch(&report.batch_id)
+        .is_some_and(|batch| batch.server_reported);
+    if report.server_reported_root {
+        progress!(
+            "The merkle root of batch {} was reported by the server, not recorded on upload",
+            report.batch_id
+        );
+    }
+
+    report.local_root = local_root(&path, context)?;
+    report.server_root = server_root(syncx_client, &report.batch_id, context).await?;
+    report.root_matches = report.local_root == report.server_root;
+    progress!(
+        "Local merkle root <{}> matches server merkle root of batch {} <{}>",
+        report.local_root,
+        report.batch_id,
+        report.root_matches
+    );
+
+    if plan.is_empty() {
+        progress!(
+            "{} is up to date with batch {}",
+            path.display(),
+            report.batch_id
+        );
+    }
+
+    Ok(report)
+}
+
+/// Hashes the files under `dir`, reusing the manifest's hash of any file whose
+/// size and modification time haven't changed.
+fn local_files(dir: &Path, context: &Context) -> Result<FileHashes, CommandError> {
+    let manifest = Manifest::read(&context.manifest_path());
+
+    let mut files = FileHashes::new();
+    for path in list_files_recursive(dir).map_err(|e| CommandError::local(e.to_string()))? {
+        if path == dir.join(METADATA_MANIFEST) {
+            continue;
+        }
+
+        let entry = ManifestEntry::new(dir, &path, "", manifest.get(&path))?;
+        files.insert(entry.name, entry.content_hash);
+    }
+
+    Ok(files)
+}
+
+/// Lists the files of batch `batch_id`. When it is empty, lists the latest batch
+/// of `lineage`, or of the account when both are empty. Returns `None` if there
+/// is no such batch.
+async fn remote_files(
+    syncx_client: &mut SyncxClient<Channel>,
+    batch_id: &str,
+    lineage: &str,
+    context: &Context,
+) -> Result<Option<RemoteBatch>, CommandError> {
+    let request = ListFilesRequest {
+        jwt: context.app_config.jwt.to_string(),
+        batch_id: batch_id.to_string(),
+        lineage: lineage.to_string(),
+    };
+
+    match syncx_client.list_files(request).await {
+        Ok(response) => {
+            let response = response.into_inner();
+            let files = response
+                .files
+                .into_iter()
+                .map(|file| (file.file_name, file.content_hash))
+                .collect::<FileHashes>();
+
+            Ok(Some(RemoteBatch {
+                batch_id: response.batch_id,
+                lineage: response.lineage,
+                files,
+            }))
+        }
+        Err(e) if e.code() == Code::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Whether `remote` is known to have been uploaded from, or synced with, another
+/// directory than `dir`, so a first sync of `dir` must not take it up.
+fn uploaded_elsewhere(app_config: &AppConfig, dir: &Path, remote: &RemoteBatch) -> bool {
+    let in_lineage = |batch_id: &str, lineage: &str| {
+        batch_id == remote.batch_id || (!lineage.is_empty() && lineage == remote.lineage)
+    };
+
+    let batches = app_config.batches.iter().any(|batch| {
+        in_lineage(&batch.id, &batch.lineage)
+            && batch.directory.as_deref().is_some_and(|other| other != dir)
+    });
+    let synced = app_config
+        .synced
+        .iter()
+        .any(|state| in_lineage(&state.batch_id, &state.lineage) && state.directory != dir);
+
+    batches || synced
+}
+
+/// Whether batch `batch_id` of lineage `lineage` continues the batch `state`
+/// recorded, so that files it lacks were deleted since the last sync.
+fn continues_sync(state: Option<&SyncState>, batch_id: &str, lineage: &str) -> bool {
+    state.is_some_and(|state| {
+        state.batch_id == batch_id || (!lineage.is_empty() && state.lineage == lineage)
+    })
+}
+
+/// Records batch `batch_id` as uploaded from `dir` if it was uploaded elsewhere,
+/// so its files can be checked and the next upload can build on it. Its root can
+/// only come from the server, so it is marked as server-reported.
+async fn record_batch(
+    syncx_client: &mut SyncxClient<Channel>,
+    batch_id: &str,
+    lineage: &str,
+    dir: &Path,
+    context: &mut Context,
+) -> Result<(), CommandError> {
+    if context.app_config.batch(batch_id).is_some() {
+        return Ok(());
+    }
+
+    let merkle_root = server_root(syncx_client, batch_id, context).await?;
+    context.app_config.add_batch(BatchRecord {
+        id: batch_id.to_string(),
+        merkle_root,
+        files: Vec::new(),
+        directory: Some(dir.to_path_buf()),
+        server_reported: true,
+        lineage: lineage.to_string(),
+    });
+
+    Ok(())
+}
+
+async fn server_root(
+    syncx_client: &mut SyncxClient<Channel>,
+    batch_id: &str,
+    context: &Context,
+) -> Result<String, CommandError> {
+    let request = RootRequest {
+        jwt: context.app_config.jwt.to_string(),
+        batch_id: batch_id.to_string(),
+    };
+
+    Ok(syncx_client
+        .get_root(request)
+        .await?
+        .into_inner()
+        .merkle_root)
+}
+
+/// Applies the server's side of `plan` to `dir`: downloads changed files, saving
+/// conflicting ones next to the local copy, and deletes the files removed from
+/// batch `batch_id`. Nothing in `dir` changes unless every download checks out.
+async fn pull(
+    syncx_client: &mut SyncxClient<Channel>,
+    batch_id: &str,
+    remote: &FileHashes,
+    plan: &SyncPlan,
+    dir: &Path,
+    apply_metadata: bool,
+    context: &mut Context,
+) -> Result<(), CommandError> {
+    let file_names = plan
+        .pull
+        .iter()
+        .chain(plan.conflicts.iter())
+        .cloned()
+        .collect::<Vec<String>>();
+
+    let mut pulled = Vec::new();
+    if !file_names.is_empty() {
+        let staging_dir = tempfile::tempdir()?;
+
+        let request = FilesDownloadRequest {
+            jwt: context.app_config.jwt.to_string(),
+            file_names,
+            pattern: String::new(),
+            batch_id: batch_id.to_string(),
+        };
+
+        let mut part_files = Vec::new();
+        let (merkle_proof, _) =
+            client::receive_files(syncx_client, request, staging_dir.path(), &mut part_files)
+                .await?;
+
+        let mut leaves = Vec::with_capacity(part_files.len());
+        for (_, part_path, _) in &part_files {
+            let content = file_to_bytes(part_path).map_err(|e| {
+                CommandError::local(format!("Failed to read {:?}: {}", part_path, e))
+            })?;
+            leaves.push(hash_bytes(&content));
+        }
+        let merkle_root = client::recorded_root(context, batch_id)?;
+        let (valid, _) = MerkleTree::verify_multiproof(&leaves, &merkle_proof, merkle_root);
+        if !valid {
+            return Err(CommandError::verification(format!(
+                "Files downloaded from batch {} failed verification",
+                batch_id
+            )));
+        }
+
+        for ((output_path, part_path, metadata), leaf) in part_files.into_iter().zip(leaves) {
+            let name = relative_path_name(staging_dir.path(), &output_path)
+                .filter(|name| remote.get(name) == Some(&leaf))
+                .ok_or_else(|| {
+                    CommandError::verification(format!("Unexpected file {:?}", output_path))
+                })?;
+
+            let target_name = match plan.conflicts.contains(&name) {
+                true => conflict_name(&name, batch_id),
+                false => name,
+            };
+            let target = join_relative_path(dir, &target_name)
+                .ok_or_else(|| CommandError::local(target_name.to_string()))?;
+
+            pulled.push((part_path, target, metadata));
+        }
+    }
+
+    for (part_path, target, metadata) in &pulled {
+        move_file(part_path, target)?;
+        client::restore_metadata(target, metadata.as_ref().filter(|_| apply_metadata));
+        progress!("File {:?} downloaded", target);
+    }
+
+    for name in &plan.delete {
+        if let Some(path) = join_relative_path(dir, name) {
+            let _ = fs::remove_file(&path);
+            progress!("File {:?} deleted", path);
+        }
+    }
+
+    // Downloaded files are recorded so the next sync doesn't hash them again.
+    let manifest_path = context.manifest_path();
+    let mut manifest = Manifest::read(&manifest_path);
+    manifest.record(
+        pulled
+            .iter()
+            .filter_map(|(_, target, _)| ManifestEntry::new(dir, target, batch_id, None).ok()),
+    );
+    manifest.write(&manifest_path)?;
+
+    Ok(())
+}
+
+/// Merkle root of the files in `dir`.
+fn local_root(dir: &Path, context: &Context) -> Result<String, CommandError> {
+    let leaves = local_files(dir, context)?.into_values().collect();
+    Ok(MerkleTree::from_leaf_hashes(leaves).root().to_string())
+}
+
+/// Name under which the server's copy of a conflicting file is saved, e.g.
+/// `notes.conflict-1a2b3c4d.txt` for `notes.txt`.
+fn conflict_name(file_name: &str, batch_id: &str) -> String {
+    let path = Path::new(file_name);
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let tag = batch_id.chars().take(8).collect::<String>();
+
+    let mut name = format!("{}.conflict-{}", stem, tag);
+    if let Some(extension) = path.extension() {
+        name.push('.');
+        name.push_str(&extension.to_string_lossy());
+    }
+
+    path.with_file_name(name).to_string_lossy().to_string()
+}
+
+/// Moves `from` to `to`, copying it when they are on different filesystems.
+fn move_file(from: &Path, to: &PathBuf) -> std::io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    fs::rename(from, to).or_else(|_| {
+        fs::copy(from, to)?;
+        fs::remove_file(from)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hashes(files: &[(&str, &str)]) -> FileHashes {
+        files
+            .iter()
+            .map(|(name, hash)| (name.to_string(), hash.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn sync_plan_test() {
+        let synced = hashes(&[
+            ("same.txt", "a"),
+            ("local.txt", "a"),
+            ("remote.txt", "a"),
+            ("both.txt", "a"),
+            ("removed_remotely.txt", "a"),
+            ("removed_locally.txt", "a"),
+        ]);
+        let local = hashes(&[
+            ("same.txt", "a"),
+            ("local.txt", "b"),
+            ("remote.txt", "a"),
+            ("both.txt", "b"),
+            ("removed_remotely.txt", "a"),
+            ("new_local.txt", "a"),
+        ]);
+        let remote = hashes(&[
+            ("same.txt", "a"),
+            ("local.txt", "a"),
+            ("remote.txt", "b"),
+            ("both.txt", "c"),
+            ("removed_locally.txt", "a"),
+            ("new_remote.txt", "a"),
+        ]);
+
+        assert_eq!(
+            SyncPlan::new(&local, &remote, &synced),
+            SyncPlan {
+                pull: vec!["new_remote.txt".to_string(), "remote.txt".to_string()],
+                delete: vec!["removed_remotely.txt".to_string()],
+                conflicts: vec!["both.txt".to_string()],
+                push: true,
+            }
+        );
+
+        // Nothing has changed on either side.
+        assert!(SyncPlan::new(&local, &local, &local).is_empty());
+    }
+
+    fn batch(id: &str, lineage: &str, directory: &str) -> BatchRecord {
+        BatchRecord {
+            id: id.to_string(),
+            merkle_root: String::new(),
+            files: Vec::new(),
+            directory: Some(PathBuf::from(directory)),
+            server_reported: false,
+            lineage: lineage.to_string(),
+        }
+    }
+
+    fn remote(batch_id: &str, lineage: &str) -> RemoteBatch {
+        RemoteBatch {
+            batch_id: batch_id.to_string(),
+            lineage: lineage.to_string(),
+            files: FileHashes::new(),
+        }
+    }
+
+    #[test]
+    fn two_directories_test() {
+        let docs = Path::new("/docs");
+        let photos = Path::new("/photos");
+
+        // `/docs` was synced, then `/photos` was uploaded from the same account.
+        let mut app_config = AppConfig::default();
+        app_config.add_batch(batch("docs-1", "docs-1", "/docs"));
+        app_config.set_sync_state(SyncState {
+            directory: docs.to_path_buf(),
+            batch_id: "docs-1".to_string(),
+            files: hashes(&[("notes.txt", "a")]),
+            lineage: "docs-1".to_string(),
+        });
+        app_config.add_batch(batch("photos-1", "photos-1", "/photos"));
+
+        // Each directory follows its own lineage, whatever the account's latest batch.
+        assert_eq!(
+            app_config.directory_lineage(docs).as_deref(),
+            Some("docs-1")
+        );
+        assert_eq!(
+            app_config.directory_lineage(photos).as_deref(),
+            Some("photos-1")
+        );
+        assert_eq!(app_config.directory_lineage(Path::new("/music")), None);
+
+        // A batch of `/photos` never continues the sync of `/docs`, so nothing of
+        // `/docs` is deleted for being missing from it.
+        let state = app_config.sync_state(docs);
+        assert!(!continues_sync(state, "photos-1", "photos-1"));
+        assert!(continues_sync(state, "docs-2", "docs-1"));
+        assert!(continues_sync(state, "docs-1", ""));
+
+        // Nor is it taken up by a directory synced for the first time.
+        let music = Path::new("/music");
+        assert!(uploaded_elsewhere(
+            &app_config,
+            music,
+            &remote("photos-1", "photos-1")
+        ));
+        assert!(uploaded_elsewhere(
+            &app_config,
+            music,
+            &remote("docs-2", "docs-1")
+        ));
+        assert!(!uploaded_elsewhere(
+            &app_config,
+            music,
+            &remote("other", "other")
+        ));
+        assert!(!uploaded_elsewhere(
+            &app_config,
+            docs,
+            &remote("docs-2", "docs-1")
+        ));
+    }
+
+    #[test]
+    fn conflict_name_test() {
+        assert_eq!(
+            conflict_name("docs/notes.txt", "1a2b3c4d5e6f"),
+            "docs/notes.conflict-1a2b3c4d.txt"
+        );
+        assert_eq!(conflict_name("Makefile", "1a2b"), "Makefile.conflict-1a2b");
+    }
+}
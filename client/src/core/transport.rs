@@ -0,0 +1,112 @@
+use common::syncx::syncx_client::SyncxClient;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
+
+use super::{
+    context::{AppConfig, ConnectionSettings, TlsSettings},
+    errors::SynxClientError,
+};
+
+/// Creates a client for the server at `server`, or at the endpoint in the config
+/// when `None`, over TLS if the config names a CA bundle.
+///
+/// The connection is only opened by the first request, so commands that never
+/// reach the server work offline.
+pub fn connect(
+    app_config: &AppConfig,
+    server: Option<&str>,
+) -> Result<SyncxClient<Channel>, Box<dyn std::error::Error>> {
+    let settings = &app_config.connection;
+    let server = server.unwrap_or(&settings.endpoint);
+    let tls_config = tls_config(&app_config.tls)?;
+
+    let mut endpoint = Endpoint::from_shared(endpoint_uri(server, tls_config.is_some()))?;
+    if let Some(tls_config) = tls_config {
+        endpoint = endpoint.tls_config(tls_config)?;
+    }
+
+    let channel = configure_endpoint(endpoint, settings).connect_lazy();
+
+    Ok(SyncxClient::new(channel)
+        .max_decoding_message_size(settings.max_message_size)
+        .max_encoding_message_size(settings.max_message_size))
+}
+
+/// Adds the scheme to an endpoint given without one.
+fn endpoint_uri(server: &str, tls: bool) -> String {
+    if server.contains("://") {
+        server.to_string()
+    } else if tls {
+        format!("https://{}", server)
+    } else {
+        format!("http://{}", server)
+    }
+}
+
+fn configure_endpoint(mut endpoint: Endpoint, settings: &ConnectionSettings) -> Endpoint {
+    endpoint = endpoint.connect_timeout(Duration::from_secs(settings.connect_timeout_secs));
+
+    if let Some(timeout) = settings.request_timeout_secs {
+        endpoint = endpoint.timeout(Duration::from_secs(timeout));
+    }
+
+    if let Some(interval) = settings.keepalive_interval_secs {
+        endpoint = endpoint
+            .http2_keep_alive_interval(Duration::from_secs(interval))
+            .keep_alive_timeout(Duration::from_secs(settings.keepalive_timeout_secs))
+            .keep_alive_while_idle(true);
+    }
+
+    endpoint
+}
+
+/// Builds the client TLS config from `settings`, or returns `None` when TLS is
+/// not configured.
+pub fn tls_config(settings: &TlsSettings) -> Result<Option<ClientTlsConfig>, SynxClientError> {
+    let ca_cert = match &settings.ca_cert {
+        Some(ca_cert) => ca_cert,
+        None => return Ok(None),
+    };
+
+    let mut tls_config =
+        ClientTlsConfig::new().ca_certificate(Certificate::from_pem(read_pem(ca_cert)?));
+
+    match (&settings.client_cert, &settings.client_key) {
+        (Some(cert), Some(key)) => {
+            tls_config = tls_config.identity(Identity::from_pem(read_pem(cert)?, read_pem(key)?));
+        }
+        (None, None) => {}
+        _ => return Err(SynxClientError::IncompleteClientIdentity),
+    }
+
+    if let Some(domain_name) = &settings.domain_name {
+        tls_config = tls_config.domain_name(domain_name);
+    }
+
+    Ok(Some(tls_config))
+}
+
+fn read_pem(path: &Path) -> Result<Vec<u8>, SynxClientError> {
+    fs::read(path)
+        .map_err(|e| SynxClientError::TlsCertificateReadError(format!("{:?}: {}", path, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn endpoint_uri_test() {
+        assert_eq!(endpoint_uri("[::1]:10000", false), "http://[::1]:10000");
+        assert_eq!(
+            endpoint_uri("syncx.example.com:443", true),
+            "https://syncx.example.com:443"
+        );
+        assert_eq!(
+            endpoint_uri("http://10.0.0.1:10000", true),
+            "http://10.0.0.1:10000"
+        );
+    }
+}
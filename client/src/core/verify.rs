@@ -0,0 +1,400 @@
+use common::{
+    common::{
+        file_to_bytes, generate_merkle_tree, list_files_recursive, relative_path_name,
+        METADATA_MANIFEST,
+    },
+    syncx::{proof_request::Leaf, syncx_client::SyncxClient, ProofRequest},
+};
+use merkle_tree::{merkle_tree::MerkleTree, utils::hash_bytes};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tonic::{transport::Channel, Code};
+
+use super::{
+    context::{BatchRecord, Context},
+    manifest::Manifest,
+    output::{progress, CommandError, Report},
+    service::client,
+};
+
+/// How the files of a directory differ from the files of a batch.
+#[derive(Serialize, Debug, Default, PartialEq)]
+struct FileDifferences {
+    /// Uploaded in the batch, but no longer in the directory.
+    missing: Vec<String>,
+    /// In the directory, but not part of the batch.
+    extra: Vec<String>,
+    /// In both, with different content.
+    modified: Vec<String>,
+}
+
+impl FileDifferences {
+    /// Compares the content hashes of local files with the hashes recorded for a
+    /// batch, both by file name.
+    fn new(local: &BTreeMap<String, String>, recorded: &BTreeMap<String, String>) -> Self {
+        let mut differences = FileDifferences::default();
+
+        for (name, hash) in recorded {
+            match local.get(name) {
+                None => differences.missing.push(name.to_string()),
+                Some(local_hash) if local_hash != hash => {
+                    differences.modified.push(name.to_string())
+                }
+                Some(_) => {}
+            }
+        }
+
+        differences.extra = local
+            .keys()
+            .filter(|name| !recorded.contains_key(*name))
+            .cloned()
+            .collect();
+
+        differences
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct VerifyReport {
+    pub batch_id: String,
+    pub local_root: String,
+    pub recorded_root: String,
+    pub matches: bool,
+    /// Only listed when the roots differ and the manifest holds the batch's hashes.
+    #[serde(flatten)]
+    differences: FileDifferences,
+    /// Recorded hashes the server was asked to prove, when proofs were requested.
+    pub proven: Option<usize>,
+    pub unproven: Vec<String>,
+}
+
+impl Report for VerifyReport {
+    fn failure(&self) -> Option<CommandError> {
+        if !self.matches {
+            return Some(CommandError::verification(format!(
+                "Local files do not match batch {}",
+                self.batch_id
+            )));
+        }
+
+        match self.unproven.is_empty() {
+            true => None,
+            false => Some(CommandError::verification(format!(
+                "{} recorded file hashes of batch {} could not be proven",
+                self.unproven.len(),
+                self.batch_id
+            ))),
+        }
+    }
+}
+
+/// Rebuilds the merkle tree of the files in `directory` and compares its root with
+/// the one recorded for batch `batch_id`, or for the latest batch uploaded from the
+/// directory. When they differ, the files are compared with the hashes recorded for
+/// the batch to report which are missing, extra or modified. With `prove`, the
+/// server is also asked to prove each recorded hash is a leaf of the recorded root.
+pub async fn verify(
+    syncx_client: &mut SyncxClient<Channel>,
+    directory: &str,
+    batch_id: Option<&str>,
+    prove: bool,
+    context: &mut Context,
+) -> Result<VerifyReport, CommandError> {
+    let path = fs::canonicalize(directory).map_err(|e| {
+        CommandError::local(format!("Failed to resolve directory {}: {}", directory, e))
+    })?;
+
+    let batch = match batch_id {
+        Some(batch_id) => context.app_config.batch(batch_id),
+        None => context
+            .app_config
+            .batches
+            .iter()
+            .rev()
+            .find(|batch| batch.directory.as_deref() == Some(path.as_path())),
+    };
+    let batch = batch.cloned().ok_or_else(|| {
+        CommandError::local(format!("No uploaded batch recorded for {}", path.display()))
+    })?;
+
+    let recorded = recorded_hashes(&path, &batch, context);
+    let mut report = compare(&path, &batch, &recorded)?;
+
+    if prove && !recorded.is_empty() {
+        report.unproven = prove_hashes(
+            syncx_client,
+            &batch.id,
+            &batch.merkle_root,
+            &recorded,
+            context,
+        )
+        .await?;
+        report.proven = Some(recorded.len() - report.unproven.len());
+    }
+
+    Ok(report)
+}
+
+/// The content hash of each file of `batch`, by name. Batch records keep them
+/// since files are recorded per batch. Older records fall back to the manifest,
+/// which only holds the latest upload of each file, so it is only used for the
+/// latest batch uploaded from `dir`.
+fn recorded_hashes(dir: &Path, batch: &BatchRecord, context: &Context) -> BTreeMap<String, String> {
+    if !batch.files.is_empty() && batch.files.iter().all(|file| !file.content_hash.is_empty()) {
+        return batch
+            .files
+            .iter()
+            .map(|file| (file.name.to_string(), file.content_hash.to_string()))
+            .collect();
+    }
+
+    let latest = context
+        .app_config
+        .batches
+        .iter()
+        .rev()
+        .find(|batch| batch.directory.as_deref() == Some(dir));
+    if latest.map(|latest| &latest.id) != Some(&batch.id) {
+        return BTreeMap::new();
+    }
+
+    Manifest::read(&context.manifest_path())
+        .files
+        .values()
+        .filter(|entry| entry.batch_id == batch.id && entry.path.starts_with(dir))
+        .map(|entry| (entry.name.to_string(), entry.content_hash.to_string()))
+        .collect()
+}
+
+/// Compares the files in `dir` with `batch`, by merkle root and, when the roots
+/// differ, by the `recorded` hash of each file. An empty directory matches no batch.
+fn compare(
+    dir: &Path,
+    batch: &BatchRecord,
+    recorded: &BTreeMap<String, String>,
+) -> Result<VerifyReport, CommandError> {
+    let files = list_files_recursive(dir)
+        .map_err(CommandError::local)?
+        .into_iter()
+        .filter(|file| *file != dir.join(METADATA_MANIFEST))
+        .collect::<Vec<PathBuf>>();
+
+    let local_root = match files.is_empty() {
+        true => String::new(),
+        false => generate_merkle_tree(&files)
+            .map_err(CommandError::local)?
+            .root()
+            .to_string(),
+    };
+    progress!("Local merkle root: <{}>", local_root);
+    progress!(
+        "Merkle root recorded for batch {}: <{}>",
+        batch.id,
+        batch.merkle_root
+    );
+
+    let matches = local_root == batch.merkle_root;
+    progress!("Local files match batch {} <{}>", batch.id, matches);
+
+    let mut report = VerifyReport {
+        batch_id: batch.id.to_string(),
+        local_root,
+        recorded_root: batch.merkle_root.to_string(),
+        matches,
+        differences: FileDifferences::default(),
+        proven: None,
+        unproven: Vec::new(),
+    };
+
+    if recorded.is_empty() {
+        progress!("No file hashes recorded for batch {}", batch.id);
+        return Ok(report);
+    }
+
+    if !matches {
+        report.differences = FileDifferences::new(&local_hashes(dir, &files), recorded);
+        for name in &report.differences.missing {
+            progress!("Missing: {}", name);
+        }
+        for name in &report.differences.extra {
+            progress!("Extra: {}", name);
+        }
+        for name in &report.differences.modified {
+            progress!("Modified: {}", name);
+        }
+    }
+
+    Ok(report)
+}
+
+fn local_hashes(base_dir: &Path, files: &[PathBuf]) -> BTreeMap<String, String> {
+    files
+        .iter()
+        .filter_map(|file| {
+            let name = relative_path_name(base_dir, file)?;
+            let content = file_to_bytes(file).ok()?;
+            Some((name, hash_bytes(&content)))
+        })
+        .collect()
+}
+
+/// Asks the server for a proof of each of the `recorded` hashes of batch `batch_id`
+/// and checks them against `merkle_root`. Returns the files that can't be proven.
+async fn prove_hashes(
+    syncx_client: &mut SyncxClient<Channel>,
+    batch_id: &str,
+    merkle_root: &str,
+    recorded: &BTreeMap<String, String>,
+    context: &Context,
+) -> Result<Vec<String>, CommandError> {
+    progress!(
+        "Proving {} recorded file hashes against batch {}...",
+        recorded.len(),
+        batch_id
+    );
+
+    let mut unproven = Vec::new();
+    for (name, hash) in recorded {
+        let request = ProofRequest {
+            jwt: context.app_config.jwt.to_string(),
+            leaf: Some(Leaf::ContentHash(hash.to_string())),
+            batch_id: batch_id.to_string(),
+        };
+
+        let valid = match syncx_client.get_proof(request).await {
+            Ok(response) => {
+                let response = response.into_inner();
+                let nodes = response
+                    .merkle_proof
+                    .map(|merkle_proof| merkle_proof.nodes)
+                    .unwrap_or_default();
+
+                response.leaf == *hash
+                    && MerkleTree::verify(hash, client::proof_nodes(&nodes), merkle_root).0
+            }
+            // The server doesn't hold the hash in the batch.
+            Err(e) if e.code() == Code::NotFound => false,
+            Err(e) => return Err(e.into()),
+        };
+
+        if !valid {
+            progress!("Unproven: {}", name);
+            unproven.push(name.to_string());
+        }
+    }
+
+    progress!(
+        "{} of {} recorded file hashes proven",
+        recorded.len() - unproven.len(),
+        recorded.len()
+    );
+
+    Ok(unproven)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{context::FileRecord, output::ErrorKind};
+
+    fn batch_record(id: &str, merkle_root: &str, files: &[(&str, &[u8])]) -> BatchRecord {
+        BatchRecord {
+            id: id.to_string(),
+            merkle_root: merkle_root.to_string(),
+            files: files
+                .iter()
+                .map(|(name, content)| FileRecord {
+                    name: name.to_string(),
+                    chunk_root: String::new(),
+                    chunk_count: 1,
+                    content_hash: hash_bytes(content),
+                })
+                .collect(),
+            directory: None,
+            server_reported: false,
+            lineage: String::new(),
+        }
+    }
+
+    fn hashes(batch: &BatchRecord) -> BTreeMap<String, String> {
+        batch
+            .files
+            .iter()
+            .map(|file| (file.name.to_string(), file.content_hash.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn older_batch_test() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), b"first").unwrap();
+        fs::write(dir.path().join("b.txt"), b"b").unwrap();
+        let files = vec![dir.path().join("a.txt"), dir.path().join("b.txt")];
+        let first_root = generate_merkle_tree(&files).unwrap().root().to_string();
+
+        // The directory still holds the first batch, though a later one changed a.txt.
+        let first = batch_record(
+            "first",
+            &first_root,
+            &[("a.txt", b"first"), ("b.txt", b"b")],
+        );
+        let report = compare(dir.path(), &first, &hashes(&first)).unwrap();
+        assert!(report.matches);
+        assert!(report.failure().is_none());
+
+        fs::write(dir.path().join("a.txt"), b"second").unwrap();
+        fs::remove_file(dir.path().join("b.txt")).unwrap();
+        let report = compare(dir.path(), &first, &hashes(&first)).unwrap();
+        assert!(!report.matches);
+        assert_eq!(
+            report.differences,
+            FileDifferences {
+                missing: vec!["b.txt".to_string()],
+                extra: Vec::new(),
+                modified: vec!["a.txt".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn empty_directory_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let batch = batch_record("batch", "root", &[("a.txt", b"a"), ("b/c.txt", b"c")]);
+
+        let report = compare(dir.path(), &batch, &hashes(&batch)).unwrap();
+        assert!(!report.matches);
+        assert_eq!(
+            report.differences.missing,
+            vec!["a.txt".to_string(), "b/c.txt".to_string()]
+        );
+
+        let failure = report.failure().unwrap();
+        assert_eq!(failure.kind, ErrorKind::Verification);
+        assert_eq!(failure.kind.exit_code(), 3);
+    }
+
+    #[test]
+    fn file_differences_test() {
+        let recorded = BTreeMap::from([
+            ("same.txt".to_string(), "a".to_string()),
+            ("changed.txt".to_string(), "a".to_string()),
+            ("gone.txt".to_string(), "a".to_string()),
+        ]);
+        let local = BTreeMap::from([
+            ("same.txt".to_string(), "a".to_string()),
+            ("changed.txt".to_string(), "b".to_string()),
+            ("new.txt".to_string(), "a".to_string()),
+        ]);
+
+        assert_eq!(
+            FileDifferences::new(&local, &recorded),
+            FileDifferences {
+                missing: vec!["gone.txt".to_string()],
+                extra: vec!["new.txt".to_string()],
+                modified: vec!["changed.txt".to_string()],
+            }
+        );
+    }
+}
@@ -0,0 +1,199 @@
+use common::common::METADATA_MANIFEST;
+use notify::RecursiveMode;
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc::unbounded_channel;
+
+use super::{
+    context::Context,
+    errors::SynxClientError,
+    manifest::Manifest,
+    output::{progress, CommandError, Report},
+    service::client,
+};
+use common::syncx::syncx_client::SyncxClient;
+
+/// Paths changed since the last sync, by what happened to them.
+#[derive(Debug, Default, PartialEq)]
+struct Changes {
+    created: usize,
+    modified: usize,
+    deleted: usize,
+}
+
+impl Changes {
+    /// Sorts `paths` by comparing them with the files recorded in `manifest`.
+    fn classify(paths: &BTreeSet<PathBuf>, manifest: &Manifest) -> Self {
+        let mut changes = Changes::default();
+        for path in paths {
+            match (path.exists(), manifest.get(path).is_some()) {
+                (true, true) => changes.modified += 1,
+                (true, false) => changes.created += 1,
+                (false, true) => changes.deleted += 1,
+                // Created and removed again before it was ever synced.
+                (false, false) => {}
+            }
+        }
+        changes
+    }
+
+    fn is_empty(&self) -> bool {
+        self.created + self.modified + self.deleted == 0
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct WatchReport {
+    pub directory: String,
+    pub syncs: usize,
+    /// Syncs that didn't complete. Their changes are picked up by the next one.
+    pub failed_syncs: usize,
+}
+
+impl Report for WatchReport {}
+
+/// Syncs `directory` with the server until interrupted with Ctrl-C. The directory
+/// is uploaded once on start, and again after each burst of changes has been quiet
+/// for `debounce`. Each sync is an incremental `upload` and waits for the server
+/// to process the batch, so the next one can build on it. A sync cut short is
+/// resumed by the next `upload` or `watch` of the directory.
+pub async fn watch(
+    syncx_client: &mut SyncxClient<tonic::transport::Channel>,
+    directory: &str,
+    debounce: Duration,
+    context: &mut Context,
+) -> Result<WatchReport, CommandError> {
+    let path = fs::canonicalize(directory)
+        .map_err(|e| SynxClientError::WatchError(format!("{}: {}", directory, e)))?;
+    let directory = path.to_string_lossy().to_string();
+
+    let (tx, mut rx) = unbounded_channel();
+    let mut debouncer = new_debouncer(debounce, move |result: DebounceEventResult| {
+        let _ = tx.send(result);
+    })
+    .map_err(|e| SynxClientError::WatchError(e.to_string()))?;
+    debouncer
+        .watcher()
+        .watch(&path, RecursiveMode::Recursive)
+        .map_err(|e| SynxClientError::WatchError(e.to_string()))?;
+
+    // The handler is installed up front, so Ctrl-C during a sync lets it finish
+    // before the watch stops.
+    let mut shutdown = tokio::spawn(tokio::signal::ctrl_c());
+
+    let mut report = WatchReport {
+        directory,
+        syncs: 0,
+        failed_syncs: 0,
+    };
+
+    // Changes made while nothing was watching are picked up by a first sync.
+    sync(syncx_client, &mut report, context).await;
+
+    loop {
+        progress!(
+            "Watching {} ({} syncs so far). Press Ctrl-C to stop",
+            report.directory,
+            report.syncs
+        );
+
+        let mut paths = BTreeSet::new();
+        tokio::select! {
+            _ = &mut shutdown => break,
+            result = rx.recv() => match result {
+                Some(result) => collect_paths(&path, result, &mut paths),
+                None => break,
+            },
+        }
+
+        // Changes made during the last sync are folded into this one.
+        while let Ok(result) = rx.try_recv() {
+            collect_paths(&path, result, &mut paths);
+        }
+
+        let changes = Changes::classify(&paths, &Manifest::read(&context.manifest_path()));
+        if changes.is_empty() {
+            continue;
+        }
+
+        progress!(
+            "{} created, {} modified, {} deleted. Syncing...",
+            changes.created,
+            changes.modified,
+            changes.deleted
+        );
+        sync(syncx_client, &mut report, context).await;
+    }
+
+    progress!("Stopped watching {}", report.directory);
+    Ok(report)
+}
+
+async fn sync(
+    syncx_client: &mut SyncxClient<tonic::transport::Channel>,
+    report: &mut WatchReport,
+    context: &mut Context,
+) {
+    let upload = client::upload_files(syncx_client, &report.directory, true, false, false, context)
+        .await
+        .and_then(|upload| upload.failure().map_or(Ok(()), Err));
+
+    report.syncs += 1;
+    if let Err(e) = upload {
+        progress!("Sync failed: {}", e);
+        report.failed_syncs += 1;
+    }
+}
+
+/// Adds the paths of debounced events under `base_dir` to `paths`, leaving out
+/// the directories themselves and the metadata manifest.
+fn collect_paths(base_dir: &Path, result: DebounceEventResult, paths: &mut BTreeSet<PathBuf>) {
+    match result {
+        Ok(events) => paths.extend(
+            events
+                .into_iter()
+                .map(|event| event.path)
+                .filter(|path| *path != base_dir.join(METADATA_MANIFEST) && !path.is_dir()),
+        ),
+        Err(e) => progress!("Watch error: {:?}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::manifest::ManifestEntry;
+
+    #[test]
+    fn classify_changes_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let synced = dir.path().join("synced.txt");
+        let created = dir.path().join("created.txt");
+        let deleted = dir.path().join("deleted.txt");
+        fs::write(&synced, b"synced").unwrap();
+        fs::write(&deleted, b"deleted").unwrap();
+
+        let mut manifest = Manifest::default();
+        manifest.record(vec![
+            ManifestEntry::new(dir.path(), &synced, "batch", None).unwrap(),
+            ManifestEntry::new(dir.path(), &deleted, "batch", None).unwrap(),
+        ]);
+
+        fs::write(&created, b"created").unwrap();
+        fs::remove_file(&deleted).unwrap();
+
+        let paths = BTreeSet::from([synced, created, deleted, dir.path().join("transient.txt")]);
+        assert_eq!(
+            Changes::classify(&paths, &manifest),
+            Changes {
+                created: 1,
+                modified: 1,
+                deleted: 1,
+            }
+        );
+    }
+}
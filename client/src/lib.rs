@@ -0,0 +1,27 @@
+//! The syncx client as a library. `SyncxSession` uploads, downloads and verifies
+//! files on behalf of one client profile, returning a `CommandError` on failure
+//! and reporting what it is doing through a progress callback.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), syncx_client::CommandError> {
+//! let mut session = syncx_client::SyncxSession::builder()
+//!     .profile("backup")
+//!     .on_progress(|progress| eprintln!("{}", progress))
+//!     .open()?;
+//!
+//! let upload = session
+//!     .upload_dir("./photos", syncx_client::UploadOptions::default())
+//!     .await?;
+//! println!("uploaded batch {:?}", upload.batch_id);
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod core;
+
+pub use crate::core::{
+    output::{CommandError, ErrorKind, Progress, Report, Stage},
+    session::{
+        DownloadOptions, FileSelection, RestoreOptions, SessionBuilder, SyncxSession, UploadOptions,
+    },
+};
@@ -1,4 +1,16 @@
+use std::{env, path::PathBuf};
+
 fn main() {
-    tonic_build::compile_protos("proto/syncx.proto")
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+
+    // `FileMetadata` is also written to the upload manifest and the server's records.
+    // The descriptor set is served by the server's reflection service.
+    tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("syncx_descriptor.bin"))
+        .type_attribute(
+            "syncx.FileMetadata",
+            "#[derive(serde::Serialize, serde::Deserialize)] #[serde(default)]",
+        )
+        .compile(&["proto/syncx.proto"], &["proto"])
         .unwrap_or_else(|e| panic!("Failed to compile protos {:?}", e));
 }
@@ -4,9 +4,14 @@ use rayon::prelude::*;
 use std::fs::{self, read_dir, File};
 use std::io::{self, BufReader, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
 use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
 
 use super::errors::CommonError;
+use super::syncx::FileMetadata;
+
+/// Name of the archive entry listing the `FileMetadata` of every uploaded file and symlink.
+pub const METADATA_MANIFEST: &str = ".syncx_metadata.json";
 
 /// Creates a ZIP archive from a collection of file paths, efficiently handling large files by streaming.
 ///
@@ -46,7 +51,62 @@ use super::errors::CommonError;
 ///  algorithm, which provides a good balance between compression ratio and
 ///  speed, making it suitable for a wide range of file types.
 pub fn zip_files<P: AsRef<Path>>(file_paths: &[P], output_path: &P) -> io::Result<()> {
-    let file = File::create(output_path)?;
+    zip_entries(
+        file_paths,
+        output_path.as_ref(),
+        |path| {
+            path.file_name()
+                .and_then(|f| f.to_str())
+                .map(|f| f.to_string())
+        },
+        None,
+        |_| {},
+    )
+}
+
+/// Creates a ZIP archive like `zip_files`, but stores each file under its path relative
+/// to `base_dir` (with `/` separators) so that nested directories survive the round trip.
+/// When `metadata` is not empty it is stored as JSON in a `METADATA_MANIFEST` entry.
+/// `on_progress` is called with the number of bytes of file content added to the
+/// archive each time a block of it has been written.
+pub fn zip_files_relative<P: AsRef<Path>>(
+    base_dir: &Path,
+    file_paths: &[P],
+    output_path: &Path,
+    metadata: &[FileMetadata],
+    on_progress: impl FnMut(u64),
+) -> io::Result<()> {
+    let manifest = match metadata.is_empty() {
+        true => None,
+        false => Some(serde_json::to_vec(metadata)?),
+    };
+
+    zip_entries(
+        file_paths,
+        output_path,
+        |path| relative_path_name(base_dir, path),
+        manifest.as_deref(),
+        on_progress,
+    )
+}
+
+fn zip_entries<P: AsRef<Path>>(
+    file_paths: &[P],
+    output_path: &Path,
+    entry_name: impl Fn(&Path) -> Option<String>,
+    manifest: Option<&[u8]>,
+    mut on_progress: impl FnMut(u64),
+) -> io::Result<()> {
+    // The archive holds the user's files, so it is private to them and never
+    // written through an existing file or link.
+    let mut file_options = File::options();
+    file_options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        file_options.mode(0o600);
+    }
+    let file = file_options.open(output_path)?;
     let mut zip = ZipWriter::new(file);
 
     let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
@@ -54,11 +114,15 @@ pub fn zip_files<P: AsRef<Path>>(file_paths: &[P], output_path: &P) -> io::Resul
     for path in file_paths {
         let path = path.as_ref();
         if path.is_file() {
-            let file_name = path
-                .file_name()
-                .and_then(|f| f.to_str())
+            let file_name = entry_name(path)
                 .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid file name"))?;
 
+            let mode = file_mode(&fs::metadata(path)?);
+            let options = match mode {
+                0 => options,
+                mode => options.unix_permissions(mode),
+            };
+
             zip.start_file(file_name, options)?;
             let mut file = BufReader::new(File::open(path)?);
             let mut buffer = [0; 4096];
@@ -69,10 +133,16 @@ pub fn zip_files<P: AsRef<Path>>(file_paths: &[P], output_path: &P) -> io::Resul
                     break;
                 }
                 zip.write_all(&buffer[..bytes_read])?;
+                on_progress(bytes_read as u64);
             }
         }
     }
 
+    if let Some(manifest) = manifest {
+        zip.start_file(METADATA_MANIFEST, options)?;
+        zip.write_all(manifest)?;
+    }
+
     zip.finish()?;
 
     Ok(())
@@ -143,6 +213,223 @@ pub fn list_files_in_dir(dir_path: &PathBuf) -> io::Result<Vec<PathBuf>> {
     Ok(files)
 }
 
+/// Lists the files in `dir_path` and all of its subdirectories. Symbolic links are
+/// neither listed nor followed.
+pub fn list_files_recursive(dir_path: &Path) -> io::Result<Vec<PathBuf>> {
+    Ok(walk_dir(dir_path)?.0)
+}
+
+/// Lists the symbolic links in `dir_path` and all of its subdirectories.
+pub fn list_symlinks_recursive(dir_path: &Path) -> io::Result<Vec<PathBuf>> {
+    Ok(walk_dir(dir_path)?.1)
+}
+
+fn walk_dir(dir_path: &Path) -> io::Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let mut files = Vec::new();
+    let mut symlinks = Vec::new();
+
+    if dir_path.is_dir() {
+        for entry in read_dir(dir_path)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_symlink() {
+                symlinks.push(entry.path());
+            } else if file_type.is_dir() {
+                let (nested_files, nested_symlinks) = walk_dir(&entry.path())?;
+                files.extend(nested_files);
+                symlinks.extend(nested_symlinks);
+            } else if file_type.is_file() {
+                files.push(entry.path());
+            }
+        }
+    }
+
+    Ok((files, symlinks))
+}
+
+/// Reads the metadata of the file or symbolic link at `path`, recording its path
+/// relative to `base_dir`.
+pub fn read_file_metadata(base_dir: &Path, path: &Path) -> io::Result<FileMetadata> {
+    let name = relative_path_name(base_dir, path)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid file name"))?;
+    let metadata = fs::symlink_metadata(path)?;
+
+    let symlink_target = match metadata.file_type().is_symlink() {
+        true => fs::read_link(path)?.to_string_lossy().into_owned(),
+        false => String::new(),
+    };
+
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+
+    Ok(FileMetadata {
+        path: name,
+        mode: file_mode(&metadata),
+        mtime_seconds: mtime.as_secs() as i64,
+        mtime_nanos: mtime.subsec_nanos(),
+        symlink_target,
+    })
+}
+
+/// Sets the modification time and permissions recorded in `metadata` on the file at `path`.
+pub fn apply_file_metadata(path: &Path, metadata: &FileMetadata) -> io::Result<()> {
+    // The time is set first, as the recorded mode may not allow writing.
+    if metadata.mtime_seconds > 0 {
+        let mtime = UNIX_EPOCH + Duration::new(metadata.mtime_seconds as u64, metadata.mtime_nanos);
+        File::options()
+            .write(true)
+            .open(path)?
+            .set_modified(mtime)?;
+    }
+
+    #[cfg(unix)]
+    if metadata.mode != 0 {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(metadata.mode & 0o777))?;
+    }
+
+    Ok(())
+}
+
+/// Creates a symbolic link at `path` pointing to `target`.
+pub fn create_symlink(path: &Path, target: &str) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(target, path)
+    }
+
+    #[cfg(not(unix))]
+    {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("Can't create symbolic link {:?} -> {}", path, target),
+        ))
+    }
+}
+
+#[cfg(unix)]
+fn file_mode(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn file_mode(_metadata: &fs::Metadata) -> u32 {
+    0
+}
+
+/// Normalizes a relative file path to `/` separated components, dropping empty and `.`
+/// components. Returns `None` for absolute paths, paths containing `..`, and paths
+/// with no components, so the result can always be joined safely onto a directory.
+pub fn normalize_relative_path(path: &str) -> Option<String> {
+    if path.starts_with(['/', '\\']) {
+        return None;
+    }
+
+    let mut components = Vec::new();
+    for component in path.split(['/', '\\']) {
+        match component {
+            "" | "." => continue,
+            ".." => return None,
+            component => components.push(component),
+        }
+    }
+
+    match components.is_empty() {
+        true => None,
+        false => Some(components.join("/")),
+    }
+}
+
+/// Resolves the `target` of a symbolic link stored at relative path `link_path`,
+/// returning what it points to relative to the same directory. Returns `None` for
+/// absolute targets and for targets that point outside that directory.
+pub fn resolve_link_target(link_path: &str, target: &str) -> Option<String> {
+    if target.starts_with(['/', '\\']) || Path::new(target).is_absolute() {
+        return None;
+    }
+
+    // The target is relative to the directory holding the link.
+    let mut components = link_path
+        .split(['/', '\\'])
+        .filter(|component| !component.is_empty() && *component != ".")
+        .collect::<Vec<&str>>();
+    components.pop();
+
+    for component in target.split(['/', '\\']) {
+        match component {
+            "" | "." => continue,
+            ".." => {
+                components.pop()?;
+            }
+            component => components.push(component),
+        }
+    }
+
+    Some(components.join("/"))
+}
+
+/// Returns the path of `path` relative to `base_dir`, with `/` separators.
+pub fn relative_path_name(base_dir: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(base_dir).ok()?;
+    let components = relative
+        .components()
+        .map(|component| component.as_os_str().to_str())
+        .collect::<Option<Vec<&str>>>()?;
+
+    normalize_relative_path(&components.join("/"))
+}
+
+/// Joins the relative path `name` onto `base_dir`, or returns `None` if it would escape it.
+pub fn join_relative_path(base_dir: &Path, name: &str) -> Option<PathBuf> {
+    let name = normalize_relative_path(name)?;
+    Some(
+        name.split('/')
+            .fold(base_dir.to_path_buf(), |path, component| {
+                path.join(component)
+            }),
+    )
+}
+
+/// Size in bytes of the chunks a file is split into for retrievability challenges.
+pub const CHALLENGE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Most chunks a single retrievability challenge may ask for.
+pub const MAX_CHALLENGE_CHUNKS: usize = 64;
+
+/// Largest message, in bytes, the server accepts from clients.
+pub const MAX_REQUEST_SIZE: usize = 4 * 1024 * 1024;
+
+/// Splits `bytes` into `CHALLENGE_CHUNK_SIZE` chunks. An empty file is a single empty chunk.
+pub fn file_chunks(bytes: &[u8]) -> Vec<&[u8]> {
+    match bytes.is_empty() {
+        true => vec![bytes],
+        false => bytes.chunks(CHALLENGE_CHUNK_SIZE).collect(),
+    }
+}
+
+/// Leaf bytes of chunk `index` in a file's chunk tree. The index is hashed along with
+/// the chunk so that a chunk can't be passed off as another one with the same proof.
+pub fn chunk_leaf_bytes(index: u64, chunk: &[u8]) -> Vec<u8> {
+    let mut leaf = index.to_be_bytes().to_vec();
+    leaf.extend_from_slice(chunk);
+    leaf
+}
+
+/// Generates the Merkle tree over the chunks of a file, as used by retrievability challenges.
+pub fn generate_chunk_merkle_tree(bytes: &[u8]) -> MerkleTree {
+    let leaf_bytes = file_chunks(bytes)
+        .into_iter()
+        .enumerate()
+        .map(|(index, chunk)| chunk_leaf_bytes(index as u64, chunk))
+        .collect::<Vec<Vec<u8>>>();
+
+    MerkleTree::new(&leaf_bytes)
+}
+
 /// Reads a file and accumulates its contents into a `Vec<u8>`.
 ///
 /// This function reads the file in chunks to efficiently handle large files while
@@ -234,6 +521,7 @@ pub fn delete_files_in_directory(dir: &Path) -> std::io::Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use merkle_tree::utils::hash_bytes;
     use std::io::Write;
     use tempfile::tempdir;
 
@@ -261,6 +549,85 @@ mod tests {
         }
     }
 
+    #[test]
+    fn should_normalize_relative_paths() {
+        assert_eq!(
+            normalize_relative_path("a/./b//c.txt").unwrap(),
+            "a/b/c.txt"
+        );
+        assert_eq!(normalize_relative_path("a\\b.txt").unwrap(), "a/b.txt");
+        assert!(normalize_relative_path("../secret").is_none());
+        assert!(normalize_relative_path("a/../../secret").is_none());
+        assert!(normalize_relative_path("/etc/passwd").is_none());
+        assert!(normalize_relative_path("./").is_none());
+    }
+
+    #[test]
+    fn should_resolve_link_targets() {
+        assert_eq!(resolve_link_target("a/link", "b.txt").unwrap(), "a/b.txt");
+        assert_eq!(
+            resolve_link_target("a/b/link", "../c.txt").unwrap(),
+            "a/c.txt"
+        );
+        assert_eq!(resolve_link_target("a/link", "..").unwrap(), "");
+        assert!(resolve_link_target("link", "../secret").is_none());
+        assert!(resolve_link_target("a/link", "b/../../../secret").is_none());
+        assert!(resolve_link_target("a/link", "/etc/passwd").is_none());
+    }
+
+    #[test]
+    fn should_zip_and_unzip_nested_files() -> io::Result<()> {
+        let temp_dir = tempdir().unwrap();
+        fs::create_dir_all(temp_dir.path().join("a/b"))?;
+        fs::write(temp_dir.path().join("a/b/file.txt"), b"nested")?;
+        fs::write(temp_dir.path().join("file.txt"), b"top")?;
+
+        let files = list_files_recursive(temp_dir.path())?;
+        assert_eq!(files.len(), 2);
+
+        let zip_dir = tempdir().unwrap();
+        let zip_path = zip_dir.path().join("test.zip");
+        zip_files_relative(temp_dir.path(), &files, &zip_path, &[], |_| {})?;
+
+        let extract_dir = tempdir().unwrap();
+        unzip_file(&zip_path, &extract_dir.path().to_path_buf())?;
+
+        assert_eq!(
+            fs::read(extract_dir.path().join("a/b/file.txt"))?,
+            b"nested"
+        );
+        assert_eq!(fs::read(extract_dir.path().join("file.txt"))?, b"top");
+        Ok(())
+    }
+
+    #[test]
+    fn should_apply_file_metadata() -> io::Result<()> {
+        let temp_dir = tempdir().unwrap();
+        let source = temp_dir.path().join("source.sh");
+        fs::write(&source, b"#!/bin/sh")?;
+        let mtime = UNIX_EPOCH + Duration::new(1_700_000_000, 500);
+        File::options()
+            .write(true)
+            .open(&source)?
+            .set_modified(mtime)?;
+
+        let mut metadata = read_file_metadata(temp_dir.path(), &source)?;
+        assert_eq!(metadata.path, "source.sh");
+        assert_eq!(metadata.mtime_seconds, 1_700_000_000);
+        metadata.mode = 0o750;
+
+        let target = temp_dir.path().join("target.sh");
+        fs::write(&target, b"#!/bin/sh")?;
+        apply_file_metadata(&target, &metadata)?;
+
+        let restored = read_file_metadata(temp_dir.path(), &target)?;
+        assert_eq!(restored.mtime_seconds, metadata.mtime_seconds);
+        assert_eq!(restored.mtime_nanos, metadata.mtime_nanos);
+        #[cfg(unix)]
+        assert_eq!(restored.mode, 0o750);
+        Ok(())
+    }
+
     #[test]
     fn should_convert_file_to_bytes() {
         // Create a temporary directory
@@ -306,6 +673,25 @@ mod tests {
         assert!(merkle_tree.leaf_nodes() == &leaf_hashes);
     }
 
+    #[test]
+    fn should_prove_file_chunks() {
+        let content = vec![7u8; CHALLENGE_CHUNK_SIZE * 2 + 10];
+        let chunks = file_chunks(&content);
+        assert_eq!(chunks.len(), 3);
+
+        let merkle_tree = generate_chunk_merkle_tree(&content);
+        let leaf = hash_bytes(&chunk_leaf_bytes(1, chunks[1]));
+        let merkle_proof = merkle_tree.generate_merkle_proof(&leaf).unwrap();
+
+        let (valid, _) = MerkleTree::verify(&leaf, merkle_proof.clone(), merkle_tree.root());
+        assert!(valid);
+
+        // Identical chunks at different positions must not share a proof.
+        let wrong_leaf = hash_bytes(&chunk_leaf_bytes(0, chunks[1]));
+        let (valid, _) = MerkleTree::verify(&wrong_leaf, merkle_proof, merkle_tree.root());
+        assert!(!valid);
+    }
+
     #[test]
     fn should_zip_and_unzip() -> io::Result<()> {
         // Create a temporary directory with test files
@@ -2,4 +2,6 @@ pub mod common;
 pub mod errors;
 pub mod syncx {
     tonic::include_proto!("syncx");
+
+    pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("syncx_descriptor");
 }
@@ -3,7 +3,7 @@ use super::{errors::MerkleTreeError, utils::*};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use serde_json;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct MerkleTree {
@@ -11,9 +11,27 @@ pub struct MerkleTree {
     indexes: HashMap<String, (usize, usize)>,
 }
 
+/// A single proof for several leaves of the same tree.
+///
+/// `indices` holds the position of each proven leaf among the tree's leaves, in the
+/// order the leaves are passed to `verify_multiproof`, and `nodes` holds every
+/// `(level, index, hash)` needed to rebuild the root that can't be derived from them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MultiProof {
+    pub leaf_count: usize,
+    pub indices: Vec<usize>,
+    pub nodes: Vec<(usize, usize, String)>,
+}
+
 impl MerkleTree {
     pub fn new(leaf_bytes: &Vec<Vec<u8>>) -> Self {
-        let leaves = Self::build_leaf_nodes(leaf_bytes);
+        Self::from_leaf_hashes(Self::build_leaf_nodes(leaf_bytes))
+    }
+
+    /// Builds a tree from the hashes of its leaves. Leaves are sorted, so the tree
+    /// doesn't depend on the order they are given in.
+    pub fn from_leaf_hashes(mut leaves: Vec<String>) -> Self {
+        leaves.sort();
         let mut indexes: HashMap<_, _> = HashMap::new();
 
         leaves.iter().enumerate().for_each(|(index, leaf)| {
@@ -88,13 +106,9 @@ impl MerkleTree {
             }
 
             // Find the sibling index (left or right)
-            let sibling_index = if index % 2 == 0 {
-                (index + 1).min(level.len() - 1)
-            } else {
-                index - 1
-            };
+            let sibling_index = Self::sibling_index(index, level.len());
 
-            let is_left_sibling = (index % 2 == 0) as u8; // 1 for right sibling, 0 for left
+            let is_left_sibling = index.is_multiple_of(2) as u8; // 1 for right sibling, 0 for left
             proof.push((level[sibling_index].clone(), is_left_sibling));
             // Move up to the parent level
             index /= 2;
@@ -117,6 +131,104 @@ impl MerkleTree {
         (current_leaf == root_leaf, current_leaf)
     }
 
+    pub fn generate_multiproof(&self, leaves: &[String]) -> Result<MultiProof, MerkleTreeError> {
+        let indices = leaves
+            .iter()
+            .map(|leaf| match self.indexes.get(leaf) {
+                Some((0, index)) => Ok(*index),
+                _ => Err(MerkleTreeError::InvalidNode),
+            })
+            .collect::<Result<Vec<usize>, MerkleTreeError>>()?;
+
+        let mut known = indices.iter().copied().collect::<BTreeSet<usize>>();
+        let mut nodes = Vec::new();
+
+        for (level, level_nodes) in self.nodes.iter().enumerate().rev().skip(1).rev() {
+            for index in known.clone() {
+                let sibling_index = Self::sibling_index(index, level_nodes.len());
+                if known.insert(sibling_index) {
+                    nodes.push((level, sibling_index, level_nodes[sibling_index].clone()));
+                }
+            }
+
+            known = known.into_iter().map(|index| index / 2).collect();
+        }
+
+        Ok(MultiProof {
+            leaf_count: self.nodes[0].len(),
+            indices,
+            nodes,
+        })
+    }
+
+    pub fn verify_multiproof(
+        leaves: &[String],
+        proof: &MultiProof,
+        root_leaf: &str,
+    ) -> (bool, String) {
+        if leaves.is_empty() || leaves.len() != proof.indices.len() {
+            return (false, String::new());
+        }
+
+        let mut level_nodes = BTreeMap::new();
+        for (leaf, index) in leaves.iter().zip(proof.indices.iter()) {
+            if *index >= proof.leaf_count {
+                return (false, String::new());
+            }
+
+            // The same position can't be claimed by two different leaves.
+            if let Some(existing) = level_nodes.insert(*index, leaf.clone()) {
+                if existing != *leaf {
+                    return (false, String::new());
+                }
+            }
+        }
+
+        let mut level = 0;
+        let mut level_size = proof.leaf_count;
+        while level_size > 1 {
+            for (_, index, hash) in proof.nodes.iter().filter(|(l, _, _)| *l == level) {
+                level_nodes.entry(*index).or_insert_with(|| hash.clone());
+            }
+
+            let mut parents = BTreeMap::new();
+            for index in level_nodes.keys() {
+                let left_index = index - index % 2;
+                let right_index = Self::sibling_index(left_index, level_size);
+
+                let (Some(left), Some(right)) =
+                    (level_nodes.get(&left_index), level_nodes.get(&right_index))
+                else {
+                    return (false, String::new());
+                };
+
+                parents.insert(
+                    index / 2,
+                    hash_bytes(format!("{}{}", left, right).as_bytes()),
+                );
+            }
+
+            level_nodes = parents;
+            level_size = level_size.div_ceil(2);
+            level += 1;
+        }
+
+        match level_nodes.get(&0) {
+            Some(root) => (root == root_leaf, root.clone()),
+            None => (false, String::new()),
+        }
+    }
+
+    /// Index of the node `index` is hashed with. The last node of an odd level is
+    /// hashed with itself.
+    fn sibling_index(index: usize, level_size: usize) -> usize {
+        if index.is_multiple_of(2) {
+            (index + 1).min(level_size - 1)
+        } else {
+            index - 1
+        }
+    }
+
     pub fn serialize(&self) -> Result<String, MerkleTreeError> {
         Ok(serde_json::to_string(&self).map_err(|_| MerkleTreeError::SerializeTreeError)?)
     }
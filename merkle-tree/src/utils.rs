@@ -1,8 +1,17 @@
 use hex;
 use sha2::{Digest, Sha256};
+use std::io::{self, Read};
 
 pub fn hash_bytes(byte: &[u8]) -> String {
     let mut hasher = Sha256::new();
     hasher.update(byte);
     hex::encode(hasher.finalize())
 }
+
+/// Hashes everything `reader` yields, as `hash_bytes` would, without holding it
+/// in memory.
+pub fn hash_reader<R: Read>(mut reader: R) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut reader, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
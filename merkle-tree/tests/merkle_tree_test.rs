@@ -2,6 +2,7 @@ mod common;
 
 use common::*;
 use merkle_tree::merkle_tree::MerkleTree;
+use merkle_tree::utils::{hash_bytes, hash_reader};
 
 #[cfg(test)]
 mod tests {
@@ -32,6 +33,15 @@ mod tests {
         assert!(merkle_tree.nodes == expected_merkle_tree_nodes);
     }
 
+    #[test]
+    fn test_merkle_tree_from_leaf_hashes() {
+        let merkle_tree = MerkleTree::new(&BYTE_ARRAY_MATRIX);
+        let merkle_tree_from_hashes =
+            MerkleTree::from_leaf_hashes(vec![LD.into(), LB.into(), LA.into(), LC.into()]);
+
+        assert_eq!(merkle_tree, merkle_tree_from_hashes);
+    }
+
     #[test]
     fn test_odd_leaf_nodes_merkle_tree() {
         let merkle_tree = MerkleTree::new(&odd_leaves());
@@ -62,7 +72,7 @@ mod tests {
         let leaf = LB;
         let proof = merkle_tree.generate_merkle_proof(leaf).unwrap();
 
-        let valid_leaf = MerkleTree::verify(
+        let (valid_leaf, _) = MerkleTree::verify(
             leaf,
             proof,
             &merkle_tree.nodes[merkle_tree.nodes.len() - 1][0],
@@ -71,6 +81,42 @@ mod tests {
         assert!(valid_leaf);
     }
 
+    #[test]
+    fn test_verify_multiproof() {
+        let merkle_tree = MerkleTree::new(&odd_leaves());
+        let leaves = vec![LD.to_string(), LA.to_string()];
+        let proof = merkle_tree.generate_multiproof(&leaves).unwrap();
+
+        // LA and LD only need LB to rebuild the root, since LD is hashed with itself.
+        assert!(proof.nodes == vec![(0, 1, LB.to_string())]);
+
+        let (valid, root) = MerkleTree::verify_multiproof(&leaves, &proof, merkle_tree.root());
+        assert!(valid);
+        assert!(root == H_LALB_LDLD);
+    }
+
+    #[test]
+    fn test_reject_invalid_multiproof() {
+        let merkle_tree = MerkleTree::new(&BYTE_ARRAY_MATRIX);
+        let leaves = vec![LB.to_string(), LC.to_string()];
+        let proof = merkle_tree.generate_multiproof(&leaves).unwrap();
+
+        let tampered_leaves = vec![LB.to_string(), LD.to_string()];
+        let (valid, _) =
+            MerkleTree::verify_multiproof(&tampered_leaves, &proof, merkle_tree.root());
+        assert!(!valid);
+
+        let mut incomplete_proof = proof.clone();
+        incomplete_proof.nodes.pop();
+        let (valid, _) =
+            MerkleTree::verify_multiproof(&leaves, &incomplete_proof, merkle_tree.root());
+        assert!(!valid);
+
+        assert!(merkle_tree
+            .generate_multiproof(&[H_LA_LB.to_string()])
+            .is_err());
+    }
+
     #[test]
     fn test_serialize_and_deserialize_tree() {
         let merkle_tree = MerkleTree::new(&BYTE_ARRAY_MATRIX);
@@ -79,4 +125,10 @@ mod tests {
         let deserialized_tree = merkle_tree.deserialize(&serialized_tree).unwrap();
         assert!(deserialized_tree == merkle_tree);
     }
+
+    #[test]
+    fn test_hash_reader() {
+        let bytes = vec![7u8; 100_000];
+        assert_eq!(hash_reader(bytes.as_slice()).unwrap(), hash_bytes(&bytes));
+    }
 }
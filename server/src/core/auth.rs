@@ -98,8 +98,8 @@ pub mod jwt {
 
 pub mod hash_utils {
     use argon2::{
-        password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
-        Argon2,
+        password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, SaltString},
+        Argon2, PasswordVerifier,
     };
 
     use crate::core::errors::SynxServerError;
@@ -113,4 +113,26 @@ pub mod hash_utils {
             .map_err(|_| SynxServerError::PasswordHashError)?
             .to_string())
     }
+
+    /// Checks `password` against a hash created with `hash_password`.
+    pub fn verify_password(password: &str, hash: &str) -> Result<bool, SynxServerError> {
+        let hash = PasswordHash::new(hash).map_err(|_| SynxServerError::PasswordHashError)?;
+
+        Ok(Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn password_should_verify() {
+            let hash = hash_password("password").unwrap();
+
+            assert!(verify_password("password", &hash).unwrap());
+            assert!(!verify_password("wrong password", &hash).unwrap());
+        }
+    }
 }
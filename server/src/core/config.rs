@@ -1,4 +1,6 @@
 use dotenv::dotenv;
+use std::fs;
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
 
 use super::errors::SynxServerError;
 
@@ -11,6 +13,13 @@ pub struct Config {
     pub db_name: String,
     pub gcs_bucket_name: String,
     pub api_key: String,
+    /// PEM certificate chain and private key the server presents. The server
+    /// only serves TLS when both are set.
+    pub tls_cert_path: Option<String>,
+    pub tls_key_path: Option<String>,
+    /// PEM bundle of the CAs whose client certificates are accepted. When set,
+    /// every client must present a certificate (mutual TLS).
+    pub tls_client_ca_path: Option<String>,
 }
 
 impl Config {
@@ -57,6 +66,22 @@ impl Config {
             SynxServerError::InvalidServerSettings("LOG_CONFIG not present".to_string())
         })?;
 
+        let tls_cert_path = optional_var("TLS_CERT_PATH");
+        let tls_key_path = optional_var("TLS_KEY_PATH");
+        let tls_client_ca_path = optional_var("TLS_CLIENT_CA_PATH");
+
+        if tls_cert_path.is_some() != tls_key_path.is_some() {
+            return Err(SynxServerError::InvalidServerSettings(
+                "TLS_CERT_PATH and TLS_KEY_PATH must be set together".to_string(),
+            ));
+        }
+
+        if tls_client_ca_path.is_some() && tls_cert_path.is_none() {
+            return Err(SynxServerError::InvalidServerSettings(
+                "TLS_CLIENT_CA_PATH requires TLS_CERT_PATH and TLS_KEY_PATH".to_string(),
+            ));
+        }
+
         Ok(Config {
             database_url,
             redis_url,
@@ -65,6 +90,37 @@ impl Config {
             gcs_bucket_name,
             db_name,
             api_key,
+            tls_cert_path,
+            tls_key_path,
+            tls_client_ca_path,
         })
     }
+
+    /// Reads the configured certificates into a TLS config for the server, or
+    /// returns `None` if TLS is not configured.
+    pub fn tls_config(&self) -> Result<Option<ServerTlsConfig>, SynxServerError> {
+        let (cert_path, key_path) = match (&self.tls_cert_path, &self.tls_key_path) {
+            (Some(cert_path), Some(key_path)) => (cert_path, key_path),
+            _ => return Ok(None),
+        };
+
+        let identity = Identity::from_pem(read_pem(cert_path)?, read_pem(key_path)?);
+        let mut tls_config = ServerTlsConfig::new().identity(identity);
+
+        if let Some(client_ca_path) = &self.tls_client_ca_path {
+            tls_config =
+                tls_config.client_ca_root(Certificate::from_pem(read_pem(client_ca_path)?));
+        }
+
+        Ok(Some(tls_config))
+    }
+}
+
+/// Reads an optional setting, treating an empty value as unset.
+fn optional_var(key: &str) -> Option<String> {
+    std::env::var(key).ok().filter(|value| !value.is_empty())
+}
+
+fn read_pem(path: &str) -> Result<Vec<u8>, SynxServerError> {
+    fs::read(path).map_err(|e| SynxServerError::TlsConfigError(format!("{}: {}", path, e)))
 }
@@ -18,9 +18,25 @@ pub const WIP_DOWNLOADS_DIR: &str = "wip/pending_downloads/";
 pub const DEFAULT_ZIP_FILE: &str = "uploads.zip";
 pub const MERKLE_DIR: &str = "temp/merkle_trees";
 pub const JOB_QUEUE: &str = "syncx_queue";
+pub const JOB_RECORD_PREFIX: &str = "job";
+pub const LATEST_BATCH_PREFIX: &str = "latest_batch";
+pub const LATEST_FILE_PREFIX: &str = "latest_file";
+pub const BATCH_FILE_PREFIX: &str = "batch_file";
+pub const BATCH_LINEAGE_PREFIX: &str = "batch_lineage";
+pub const LATEST_LINEAGE_BATCH_PREFIX: &str = "latest_lineage_batch";
+pub const BATCH_FILES_PREFIX: &str = "batch_files";
+pub const BATCH_SYMLINKS_PREFIX: &str = "batch_symlinks";
+pub const FILE_METADATA_PREFIX: &str = "file_metadata";
+pub const FILE_SOURCE_PREFIX: &str = "file_source";
+pub const JOB_WATCH_INTERVAL_MILLIS: u64 = 500;
 pub const CACHE_POOL_MAX_OPEN: u32 = 16;
 pub const CACHE_POOL_MIN_IDLE: u32 = 8;
 pub const CACHE_POOL_EXPIRE_SECONDS: u64 = 60;
+pub const DOWNLOAD_CHUNK_SIZE: u64 = 64 * 1024;
+pub const UPLOAD_SESSION_PREFIX: &str = "upload_session";
+pub const UPLOAD_SESSION_EXPIRE_SECONDS: usize = 60 * 60 * 24;
+pub const HEALTH_CHECK_INTERVAL_SECONDS: u64 = 10;
+pub const HEALTH_CHECK_TIMEOUT_SECONDS: u64 = 5;
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct ClientObject {
@@ -28,6 +44,78 @@ pub struct ClientObject {
     pub password: String,
 }
 
+/// A resumable upload started with `BeginUpload`. The bytes received so far
+/// live in a local `.part` file; the session only tracks ownership, the
+/// expected size and, once the archive is complete, the checksum it was
+/// verified against, so `CommitUpload` can tell when it is safe to store.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct UploadSession {
+    pub id: String,
+    pub client_id: String,
+    pub size: u64,
+    #[serde(default)]
+    pub checksum: Option<String>,
+    /// Batch an incremental upload builds on, and the files carried over from it.
+    #[serde(default)]
+    pub base_batch_id: Option<String>,
+    #[serde(default)]
+    pub unchanged_files: Vec<String>,
+    /// Lineage the batch continues.
+    #[serde(default)]
+    pub lineage: String,
+}
+
+/// Work pushed to `JOB_QUEUE` when an upload is committed. The batch id is
+/// the id of the upload session the archive arrived through.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Job {
+    pub client_id: String,
+    pub batch_id: String,
+    /// For incremental uploads, the batch whose `unchanged_files` are merged
+    /// into this one.
+    #[serde(default)]
+    pub base_batch_id: Option<String>,
+    #[serde(default)]
+    pub unchanged_files: Vec<String>,
+    /// Lineage the batch continues.
+    #[serde(default)]
+    pub lineage: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub enum JobState {
+    Queued,
+    Unpacking,
+    Uploading,
+    Done,
+    Failed,
+}
+
+/// Progress of the job processing batch `batch_id`, written by the server when
+/// the job is queued and updated by the worker as it goes.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct JobRecord {
+    pub batch_id: String,
+    pub client_id: String,
+    pub state: JobState,
+    #[serde(default)]
+    pub reason: String,
+    #[serde(default)]
+    pub merkle_root: String,
+}
+
+impl JobRecord {
+    pub fn new(job: &Job, state: JobState) -> Self {
+        Self {
+            batch_id: job.batch_id.clone(),
+            client_id: job.client_id.clone(),
+            state,
+            reason: String::new(),
+            merkle_root: String::new(),
+        }
+    }
+}
+
 #[async_trait]
 pub trait Store {
     async fn get_client_object(&self, id: &str) -> Result<Option<ClientObject>>;
@@ -36,7 +124,17 @@ pub trait Store {
 
     async fn save_client_object(&self, client_object: ClientObject) -> Result<bool>;
 
-    fn enqueue_job(&self, value: &str) -> Result<()>;
+    fn enqueue_job(&self, job: &Job) -> Result<()>;
+
+    fn save_upload_session(&self, session: &UploadSession) -> Result<()>;
+
+    fn get_upload_session(&self, id: &str) -> Result<Option<UploadSession>>;
+
+    fn delete_upload_session(&self, id: &str) -> Result<()>;
+
+    fn save_job_record(&self, record: &JobRecord) -> Result<()>;
+
+    fn get_job_record(&self, batch_id: &str) -> Result<Option<JobRecord>>;
 }
 
 pub trait RedisPool {
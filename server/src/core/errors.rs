@@ -86,4 +86,19 @@ pub enum SynxServerError {
 
     #[error("Failed to convert file to bytes")]
     ConvertFileToBytesError,
+
+    #[error("Batch holds no files")]
+    EmptyBatch,
+
+    #[error("File {0} not found")]
+    FileNotFound(String),
+
+    #[error("Storage bucket is unreachable: {0}")]
+    StorageUnavailableError(String),
+
+    #[error("Health check timed out")]
+    HealthCheckTimeoutError,
+
+    #[error("Failed to read TLS certificate {0}")]
+    TlsConfigError(String),
 }
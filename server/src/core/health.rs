@@ -0,0 +1,219 @@
+use super::{
+    definitions::{
+        R2D2Pool, Result, CACHE_POOL_TIMEOUT_SECONDS, HEALTH_CHECK_INTERVAL_SECONDS,
+        HEALTH_CHECK_TIMEOUT_SECONDS,
+    },
+    errors::SynxServerError,
+    utils::check_bucket,
+};
+
+use log::{info, warn};
+use mongodb::{bson::doc, Client};
+use r2d2_redis::redis;
+use std::future::Future;
+use std::time::Duration;
+use tonic::server::NamedService;
+use tonic_health::{server::HealthReporter, ServingStatus};
+
+/// Probes the services the server depends on and publishes the result through the
+/// standard `grpc.health.v1.Health` service.
+pub struct HealthChecker {
+    db_client: Client,
+    db_name: String,
+    redis_pool: R2D2Pool,
+    gcs_bucket_name: String,
+    api_key: String,
+}
+
+impl HealthChecker {
+    pub fn new(
+        db_client: Client,
+        db_name: &str,
+        redis_pool: R2D2Pool,
+        gcs_bucket_name: &str,
+        api_key: &str,
+    ) -> Self {
+        Self {
+            db_client,
+            db_name: db_name.to_string(),
+            redis_pool,
+            gcs_bucket_name: gcs_bucket_name.to_string(),
+            api_key: api_key.to_string(),
+        }
+    }
+
+    /// Re-checks every dependency on a fixed interval, forever. Both the overall
+    /// server status (the empty service name) and service `S` are reported as
+    /// serving only while MongoDB, Redis and the storage bucket are all reachable.
+    pub async fn run<S: NamedService>(self, reporter: HealthReporter) {
+        report_health::<S, _, _>(
+            || self.check(),
+            reporter,
+            Duration::from_secs(HEALTH_CHECK_INTERVAL_SECONDS),
+        )
+        .await
+    }
+
+    /// Checks MongoDB, Redis and the storage bucket in turn.
+    pub async fn check(&self) -> Result<()> {
+        with_timeout(self.check_database()).await?;
+        with_timeout(self.check_redis()).await?;
+        with_timeout(check_bucket(&self.gcs_bucket_name, &self.api_key)).await
+    }
+
+    async fn check_database(&self) -> Result<()> {
+        self.db_client
+            .database(&self.db_name)
+            .run_command(doc! { "ping": 1 }, None)
+            .await
+            .map_err(|e| SynxServerError::DatabaseConnectionError(e.to_string()))?;
+
+        Ok(())
+    }
+
+    /// Pings Redis. r2d2 blocks while it waits for a connection, so the ping runs on
+    /// the blocking thread pool rather than in the checker's task.
+    async fn check_redis(&self) -> Result<()> {
+        let redis_pool = self.redis_pool.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let mut conn = redis_pool
+                .get_timeout(Duration::from_secs(CACHE_POOL_TIMEOUT_SECONDS))
+                .map_err(|e| SynxServerError::RedisPoolError(e.to_string()))?;
+            redis::cmd("PING")
+                .query::<String>(&mut *conn)
+                .map_err(|e| SynxServerError::RedisCMDError(e.to_string()))?;
+
+            Ok(())
+        })
+        .await
+        .map_err(|e| SynxServerError::RedisCMDError(e.to_string()))?
+    }
+}
+
+/// Runs `check` every `period` and reports both the overall server status and
+/// service `S` as serving only while it succeeds.
+async fn report_health<S, F, Fut>(mut check: F, mut reporter: HealthReporter, period: Duration)
+where
+    S: NamedService,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let mut interval = tokio::time::interval(period);
+    let mut last_status = None;
+
+    loop {
+        interval.tick().await;
+
+        let status = match check().await {
+            Ok(()) => ServingStatus::Serving,
+            Err(e) => {
+                warn!("Health check failed: {}", e);
+                ServingStatus::NotServing
+            }
+        };
+
+        if last_status != Some(status) {
+            info!("Server health changed to {:?}", status);
+            last_status = Some(status);
+        }
+
+        reporter.set_service_status("", status).await;
+        reporter.set_service_status(S::NAME, status).await;
+    }
+}
+
+/// Fails a check that takes longer than `HEALTH_CHECK_TIMEOUT_SECONDS`, so that an
+/// unresponsive dependency is reported instead of stalling the checker.
+async fn with_timeout(check: impl Future<Output = Result<()>>) -> Result<()> {
+    tokio::time::timeout(Duration::from_secs(HEALTH_CHECK_TIMEOUT_SECONDS), check)
+        .await
+        .unwrap_or(Err(SynxServerError::HealthCheckTimeoutError))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::transport::{Channel, Endpoint, Server};
+    use tonic_health::pb::{
+        health_check_response, health_client::HealthClient, HealthCheckRequest,
+    };
+
+    struct TestService;
+
+    impl NamedService for TestService {
+        const NAME: &'static str = "syncx.Test";
+    }
+
+    /// Polls the health service until `service` reports `status`.
+    async fn wait_for_status(
+        client: &mut HealthClient<Channel>,
+        service: &str,
+        status: health_check_response::ServingStatus,
+    ) {
+        for _ in 0..200 {
+            let request = HealthCheckRequest {
+                service: service.to_string(),
+            };
+            if let Ok(response) = client.check(request).await {
+                if response.into_inner().status == status as i32 {
+                    return;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        panic!("{:?} never reported {:?}", service, status);
+    }
+
+    #[tokio::test]
+    async fn health_follows_dependency_checks() {
+        let (reporter, health_server) = tonic_health::server::health_reporter();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(health_server)
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        let healthy = Arc::new(AtomicBool::new(true));
+        let dependency = healthy.clone();
+        let check = move || {
+            let healthy = dependency.load(Ordering::SeqCst);
+            async move {
+                match healthy {
+                    true => Ok(()),
+                    false => Err(SynxServerError::HealthCheckTimeoutError),
+                }
+            }
+        };
+        tokio::spawn(report_health::<TestService, _, _>(
+            check,
+            reporter,
+            Duration::from_millis(10),
+        ));
+
+        let channel = Endpoint::from_shared(format!("http://{}", address))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let mut client = HealthClient::new(channel);
+        for service in ["", TestService::NAME] {
+            let serving = health_check_response::ServingStatus::Serving;
+            wait_for_status(&mut client, service, serving).await;
+        }
+
+        // A failing dependency takes the server out of service.
+        healthy.store(false, Ordering::SeqCst);
+        for service in ["", TestService::NAME] {
+            let not_serving = health_check_response::ServingStatus::NotServing;
+            wait_for_status(&mut client, service, not_serving).await;
+        }
+    }
+}
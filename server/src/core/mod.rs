@@ -2,6 +2,7 @@ pub mod auth;
 pub mod config;
 pub mod definitions;
 pub mod errors;
+pub mod health;
 pub mod path_resolver;
 pub mod server;
 pub mod store_v1;
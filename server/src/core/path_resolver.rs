@@ -3,35 +3,50 @@ const ZIP_DIR: &str = "zips";
 const TEMP_DIR: &str = "temp";
 const WIP_UPLOADS_DIR: &str = "wip_uploads";
 const WIP_DOWNLOADS_DIR: &str = "wip_downloads";
+const UPLOAD_SESSIONS_DIR: &str = "upload_sessions";
 
-///Returns the object name for a file (`file_name`) in google cloud storage
+///Returns the object name for a file (`file_name`) of batch `batch_id` in google cloud storage
 /// `backup` folder.
-pub fn gcs_backup_object_name(id: &str, file_name: &str) -> String {
-    format!("{}/{}/{}", GCS_BACKUP_DIR, id, file_name)
+pub fn gcs_backup_object_name(id: &str, batch_id: &str, file_name: &str) -> String {
+    format!("{}/{}/{}/{}", GCS_BACKUP_DIR, id, batch_id, file_name)
 }
 
-///Returns the object name for a zip file in google cloud storage
+///Returns the object name for the zip file of batch `batch_id` in google cloud storage
 /// `zips` folder
-pub fn gcs_zip_file_object_name(id: &str) -> String {
-    format!("{}/{}.zip", ZIP_DIR, id)
+pub fn gcs_zip_file_object_name(id: &str, batch_id: &str) -> String {
+    format!("{}/{}/{}.zip", ZIP_DIR, id, batch_id)
 }
 
 pub fn local_merkle_tree_path() -> String {
     format!("{}/merkle_trees", TEMP_DIR)
 }
 
-pub fn local_merkle_tree_file(id: &str) -> String {
-    format!("{}_mtree.txt", id)
+pub fn local_merkle_tree_file(id: &str, batch_id: &str) -> String {
+    format!("{}_{}_mtree.txt", id, batch_id)
 }
 
 pub fn local_zip_dir() -> String {
     format!("{}/{}", TEMP_DIR, ZIP_DIR)
 }
 
-pub fn wip_uploads_dir(id: &str) -> String {
-    format!("{}/{}/{}", TEMP_DIR, WIP_UPLOADS_DIR, id)
+pub fn wip_uploads_dir(id: &str, batch_id: &str) -> String {
+    format!("{}/{}/{}/{}", TEMP_DIR, WIP_UPLOADS_DIR, id, batch_id)
 }
 
-pub fn wip_downloads_dir(id: &str) -> String {
-    format!("{}/{}/{}", TEMP_DIR, WIP_DOWNLOADS_DIR, id)
+pub fn wip_downloads_dir(id: &str, batch_id: &str) -> String {
+    format!("{}/{}/{}/{}", TEMP_DIR, WIP_DOWNLOADS_DIR, id, batch_id)
+}
+
+/// Returns whether `batch_id` is safe to use in the paths and object names above.
+pub fn is_valid_batch_id(batch_id: &str) -> bool {
+    !batch_id.is_empty() && !batch_id.contains(['/', '\\']) && !batch_id.contains("..")
+}
+
+pub fn local_upload_sessions_dir() -> String {
+    format!("{}/{}", TEMP_DIR, UPLOAD_SESSIONS_DIR)
+}
+
+/// Returns the path of the partially received archive for upload session `id`.
+pub fn local_upload_session_file(id: &str) -> String {
+    format!("{}/{}.part", local_upload_sessions_dir(), id)
 }
@@ -1,19 +1,34 @@
 extern crate common;
 
 use common::{
-    common::file_to_bytes,
+    common::{
+        chunk_leaf_bytes, file_chunks, file_to_bytes, generate_chunk_merkle_tree,
+        join_relative_path, normalize_relative_path, MAX_CHALLENGE_CHUNKS,
+    },
     syncx::{
-        syncx_server::Syncx, CreateClientRequest, CreateClientResponse, FileDownloadRequest,
-        FileDownloadResponse, FileUploadRequest, FileUploadResponse, MerkleProof, MerkleProofNode,
+        self, files_download_response::Frame, proof_request::Leaf, restore_response,
+        syncx_server::Syncx, BeginUploadRequest, BeginUploadResponse, ChallengeRequest,
+        ChallengeResponse, ChunkResponse, CommitUploadRequest, CommitUploadResponse,
+        CreateClientRequest, CreateClientResponse, FileDownloadRequest, FileDownloadResponse,
+        FileEntry, FileHeader, FileMetadata, FileUploadRequest, FileUploadResponse,
+        FilesDownloadRequest, FilesDownloadResponse, JobStatus, JobStatusRequest, ListFilesRequest,
+        ListFilesResponse, LoginRequest, LoginResponse, MerkleMultiProof, MerkleProof,
+        MerkleProofNode, MultiProofNode, ProofRequest, ProofResponse, QueryUploadRequest,
+        QueryUploadResponse, RestoreFileHeader, RestoreRequest, RestoreResponse, RootRequest,
+        RootResponse,
     },
 };
 use merkle_tree::{merkle_tree::MerkleTree, utils::hash_bytes};
+use sha2::{Digest, Sha256};
 
 use log::{debug, error, info};
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::fs::File;
-use std::io::Write;
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status};
@@ -22,7 +37,10 @@ use uuid::Uuid;
 use super::{
     auth,
     config::Config,
-    definitions::{ClientObject, Result, Store, TEMP_DIR, WIP_DOWNLOADS_DIR},
+    definitions::{
+        ClientObject, Job, JobRecord, JobState, Result, Store, UploadSession, DOWNLOAD_CHUNK_SIZE,
+        JOB_WATCH_INTERVAL_MILLIS,
+    },
     errors::SynxServerError,
     path_resolver::*,
     utils::*,
@@ -30,66 +48,333 @@ use super::{
 
 #[derive(Debug)]
 pub struct Server<T> {
-    store: T,
+    store: Arc<T>,
     config: Config,
 }
 
+#[allow(clippy::result_large_err)]
 impl<T: Store> Server<T> {
     pub async fn new(store: T, config: Config) -> Self
     where
         T: Store + Send + Sync + 'static,
     {
-        Self { store, config }
+        Self {
+            store: Arc::new(store),
+            config,
+        }
+    }
+
+    /// Verifies `jwt` and returns the id of the client it was issued to.
+    fn authorize(&self, jwt: &str) -> std::result::Result<String, Status> {
+        auth::jwt::verify_jwt(jwt, &self.config.jwt_secret)
+            .map(|claims| claims.sub)
+            .map_err(|_| {
+                error!("Un-authorized access with JWT {}", jwt);
+                Status::internal("Authorization failed")
+            })
+    }
+
+    /// Fetches upload session `upload_id`, making sure it belongs to client `uid`.
+    fn upload_session(
+        &self,
+        upload_id: &str,
+        uid: &str,
+    ) -> std::result::Result<UploadSession, Status> {
+        let session = self
+            .store
+            .get_upload_session(upload_id)
+            .map_err(|e| {
+                error!("Error fetching upload session {}. Error {}", upload_id, e);
+                Status::internal("Internal server error")
+            })?
+            .ok_or_else(|| Status::not_found(format!("Upload {} not found", upload_id)))?;
+
+        if session.client_id != uid {
+            error!("Client #{} attempted to access upload {}", uid, upload_id);
+            return Err(Status::permission_denied(format!(
+                "Upload {} does not belong to client",
+                upload_id
+            )));
+        }
+
+        Ok(session)
     }
 
-    fn file_exists(&self, file_name: &str, id: &str) -> Result<Option<String>> {
+    /// Fetches the record of the job processing batch `batch_id`, making sure it belongs to client `uid`.
+    fn job_record(&self, batch_id: &str, uid: &str) -> std::result::Result<JobRecord, Status> {
+        let record = self
+            .store
+            .get_job_record(batch_id)
+            .map_err(|e| {
+                error!("Error fetching job record {}. Error {}", batch_id, e);
+                Status::internal("Internal server error")
+            })?
+            .ok_or_else(|| Status::not_found(format!("No job found for batch {}", batch_id)))?;
+
+        if record.client_id != uid {
+            error!("Client #{} attempted to access job {}", uid, batch_id);
+            return Err(Status::permission_denied(format!(
+                "Batch {} does not belong to client",
+                batch_id
+            )));
+        }
+
+        Ok(record)
+    }
+
+    /// Returns the number of bytes received so far for upload session `id`.
+    fn bytes_received(id: &str) -> u64 {
+        fs::metadata(local_upload_session_file(id))
+            .map(|metadata| metadata.len())
+            .unwrap_or(0)
+    }
+
+    /// Checks the base batch of an incremental upload: it must belong to client `uid`
+    /// and hold every file carried over from it. Returns the base batch, if there
+    /// is one, and the normalized names of the carried over files.
+    fn check_base_batch(
+        &self,
+        uid: &str,
+        base_batch_id: &str,
+        unchanged_files: Vec<String>,
+    ) -> std::result::Result<(Option<String>, Vec<String>), Status> {
+        if base_batch_id.is_empty() {
+            return match unchanged_files.is_empty() {
+                true => Ok((None, unchanged_files)),
+                false => Err(Status::invalid_argument(
+                    "Unchanged files require a base batch",
+                )),
+            };
+        }
+
+        let base_files = self
+            .batch_files(uid, base_batch_id)
+            .map_err(|e| {
+                error!(
+                    "Error listing files of batch {}. Error {}",
+                    base_batch_id, e
+                );
+                Status::internal("Internal server error")
+            })?
+            .ok_or_else(|| Status::not_found(format!("Batch {} not found", base_batch_id)))?
+            .into_iter()
+            .collect::<HashSet<String>>();
+
+        let mut file_names = Vec::with_capacity(unchanged_files.len());
+        for file_name in unchanged_files {
+            let file_name = Self::normalize_file_name(&file_name)?;
+            if !base_files.contains(&file_name) {
+                return Err(Status::invalid_argument(format!(
+                    "File {} is not part of batch {}",
+                    file_name, base_batch_id
+                )));
+            }
+            file_names.push(file_name);
+        }
+
+        Ok((Some(base_batch_id.to_string()), file_names))
+    }
+
+    /// Normalizes a file path sent by a client, rejecting any that could escape the
+    /// client's own files.
+    fn normalize_file_name(file_name: &str) -> std::result::Result<String, Status> {
+        normalize_relative_path(file_name)
+            .ok_or_else(|| Status::invalid_argument(format!("Invalid file path {}", file_name)))
+    }
+
+    /// Returns the batch holding `file_name`: `batch_id` if the file was uploaded in it,
+    /// or the latest batch containing the file when `batch_id` is empty.
+    fn file_batch(&self, file_name: &str, id: &str, batch_id: &str) -> Result<Option<String>> {
+        if batch_id.is_empty() {
+            return self
+                .store
+                .fetch_from_cache(&latest_file_key(id, file_name))
+                .map_err(|e| SynxServerError::RedisCMDError(e.to_string()));
+        }
+
         Ok(self
             .store
-            .fetch_from_cache(&hash_str(&format!("{}{}", id, &file_name)))
-            .map_err(|e| SynxServerError::RedisCMDError(e.to_string()))?)
+            .fetch_from_cache(&batch_file_key(id, batch_id, file_name))
+            .map_err(|e| SynxServerError::RedisCMDError(e.to_string()))?
+            .map(|_| batch_id.to_string()))
     }
 
-    async fn download_file(&self, id: &str, file_name: &str) -> Result<Vec<(String, PathBuf)>> {
-        let wip_dir = wip_downloads_dir(id);
-        let download_path = Path::new(&wip_dir);
+    /// Returns the content hash of `file_name` in batch `batch_id`, which is the file's
+    /// leaf in the batch's merkle tree.
+    fn file_leaf(&self, file_name: &str, id: &str, batch_id: &str) -> Result<Option<String>> {
+        self.store
+            .fetch_from_cache(&batch_file_key(id, batch_id, file_name))
+            .map_err(|e| SynxServerError::RedisCMDError(e.to_string()))
+    }
 
-        let _ = ensure_directory_exists(&download_path.to_path_buf())?;
+    /// Returns the batch whose storage holds `file_name` of batch `batch_id`. That is
+    /// the batch itself, unless an incremental upload carried the file over.
+    fn file_source(&self, id: &str, batch_id: &str, file_name: &str) -> Result<String> {
+        Ok(self
+            .store
+            .fetch_from_cache(&file_source_key(id, batch_id, file_name))
+            .map_err(|e| SynxServerError::RedisCMDError(e.to_string()))?
+            .unwrap_or_else(|| batch_id.to_string()))
+    }
 
-        let obj_name_1 = gcs_backup_object_name(id, file_name);
-        let path_1 = download_path.join(&file_name);
+    /// Returns the batch client `id` uploaded most recently.
+    fn latest_batch(&self, id: &str) -> Result<Option<String>> {
+        self.store
+            .fetch_from_cache(&latest_batch_key(id))
+            .map_err(|e| SynxServerError::RedisCMDError(e.to_string()))
+    }
 
-        let merkle_file_name = local_merkle_tree_file(id);
-        let obj_name_2 = gcs_backup_object_name(id, &merkle_file_name);
-        let path_2 = download_path.join("merkletree.txt");
+    /// Returns the lineage batch `batch_id` of client `id` belongs to, if one was recorded.
+    fn batch_lineage(&self, id: &str, batch_id: &str) -> Result<Option<String>> {
+        self.store
+            .fetch_from_cache(&batch_lineage_key(id, batch_id))
+            .map_err(|e| SynxServerError::RedisCMDError(e.to_string()))
+    }
 
-        let files_and_download_path = vec![(obj_name_1, path_1), (obj_name_2, path_2)];
+    /// Returns the latest processed batch of lineage `lineage` of client `id`.
+    fn lineage_batch(&self, id: &str, lineage: &str) -> std::result::Result<String, Status> {
+        if !is_valid_batch_id(lineage) {
+            return Err(Status::invalid_argument(format!(
+                "Invalid lineage {}",
+                lineage
+            )));
+        }
 
-        for (name, path) in &files_and_download_path {
-            let _ = download_file(
-                &name,
-                &self.config.gcs_bucket_name,
-                &self.config.api_key,
-                path.as_path(),
-            )
-            .await?;
+        self.store
+            .fetch_from_cache(&latest_lineage_batch_key(id, lineage))
+            .map_err(|e| {
+                error!("Error resolving lineage of client #{}. Error {}", id, e);
+                Status::internal("Internal server error")
+            })?
+            .ok_or_else(|| Status::not_found(format!("No batches found in lineage {}", lineage)))
+    }
+
+    /// Returns the batch a request refers to: `batch_id`, which must be a processed
+    /// batch of client `id`, or the client's latest batch when `batch_id` is empty.
+    fn resolve_batch(&self, id: &str, batch_id: &str) -> std::result::Result<String, Status> {
+        let internal_error = |e: SynxServerError| {
+            error!("Error resolving batch of client #{}. Error {}", id, e);
+            Status::internal("Internal server error")
+        };
+
+        if batch_id.is_empty() {
+            return self
+                .latest_batch(id)
+                .map_err(internal_error)?
+                .ok_or_else(|| Status::not_found("No uploaded batches found"));
         }
 
-        Ok(files_and_download_path)
+        // Batch ids end up in storage paths, so they are checked before anything else.
+        if !is_valid_batch_id(batch_id) {
+            return Err(Status::invalid_argument(format!(
+                "Invalid batch id {}",
+                batch_id
+            )));
+        }
+
+        self.batch_files(id, batch_id)
+            .map_err(internal_error)?
+            .ok_or_else(|| Status::not_found(format!("Batch {} not found", batch_id)))?;
+
+        Ok(batch_id.to_string())
     }
 
-    fn generate_merkle_proof(
-        &self,
-        files_and_download_path: Vec<(String, PathBuf)>,
-        download_bytes: &Vec<u8>,
-    ) -> Result<Vec<MerkleProofNode>> {
-        let merkle_tree_bytes = file_to_bytes(&files_and_download_path[1].1)
-            .map_err(|_| SynxServerError::ConvertFileToBytesError)?;
+    /// Returns the names of the files uploaded in batch `batch_id`.
+    fn batch_files(&self, id: &str, batch_id: &str) -> Result<Option<Vec<String>>> {
+        let Some(file_names) = self
+            .store
+            .fetch_from_cache(&batch_files_key(id, batch_id))
+            .map_err(|e| SynxServerError::RedisCMDError(e.to_string()))?
+        else {
+            return Ok(None);
+        };
 
-        let merkle_tree = MerkleTree::from_bytes(&merkle_tree_bytes)
-            .map_err(|_| SynxServerError::MerkleTreeGenerationError)?;
+        serde_json::from_str(&file_names)
+            .map(Some)
+            .map_err(|e| SynxServerError::DeserializationError(e.to_string()))
+    }
+
+    /// Returns the metadata recorded for `file_name` in batch `batch_id`. Metadata is
+    /// optional, so a failed lookup is logged rather than failing the request.
+    fn file_metadata(&self, id: &str, batch_id: &str, file_name: &str) -> Option<FileMetadata> {
+        self.cached_json(&file_metadata_key(id, batch_id, file_name))
+    }
+
+    /// Returns the symbolic links recorded for batch `batch_id`.
+    fn batch_symlinks(&self, id: &str, batch_id: &str) -> Vec<FileMetadata> {
+        self.cached_json(&batch_symlinks_key(id, batch_id))
+            .unwrap_or_default()
+    }
+
+    fn cached_json<V: serde::de::DeserializeOwned>(&self, key: &str) -> Option<V> {
+        let value = match self.store.fetch_from_cache(key) {
+            Ok(value) => value?,
+            Err(e) => {
+                error!("Error reading {} from cache. Error {}", key, e);
+                return None;
+            }
+        };
+
+        serde_json::from_str(&value)
+            .map_err(|e| error!("Error parsing {} from cache. Error {}", key, e))
+            .ok()
+    }
+
+    async fn download_file(&self, id: &str, batch_id: &str, file_name: &str) -> Result<PathBuf> {
+        let wip_dir = wip_downloads_dir(id, batch_id);
+        let download_path = Path::new(&wip_dir);
+
+        let _ = ensure_directory_exists(&download_path.to_path_buf())?;
+
+        let source_batch_id = self.file_source(id, batch_id, file_name)?;
+        let object_name = gcs_backup_object_name(id, &source_batch_id, file_name);
+        let path = join_relative_path(download_path, file_name)
+            .ok_or_else(|| SynxServerError::FileNotFound(file_name.to_string()))?;
+
+        if let Some(parent) = path.parent() {
+            ensure_directory_exists(&parent.to_path_buf())?;
+        }
+
+        download_file(
+            &object_name,
+            &self.config.gcs_bucket_name,
+            &self.config.api_key,
+            path.as_path(),
+        )
+        .await?;
+
+        Ok(path)
+    }
+
+    /// Downloads the merkle tree of batch `batch_id` from storage.
+    async fn fetch_merkle_tree(&self, id: &str, batch_id: &str) -> Result<MerkleTree> {
+        let wip_dir = wip_downloads_dir(id, batch_id);
+        let download_path = Path::new(&wip_dir);
+
+        let _ = ensure_directory_exists(&download_path.to_path_buf())?;
+
+        let merkle_file_name = local_merkle_tree_file(id, batch_id);
+        let object_name = gcs_backup_object_name(id, batch_id, &merkle_file_name);
+        let path = download_path.join(&merkle_file_name);
+
+        download_file(
+            &object_name,
+            &self.config.gcs_bucket_name,
+            &self.config.api_key,
+            path.as_path(),
+        )
+        .await?;
+
+        let merkle_tree_bytes =
+            file_to_bytes(&path).map_err(|_| SynxServerError::ConvertFileToBytesError)?;
 
+        MerkleTree::from_bytes(&merkle_tree_bytes)
+            .map_err(|_| SynxServerError::DeserializeTreeError)
+    }
+
+    fn generate_merkle_proof(merkle_tree: &MerkleTree, leaf: &str) -> Result<Vec<MerkleProofNode>> {
         let merkle_proof = merkle_tree
-            .generate_merkle_proof(&hash_bytes(&download_bytes))
+            .generate_merkle_proof(leaf)
             .map_err(|_| SynxServerError::MerkleTreeGenerationError)?;
 
         Ok(merkle_proof
@@ -108,6 +393,9 @@ where
     T: Store + Send + Sync + 'static,
 {
     type DownloadFileStream = ReceiverStream<std::result::Result<FileDownloadResponse, Status>>;
+    type DownloadFilesStream = ReceiverStream<std::result::Result<FilesDownloadResponse, Status>>;
+    type RestoreBatchStream = ReceiverStream<std::result::Result<RestoreResponse, Status>>;
+    type WatchJobStream = ReceiverStream<std::result::Result<JobStatus, Status>>;
 
     async fn register_client(
         &self,
@@ -151,101 +439,370 @@ where
         Ok(Response::new(response))
     }
 
+    async fn login(
+        &self,
+        request: Request<LoginRequest>,
+    ) -> std::result::Result<Response<LoginResponse>, Status> {
+        let LoginRequest { id, password } = request.into_inner();
+        info!("New login request for account #{}", id);
+
+        let client_object = self.store.get_client_object(&id).await.map_err(|e| {
+            error!("Error fetching client object #{}. Error {}", id, e);
+            Status::internal("Failed to fetch client object")
+        })?;
+
+        let valid = match client_object {
+            Some(client_object) => {
+                auth::hash_utils::verify_password(&password, &client_object.password).map_err(
+                    |e| {
+                        error!("Error verifying password of account #{}. Error {}", id, e);
+                        Status::internal("Failed to verify password")
+                    },
+                )?
+            }
+            None => false,
+        };
+
+        if !valid {
+            error!("Failed login for account #{}", id);
+            return Err(Status::unauthenticated("Invalid account id or password"));
+        }
+
+        let jwt_token = auth::jwt::create_jwt(&id, &self.config.jwt_secret, self.config.jwt_exp)
+            .map_err(|e| {
+                error!("Failed to generate JWT for account #{}. Error {}", id, e);
+                Status::internal("Failed to create auth token")
+            })?;
+
+        debug!("Account #{} logged in", id);
+
+        Ok(Response::new(LoginResponse { jwt_token }))
+    }
+
+    async fn begin_upload(
+        &self,
+        request: Request<BeginUploadRequest>,
+    ) -> std::result::Result<Response<BeginUploadResponse>, Status> {
+        let BeginUploadRequest {
+            jwt,
+            size,
+            base_batch_id,
+            unchanged_files,
+            lineage,
+        } = request.into_inner();
+        let uid = self.authorize(&jwt)?;
+
+        let (base_batch_id, unchanged_files) =
+            self.check_base_batch(&uid, &base_batch_id, unchanged_files)?;
+
+        // Lineages are named after the batch that started them.
+        if !lineage.is_empty() && !is_valid_batch_id(&lineage) {
+            return Err(Status::invalid_argument(format!(
+                "Invalid lineage {}",
+                lineage
+            )));
+        }
+
+        let id = Uuid::new_v4().to_string();
+        let session = UploadSession {
+            lineage: match lineage.is_empty() {
+                true => id.clone(),
+                false => lineage,
+            },
+            id,
+            client_id: uid,
+            size,
+            checksum: None,
+            base_batch_id,
+            unchanged_files,
+        };
+
+        info!(
+            "Client #{} started upload {} of {} bytes",
+            session.client_id, session.id, size
+        );
+
+        ensure_directory_exists(&PathBuf::from(local_upload_sessions_dir())).map_err(|_| {
+            error!("Error creating local upload sessions dir");
+            Status::internal("Internal server error")
+        })?;
+
+        File::create(local_upload_session_file(&session.id))?;
+
+        self.store.save_upload_session(&session).map_err(|e| {
+            error!("Error saving upload session {}. Error {}", session.id, e);
+            Status::internal("Internal server error")
+        })?;
+
+        Ok(Response::new(BeginUploadResponse {
+            upload_id: session.id,
+        }))
+    }
+
+    async fn query_upload(
+        &self,
+        request: Request<QueryUploadRequest>,
+    ) -> std::result::Result<Response<QueryUploadResponse>, Status> {
+        let QueryUploadRequest { jwt, upload_id } = request.into_inner();
+        let uid = self.authorize(&jwt)?;
+        let session = self.upload_session(&upload_id, &uid)?;
+
+        // An archive that is complete but unverified, as when the stream broke
+        // before its end was checked, is reported one byte short. The client then
+        // resends its last chunk, which carries the checksum.
+        let mut bytes_received = Self::bytes_received(&session.id);
+        if bytes_received == session.size && session.checksum.is_none() {
+            bytes_received = bytes_received.saturating_sub(1);
+        }
+
+        Ok(Response::new(QueryUploadResponse {
+            bytes_received,
+            size: session.size,
+        }))
+    }
+
     async fn upload_files(
         &self,
         request: tonic::Request<tonic::Streaming<FileUploadRequest>>,
     ) -> std::result::Result<Response<FileUploadResponse>, Status> {
-        let mut uid = String::new();
-        let mut first_chunk = true;
-        info!("New client #{}request to upload files", uid);
-
-        let local_zip_dir = local_zip_dir();
-        let zip_dir = Path::new(&local_zip_dir);
-        let _ = ensure_directory_exists(&zip_dir.to_path_buf()).map_err(|_| {
-            error!("Error creating local zip dir");
-            Status::internal("Authorization failed")
-        });
-
-        let mut file: Option<File> = None;
-        let mut zip_path: Option<PathBuf> = None;
+        let mut upload: Option<(UploadSession, File, Sha256)> = None;
+        let mut position = 0;
+        // Sent by the client with the chunk that completes the archive.
+        let mut checksum = None;
 
         let mut stream = request.into_inner();
-        info!("Streaming and recreating file {}.zip", uid);
 
         while let Some(chunk) = stream.message().await? {
-            if first_chunk {
-                match auth::jwt::verify_jwt(&chunk.jwt, &self.config.jwt_secret) {
-                    Ok(claims) => {
-                        uid = claims.sub;
-
-                        // Create the inner directory within the outer directory
-                        let file_path = zip_dir.join(format!("{}.zip", uid));
-
-                        file = Some(
-                            fs::OpenOptions::new()
-                                .append(true)
-                                .create(true)
-                                .open(&file_path)?,
-                        );
+            if upload.is_none() {
+                let uid = self.authorize(&chunk.jwt)?;
+                let session = self.upload_session(&chunk.upload_id, &uid)?;
 
-                        debug!("Zip file created {:?}", file_path);
+                let mut file = fs::OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(local_upload_session_file(&session.id))?;
 
-                        zip_path = Some(file_path);
-                    }
-                    Err(_) => {
-                        error!("Un-authorized access with JWT {}", &chunk.jwt);
-                        return Err(Status::internal("Authorization failed"));
-                    }
-                };
-                first_chunk = false;
+                // A resumed upload may start before the end of what we already have,
+                // e.g. when the last chunks of an interrupted stream were lost.
+                // Anything past the client's offset is discarded and rewritten.
+                let received = file.metadata()?.len();
+                if chunk.offset > received {
+                    return Err(Status::out_of_range(format!(
+                        "Offset {} is past the {} bytes received",
+                        chunk.offset, received
+                    )));
+                }
+
+                // The archive is hashed as it is written. When resuming, the bytes
+                // kept from earlier streams are hashed first.
+                let hasher = sha256_of_prefix(&mut file, chunk.offset)?;
+
+                file.set_len(chunk.offset)?;
+                file.seek(SeekFrom::Start(chunk.offset))?;
+                position = chunk.offset;
+
+                info!(
+                    "Client #{} streaming upload {} from offset {}",
+                    uid, session.id, position
+                );
+
+                upload = Some((session, file, hasher));
             }
 
-            if let Some(ref mut f) = file {
-                f.write_all(&chunk.content)?;
-            } else {
+            let Some((session, file, hasher)) = upload.as_mut() else {
                 return Err(Status::internal("File not initialized"));
+            };
+
+            if chunk.offset != position {
+                return Err(Status::invalid_argument(format!(
+                    "Expected chunk at offset {}, got {}",
+                    position, chunk.offset
+                )));
+            }
+
+            let end = position + chunk.content.len() as u64;
+            if end > session.size {
+                return Err(Status::out_of_range(format!(
+                    "Upload {} exceeds its declared size of {} bytes",
+                    session.id, session.size
+                )));
+            }
+
+            // Checked before the chunk is written, so the upload can still be
+            // completed by a stream that sends the checksum.
+            if end == session.size {
+                if chunk.checksum.is_empty() {
+                    return Err(Status::invalid_argument(
+                        "The last chunk of the upload carries no checksum",
+                    ));
+                }
+                checksum = Some(chunk.checksum);
             }
+
+            file.write_all(&chunk.content)?;
+            hasher.update(&chunk.content);
+            position = end;
+        }
+
+        let Some((mut session, file, hasher)) = upload else {
+            return Err(Status::invalid_argument("Upload stream is empty"));
+        };
+
+        if position == session.size {
+            let checksum = checksum.unwrap_or_default();
+            let computed = hex::encode(hasher.finalize());
+
+            if computed != checksum {
+                error!(
+                    "Checksum mismatch for upload {}: expected {}, computed {}",
+                    session.id, checksum, computed
+                );
+
+                // A corrupted archive can't be resumed, so start it over.
+                file.set_len(0)?;
+
+                return Err(Status::data_loss(format!(
+                    "Checksum mismatch for upload {}: expected {}, computed {}",
+                    session.id, checksum, computed
+                )));
+            }
+
+            session.checksum = Some(computed);
+            self.store.save_upload_session(&session).map_err(|e| {
+                error!("Error saving upload session {}. Error {}", session.id, e);
+                Status::internal("Internal server error")
+            })?;
+
+            info!("Upload {} complete and verified", session.id);
         }
 
         let response = FileUploadResponse {
-            message: "File uploaded successfully".into(),
+            message: "Chunks received".into(),
+            bytes_received: position,
         };
 
+        Ok(Response::new(response))
+    }
+
+    async fn commit_upload(
+        &self,
+        request: Request<CommitUploadRequest>,
+    ) -> std::result::Result<Response<CommitUploadResponse>, Status> {
+        let CommitUploadRequest { jwt, upload_id } = request.into_inner();
+        let uid = self.authorize(&jwt)?;
+        let session = self.upload_session(&upload_id, &uid)?;
+
+        let received = Self::bytes_received(&session.id);
+        if received != session.size {
+            return Err(Status::failed_precondition(format!(
+                "Upload {} is incomplete: {} of {} bytes received",
+                session.id, received, session.size
+            )));
+        }
+
+        if session.checksum.is_none() {
+            return Err(Status::failed_precondition(format!(
+                "Upload {} has not passed checksum verification",
+                session.id
+            )));
+        }
+
+        let local_zip_dir = local_zip_dir();
+        let zip_dir = Path::new(&local_zip_dir);
+        ensure_directory_exists(&zip_dir.to_path_buf()).map_err(|_| {
+            error!("Error creating local zip dir");
+            Status::internal("Internal server error")
+        })?;
+
+        // Every upload becomes a batch of its own, so archives of earlier
+        // uploads are never overwritten. The archive is only moved out of the
+        // session once it is stored, so a failed commit can be retried as is.
+        let batch_id = session.id.clone();
+        let session_file = local_upload_session_file(&session.id);
+
         upload_file(
-            &zip_path.unwrap(),
+            Path::new(&session_file),
             &uid,
             &self.config.api_key,
             &self.config.gcs_bucket_name,
-            &gcs_zip_file_object_name(&uid),
+            &gcs_zip_file_object_name(&uid, &batch_id),
         )
         .await
-        .unwrap();
+        .map_err(|e| {
+            error!("Error uploading zip of client #{}. Error {}", uid, e);
+            Status::internal("Internal server error")
+        })?;
 
-        let _ = self.store.enqueue_job(&uid);
-        info!("New job <{}> queued", uid);
+        let zip_path = zip_dir.join(format!("{}.zip", batch_id));
+        fs::rename(&session_file, &zip_path)?;
+        debug!("Zip file created {:?}", zip_path);
 
-        Ok(Response::new(response))
+        if let Err(e) = self.store.delete_upload_session(&session.id) {
+            error!("Error deleting upload session {}. Error {}", session.id, e);
+        }
+
+        let job = Job {
+            client_id: uid,
+            batch_id,
+            base_batch_id: session.base_batch_id,
+            unchanged_files: session.unchanged_files,
+            lineage: session.lineage,
+        };
+
+        // Record the job before queueing it, so the worker's updates always come after.
+        self.store
+            .save_job_record(&JobRecord::new(&job, JobState::Queued))
+            .map_err(|e| {
+                error!("Error saving job record {}. Error {}", job.batch_id, e);
+                Status::internal("Internal server error")
+            })?;
+
+        if let Err(e) = self.store.enqueue_job(&job) {
+            error!("Error queueing job {}. Error {}", job.batch_id, e);
+
+            // Nothing will pick the job up, so it must not be left looking queued.
+            let mut record = JobRecord::new(&job, JobState::Failed);
+            record.reason = format!("Failed to queue the job: {}", e);
+            if let Err(e) = self.store.save_job_record(&record) {
+                error!("Error saving job record {}. Error {}", job.batch_id, e);
+            }
+
+            return Err(Status::internal("Internal server error"));
+        }
+        info!("New job <{}:{}> queued", job.client_id, job.batch_id);
+
+        Ok(Response::new(CommitUploadResponse {
+            message: "File uploaded successfully".into(),
+            batch_id: job.batch_id,
+        }))
     }
 
     async fn download_file(
         &self,
         request: tonic::Request<FileDownloadRequest>,
     ) -> std::result::Result<Response<Self::DownloadFileStream>, Status> {
-        let FileDownloadRequest { jwt, file_name } = request.into_inner();
+        let FileDownloadRequest {
+            jwt,
+            file_name,
+            offset,
+            length,
+            batch_id,
+        } = request.into_inner();
+        let file_name = Self::normalize_file_name(&file_name)?;
 
         match auth::jwt::verify_jwt(&jwt, &self.config.jwt_secret) {
             Ok(claims) => {
-                let exists = self
-                    .file_exists(&file_name, &claims.sub)
+                let batch_id = self
+                    .file_batch(&file_name, &claims.sub, &batch_id)
                     .map_err(|_| Status::internal("Internal server error"))?;
 
                 // If file does not exists in cache, it means user has not uploaded such file.
-                if exists.is_none() {
+                let Some(batch_id) = batch_id else {
                     return Err(Status::internal(format!("File {} not found", file_name)));
-                }
+                };
 
-                let files_and_download_path = self
-                    .download_file(&claims.sub, &file_name)
+                let file_path = self
+                    .download_file(&claims.sub, &batch_id, &file_name)
                     .await
                     .map_err(|_| {
                         error!(
@@ -255,32 +812,100 @@ where
                         Status::internal("Internal server error")
                     })?;
 
-                let content = file_to_bytes(&files_and_download_path[0].1)
-                    .map_err(|_| Status::internal("Internal server error"))?;
+                // The leaf recorded at upload time is the file's content hash, so the
+                // file does not need to be read to prove it.
+                let leaf = self
+                    .file_leaf(&file_name, &claims.sub, &batch_id)
+                    .map_err(|_| Status::internal("Internal server error"))?
+                    .ok_or_else(|| Status::not_found(format!("File {} not found", file_name)))?;
 
-                let merkle_proof_nodes = self
-                    .generate_merkle_proof(files_and_download_path, &content)
-                    .map_err(|_| {
-                        error!("Error generating merkle proof for bytes {:?}", content);
+                let merkle_tree = self
+                    .fetch_merkle_tree(&claims.sub, &batch_id)
+                    .await
+                    .map_err(|e| {
+                        error!(
+                            "Error fetching merkle tree of batch {}. Error {}",
+                            batch_id, e
+                        );
+                        Status::internal("Internal server error")
+                    })?;
+
+                let merkle_proof_nodes =
+                    Self::generate_merkle_proof(&merkle_tree, &leaf).map_err(|_| {
+                        error!("Error generating merkle proof for leaf {}", leaf);
                         Status::internal("Internal server error")
                     })?;
 
                 let merkle_proof = Some(MerkleProof {
                     nodes: merkle_proof_nodes,
                 });
+                let metadata = self.file_metadata(&claims.sub, &batch_id, &file_name);
+
+                let mut file = File::open(&file_path).map_err(|e| {
+                    error!("Error opening file {:?}. Error {}", file_path, e);
+                    Status::internal("Internal server error")
+                })?;
+                let file_size = file
+                    .metadata()
+                    .map_err(|_| Status::internal("Internal server error"))?
+                    .len();
+                if offset > file_size {
+                    return Err(Status::out_of_range(format!(
+                        "Offset {} is past the end of {} ({} bytes)",
+                        offset, file_name, file_size
+                    )));
+                }
+
+                let end = match length {
+                    0 => file_size,
+                    length => file_size.min(offset.saturating_add(length)),
+                };
+
+                file.seek(SeekFrom::Start(offset))
+                    .map_err(|_| Status::internal("Internal server error"))?;
 
                 let (tx, rx) = mpsc::channel(4);
-                // Here, spawn a new task to handle file reading and streaming
+                // Here, spawn a new task to handle file reading and streaming. Only the
+                // requested range is read, one chunk at a time.
                 tokio::spawn(async move {
-                    let chunk = FileDownloadResponse {
-                        content,
-                        merkle_proof,
-                    };
+                    let mut merkle_proof = merkle_proof;
+                    let mut metadata = metadata;
+                    let mut chunk_offset = offset;
 
-                    let _ = tx.send(Ok(chunk)).await.map_err(|err| {
-                        error!("Error streaming chunk to client: Error {}", err);
-                        Status::internal("Internal server error")
-                    });
+                    // The proof always covers the whole file, so it is sent even when the
+                    // requested range is empty.
+                    loop {
+                        let chunk_end = end.min(chunk_offset + DOWNLOAD_CHUNK_SIZE);
+                        let content = match read_chunk(&mut file, chunk_end - chunk_offset) {
+                            Ok(content) => content,
+                            Err(e) => {
+                                error!("Error reading file {:?}. Error {}", file_path, e);
+                                let _ = tx
+                                    .send(Err(Status::internal("Internal server error")))
+                                    .await;
+                                break;
+                            }
+                        };
+
+                        let chunk = FileDownloadResponse {
+                            content,
+                            merkle_proof: merkle_proof.take(),
+                            offset: chunk_offset,
+                            file_size,
+                            batch_id: batch_id.clone(),
+                            metadata: metadata.take(),
+                        };
+
+                        if let Err(err) = tx.send(Ok(chunk)).await {
+                            error!("Error streaming chunk to client: Error {}", err);
+                            break;
+                        }
+
+                        chunk_offset = chunk_end;
+                        if chunk_offset >= end {
+                            break;
+                        }
+                    }
                 });
 
                 Ok(Response::new(Self::DownloadFileStream::new(rx)))
@@ -291,4 +916,671 @@ where
             }
         }
     }
+
+    async fn download_files(
+        &self,
+        request: Request<FilesDownloadRequest>,
+    ) -> std::result::Result<Response<Self::DownloadFilesStream>, Status> {
+        let FilesDownloadRequest {
+            jwt,
+            file_names,
+            pattern,
+            batch_id,
+        } = request.into_inner();
+        let uid = self.authorize(&jwt)?;
+
+        let internal_error = |e: SynxServerError| {
+            error!("Error downloading files for client #{}. Error {}", uid, e);
+            Status::internal("Internal server error")
+        };
+
+        // All files are proven against one tree, so they must come from a single batch.
+        let batch_id = self.resolve_batch(&uid, &batch_id)?;
+
+        let file_names = match (file_names.is_empty(), pattern.is_empty()) {
+            (false, true) => {
+                let mut normalized = Vec::with_capacity(file_names.len());
+                for file_name in &file_names {
+                    normalized.push(Self::normalize_file_name(file_name)?);
+                }
+                normalized
+            }
+            (true, false) => {
+                let pattern = glob::Pattern::new(&pattern)
+                    .map_err(|e| Status::invalid_argument(format!("Invalid pattern: {}", e)))?;
+
+                self.batch_files(&uid, &batch_id)
+                    .map_err(internal_error)?
+                    .ok_or_else(|| {
+                        Status::not_found(format!("No file list recorded for batch {}", batch_id))
+                    })?
+                    .into_iter()
+                    .filter(|file_name| pattern.matches(file_name))
+                    .collect()
+            }
+            _ => {
+                return Err(Status::invalid_argument(
+                    "Either file names or a pattern is required",
+                ))
+            }
+        };
+
+        let mut seen = HashSet::new();
+        let file_names = file_names
+            .into_iter()
+            .filter(|file_name| seen.insert(file_name.clone()))
+            .collect::<Vec<String>>();
+
+        if file_names.is_empty() {
+            return Err(Status::not_found(format!(
+                "No matching files in batch {}",
+                batch_id
+            )));
+        }
+
+        let mut leaves = Vec::with_capacity(file_names.len());
+        for file_name in &file_names {
+            let leaf = self
+                .file_leaf(file_name, &uid, &batch_id)
+                .map_err(internal_error)?
+                .ok_or_else(|| {
+                    Status::not_found(format!(
+                        "File {} not found in batch {}",
+                        file_name, batch_id
+                    ))
+                })?;
+            leaves.push(leaf);
+        }
+
+        // The tree is fetched once for the whole request.
+        let merkle_tree = self
+            .fetch_merkle_tree(&uid, &batch_id)
+            .await
+            .map_err(internal_error)?;
+
+        let multiproof = merkle_tree
+            .generate_multiproof(&leaves)
+            .map_err(|_| internal_error(SynxServerError::MerkleTreeGenerationError))?;
+
+        let mut file_paths = Vec::with_capacity(file_names.len());
+        for file_name in file_names {
+            let path = self
+                .download_file(&uid, &batch_id, &file_name)
+                .await
+                .map_err(internal_error)?;
+            let metadata = self.file_metadata(&uid, &batch_id, &file_name);
+            file_paths.push((file_name, path, metadata));
+        }
+
+        let merkle_proof = MerkleMultiProof {
+            leaf_count: multiproof.leaf_count as u64,
+            indices: multiproof.indices.iter().map(|i| *i as u64).collect(),
+            nodes: multiproof
+                .nodes
+                .into_iter()
+                .map(|(level, index, hash)| MultiProofNode {
+                    level: level as u64,
+                    index: index as u64,
+                    hash,
+                })
+                .collect(),
+        };
+
+        let (tx, rx) = mpsc::channel(4);
+        tokio::spawn(async move {
+            // Files are read from disk one chunk at a time as they are sent.
+            for (file_name, path, metadata) in file_paths {
+                let (mut file, size) = match open_file(&path) {
+                    Ok(file) => file,
+                    Err(e) => {
+                        error!("Error opening file {:?}. Error {}", path, e);
+                        let _ = tx
+                            .send(Err(Status::internal("Internal server error")))
+                            .await;
+                        return;
+                    }
+                };
+
+                let header = Frame::Header(FileHeader {
+                    file_name,
+                    size,
+                    metadata,
+                });
+                if !send_frame(&tx, header, &batch_id).await {
+                    return;
+                }
+
+                if !send_content(&tx, &mut file, size, &batch_id).await {
+                    return;
+                }
+            }
+
+            send_frame(&tx, Frame::MerkleProof(merkle_proof), &batch_id).await;
+        });
+
+        Ok(Response::new(Self::DownloadFilesStream::new(rx)))
+    }
+
+    async fn restore_batch(
+        &self,
+        request: Request<RestoreRequest>,
+    ) -> std::result::Result<Response<Self::RestoreBatchStream>, Status> {
+        let RestoreRequest { jwt, batch_id } = request.into_inner();
+        let uid = self.authorize(&jwt)?;
+
+        let internal_error = |e: SynxServerError| {
+            error!("Error restoring batch for client #{}. Error {}", uid, e);
+            Status::internal("Internal server error")
+        };
+
+        let batch_id = self.resolve_batch(&uid, &batch_id)?;
+
+        let file_names = self
+            .batch_files(&uid, &batch_id)
+            .map_err(internal_error)?
+            .ok_or_else(|| {
+                Status::not_found(format!("No file list recorded for batch {}", batch_id))
+            })?;
+
+        let merkle_tree = self
+            .fetch_merkle_tree(&uid, &batch_id)
+            .await
+            .map_err(internal_error)?;
+
+        // A file that can't be found, fetched or proven is reported as missing
+        // instead of failing the whole restore.
+        let mut files = Vec::with_capacity(file_names.len());
+        for file_name in file_names {
+            let restored = match self.file_leaf(&file_name, &uid, &batch_id) {
+                Ok(Some(leaf)) => match Self::generate_merkle_proof(&merkle_tree, &leaf) {
+                    Ok(nodes) => self
+                        .download_file(&uid, &batch_id, &file_name)
+                        .await
+                        .map(|path| (path, nodes)),
+                    Err(e) => Err(e),
+                },
+                Ok(None) => Err(SynxServerError::FileNotFound(file_name.clone())),
+                Err(e) => Err(e),
+            };
+
+            if let Err(e) = &restored {
+                error!(
+                    "Error restoring file {} of batch {}. Error {}",
+                    file_name, batch_id, e
+                );
+            }
+            let metadata = self.file_metadata(&uid, &batch_id, &file_name);
+            files.push((file_name, restored.ok(), metadata));
+        }
+        let symlinks = self.batch_symlinks(&uid, &batch_id);
+
+        let (tx, rx) = mpsc::channel(4);
+        tokio::spawn(async move {
+            // Files are read from disk one chunk at a time as they are sent.
+            for (file_name, restored, metadata) in files {
+                let opened = restored.and_then(|(path, nodes)| match open_file(&path) {
+                    Ok((file, size)) => Some((file, size, nodes)),
+                    Err(e) => {
+                        error!("Error opening file {:?}. Error {}", path, e);
+                        None
+                    }
+                });
+
+                let Some((mut file, size, nodes)) = opened else {
+                    let missing = restore_response::Frame::Missing(file_name);
+                    if !send_frame(&tx, missing, &batch_id).await {
+                        return;
+                    }
+                    continue;
+                };
+
+                let header = restore_response::Frame::Header(RestoreFileHeader {
+                    file_name,
+                    size,
+                    merkle_proof: Some(MerkleProof { nodes }),
+                    metadata,
+                });
+                if !send_frame(&tx, header, &batch_id).await {
+                    return;
+                }
+
+                if !send_content(&tx, &mut file, size, &batch_id).await {
+                    return;
+                }
+            }
+
+            for symlink in symlinks {
+                let symlink = restore_response::Frame::Symlink(symlink);
+                if !send_frame(&tx, symlink, &batch_id).await {
+                    return;
+                }
+            }
+        });
+
+        Ok(Response::new(Self::RestoreBatchStream::new(rx)))
+    }
+
+    async fn get_job_status(
+        &self,
+        request: Request<JobStatusRequest>,
+    ) -> std::result::Result<Response<JobStatus>, Status> {
+        let JobStatusRequest { jwt, batch_id } = request.into_inner();
+        let uid = self.authorize(&jwt)?;
+
+        let record = self.job_record(&batch_id, &uid)?;
+
+        Ok(Response::new(job_status(record)))
+    }
+
+    async fn watch_job(
+        &self,
+        request: Request<JobStatusRequest>,
+    ) -> std::result::Result<Response<Self::WatchJobStream>, Status> {
+        let JobStatusRequest { jwt, batch_id } = request.into_inner();
+        let uid = self.authorize(&jwt)?;
+
+        let mut record = self.job_record(&batch_id, &uid)?;
+        let store = self.store.clone();
+
+        let (tx, rx) = mpsc::channel(4);
+        // Poll the job record and stream every change until the job is done or has failed
+        tokio::spawn(async move {
+            let mut last_sent: Option<JobRecord> = None;
+
+            loop {
+                if last_sent.as_ref() != Some(&record) {
+                    let finished = matches!(record.state, JobState::Done | JobState::Failed);
+
+                    if tx.send(Ok(job_status(record.clone()))).await.is_err() {
+                        debug!("Client stopped watching job {}", batch_id);
+                        break;
+                    }
+
+                    if finished {
+                        break;
+                    }
+
+                    last_sent = Some(record);
+                }
+
+                tokio::time::sleep(Duration::from_millis(JOB_WATCH_INTERVAL_MILLIS)).await;
+
+                record = match store.get_job_record(&batch_id) {
+                    Ok(Some(record)) => record,
+                    Ok(None) => {
+                        let _ = tx
+                            .send(Err(Status::not_found(format!(
+                                "No job found for batch {}",
+                                batch_id
+                            ))))
+                            .await;
+                        break;
+                    }
+                    Err(e) => {
+                        error!("Error fetching job record {}. Error {}", batch_id, e);
+                        let _ = tx
+                            .send(Err(Status::internal("Internal server error")))
+                            .await;
+                        break;
+                    }
+                };
+            }
+        });
+
+        Ok(Response::new(Self::WatchJobStream::new(rx)))
+    }
+
+    async fn get_proof(
+        &self,
+        request: Request<ProofRequest>,
+    ) -> std::result::Result<Response<ProofResponse>, Status> {
+        let ProofRequest {
+            jwt,
+            leaf,
+            batch_id,
+        } = request.into_inner();
+        let uid = self.authorize(&jwt)?;
+
+        let internal_error = |e: SynxServerError| {
+            error!("Error looking up proof for client #{}. Error {}", uid, e);
+            Status::internal("Internal server error")
+        };
+
+        let (batch_id, leaf) = match leaf {
+            Some(Leaf::FileName(file_name)) => {
+                let file_name = Self::normalize_file_name(&file_name)?;
+                let batch_id = self
+                    .file_batch(&file_name, &uid, &batch_id)
+                    .map_err(internal_error)?
+                    .ok_or_else(|| Status::not_found(format!("File {} not found", file_name)))?;
+
+                let leaf = self
+                    .file_leaf(&file_name, &uid, &batch_id)
+                    .map_err(internal_error)?
+                    .ok_or_else(|| Status::not_found(format!("File {} not found", file_name)))?;
+
+                (batch_id, leaf)
+            }
+            Some(Leaf::ContentHash(content_hash)) => {
+                (self.resolve_batch(&uid, &batch_id)?, content_hash)
+            }
+            None => {
+                return Err(Status::invalid_argument(
+                    "Either a file name or a content hash is required",
+                ))
+            }
+        };
+
+        let merkle_tree = self
+            .fetch_merkle_tree(&uid, &batch_id)
+            .await
+            .map_err(internal_error)?;
+
+        let nodes = Self::generate_merkle_proof(&merkle_tree, &leaf).map_err(|_| {
+            Status::not_found(format!("Leaf {} not found in batch {}", leaf, batch_id))
+        })?;
+
+        Ok(Response::new(ProofResponse {
+            batch_id,
+            leaf,
+            merkle_proof: Some(MerkleProof { nodes }),
+        }))
+    }
+
+    async fn get_root(
+        &self,
+        request: Request<RootRequest>,
+    ) -> std::result::Result<Response<RootResponse>, Status> {
+        let RootRequest { jwt, batch_id } = request.into_inner();
+        let uid = self.authorize(&jwt)?;
+
+        let internal_error = |e: SynxServerError| {
+            error!("Error looking up root for client #{}. Error {}", uid, e);
+            Status::internal("Internal server error")
+        };
+
+        let batch_id = self.resolve_batch(&uid, &batch_id)?;
+
+        // The root is read from the stored tree rather than the job record,
+        // so it reflects what the server actually holds.
+        let merkle_tree = self
+            .fetch_merkle_tree(&uid, &batch_id)
+            .await
+            .map_err(internal_error)?;
+
+        Ok(Response::new(RootResponse {
+            merkle_root: merkle_tree.root().to_string(),
+            batch_id,
+        }))
+    }
+
+    async fn list_files(
+        &self,
+        request: Request<ListFilesRequest>,
+    ) -> std::result::Result<Response<ListFilesResponse>, Status> {
+        let ListFilesRequest {
+            jwt,
+            batch_id,
+            lineage,
+        } = request.into_inner();
+        let uid = self.authorize(&jwt)?;
+
+        let internal_error = |e: SynxServerError| {
+            error!("Error listing files for client #{}. Error {}", uid, e);
+            Status::internal("Internal server error")
+        };
+
+        let batch_id = match batch_id.is_empty() && !lineage.is_empty() {
+            true => self.lineage_batch(&uid, &lineage)?,
+            false => self.resolve_batch(&uid, &batch_id)?,
+        };
+        let lineage = self
+            .batch_lineage(&uid, &batch_id)
+            .map_err(internal_error)?
+            .unwrap_or_default();
+
+        let file_names = self
+            .batch_files(&uid, &batch_id)
+            .map_err(internal_error)?
+            .ok_or_else(|| Status::not_found(format!("Batch {} not found", batch_id)))?;
+
+        let mut files = Vec::with_capacity(file_names.len());
+        for file_name in file_names {
+            let content_hash = self
+                .file_leaf(&file_name, &uid, &batch_id)
+                .map_err(internal_error)?
+                .unwrap_or_default();
+            files.push(FileEntry {
+                file_name,
+                content_hash,
+            });
+        }
+
+        Ok(Response::new(ListFilesResponse {
+            batch_id,
+            files,
+            lineage,
+        }))
+    }
+
+    async fn challenge(
+        &self,
+        request: Request<ChallengeRequest>,
+    ) -> std::result::Result<Response<ChallengeResponse>, Status> {
+        let ChallengeRequest {
+            jwt,
+            batch_id,
+            nonce,
+            chunks,
+        } = request.into_inner();
+        let uid = self.authorize(&jwt)?;
+
+        if nonce.is_empty() {
+            return Err(Status::invalid_argument("Challenge nonce is required"));
+        }
+
+        if chunks.is_empty() || chunks.len() > MAX_CHALLENGE_CHUNKS {
+            return Err(Status::invalid_argument(format!(
+                "A challenge must request between 1 and {} chunks",
+                MAX_CHALLENGE_CHUNKS
+            )));
+        }
+
+        let batch_id = self.resolve_batch(&uid, &batch_id)?;
+
+        let internal_error = |e: SynxServerError| {
+            error!(
+                "Error answering challenge of client #{} for batch {}. Error {}",
+                uid, batch_id, e
+            );
+            Status::internal("Internal server error")
+        };
+
+        let merkle_tree = self
+            .fetch_merkle_tree(&uid, &batch_id)
+            .await
+            .map_err(internal_error)?;
+
+        // Files are read from storage once per challenge, however many of their chunks are asked for.
+        let mut files: HashMap<String, (String, Vec<u8>, MerkleTree)> = HashMap::new();
+        let mut responses = Vec::with_capacity(chunks.len());
+        for mut chunk in chunks {
+            chunk.file_name = Self::normalize_file_name(&chunk.file_name)?;
+            if !files.contains_key(&chunk.file_name) {
+                let file_leaf = self
+                    .file_leaf(&chunk.file_name, &uid, &batch_id)
+                    .map_err(internal_error)?
+                    .ok_or_else(|| {
+                        Status::not_found(format!("File {} not found", chunk.file_name))
+                    })?;
+
+                let file_path = self
+                    .download_file(&uid, &batch_id, &chunk.file_name)
+                    .await
+                    .map_err(internal_error)?;
+
+                let content = file_to_bytes(&file_path)
+                    .map_err(|_| internal_error(SynxServerError::ConvertFileToBytesError))?;
+
+                let chunk_tree = generate_chunk_merkle_tree(&content);
+                files.insert(chunk.file_name.clone(), (file_leaf, content, chunk_tree));
+            }
+            let (file_leaf, content, chunk_tree) = &files[&chunk.file_name];
+
+            let file_chunks = file_chunks(content);
+            let Some(chunk_content) = file_chunks.get(chunk.chunk_index as usize) else {
+                return Err(Status::out_of_range(format!(
+                    "File {} has {} chunks",
+                    chunk.file_name,
+                    file_chunks.len()
+                )));
+            };
+
+            let chunk_leaf = hash_bytes(&chunk_leaf_bytes(chunk.chunk_index, chunk_content));
+            let chunk_proof =
+                Self::generate_merkle_proof(chunk_tree, &chunk_leaf).map_err(internal_error)?;
+
+            let file_proof =
+                Self::generate_merkle_proof(&merkle_tree, file_leaf).map_err(internal_error)?;
+
+            responses.push(ChunkResponse {
+                nonce_hash: hash_bytes(&[nonce.as_slice(), chunk_content].concat()),
+                content: chunk_content.to_vec(),
+                chunk_proof: Some(MerkleProof { nodes: chunk_proof }),
+                file_leaf: file_leaf.to_string(),
+                file_proof: Some(MerkleProof { nodes: file_proof }),
+                file_name: chunk.file_name,
+                chunk_index: chunk.chunk_index,
+            });
+        }
+
+        Ok(Response::new(ChallengeResponse {
+            batch_id,
+            chunks: responses,
+        }))
+    }
+}
+
+/// A response of a stream made of frames tagged with the batch they belong to.
+trait FrameResponse {
+    type Frame;
+
+    fn new(frame: Self::Frame, batch_id: &str) -> Self;
+
+    /// Frame carrying a chunk of the file whose header was sent last.
+    fn content(chunk: Vec<u8>) -> Self::Frame;
+}
+
+impl FrameResponse for FilesDownloadResponse {
+    type Frame = Frame;
+
+    fn new(frame: Frame, batch_id: &str) -> Self {
+        Self {
+            frame: Some(frame),
+            batch_id: batch_id.to_string(),
+        }
+    }
+
+    fn content(chunk: Vec<u8>) -> Frame {
+        Frame::Content(chunk)
+    }
+}
+
+impl FrameResponse for RestoreResponse {
+    type Frame = restore_response::Frame;
+
+    fn new(frame: restore_response::Frame, batch_id: &str) -> Self {
+        Self {
+            frame: Some(frame),
+            batch_id: batch_id.to_string(),
+        }
+    }
+
+    fn content(chunk: Vec<u8>) -> restore_response::Frame {
+        restore_response::Frame::Content(chunk)
+    }
+}
+
+/// Sends one frame of a `DownloadFiles` or `RestoreBatch` stream, returning false once
+/// the client has gone away.
+async fn send_frame<R: FrameResponse>(
+    tx: &mpsc::Sender<std::result::Result<R, Status>>,
+    frame: R::Frame,
+    batch_id: &str,
+) -> bool {
+    if let Err(err) = tx.send(Ok(R::new(frame, batch_id))).await {
+        error!("Error streaming frame to client: Error {}", err);
+        return false;
+    }
+
+    true
+}
+
+/// Reads the next `len` bytes of `file`, or fewer at its end.
+fn read_chunk(file: &mut File, len: u64) -> std::io::Result<Vec<u8>> {
+    let mut content = Vec::with_capacity(len as usize);
+    Read::by_ref(file).take(len).read_to_end(&mut content)?;
+    Ok(content)
+}
+
+/// Opens the file at `path` to be streamed, returning it with its size.
+fn open_file(path: &Path) -> std::io::Result<(File, u64)> {
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    Ok((file, size))
+}
+
+/// Sends the `size` bytes of `file` as content frames, reading one chunk at a
+/// time. Returns false once the client has gone away or the file can't be read,
+/// which ends the stream with an error.
+async fn send_content<R: FrameResponse>(
+    tx: &mpsc::Sender<std::result::Result<R, Status>>,
+    file: &mut File,
+    size: u64,
+    batch_id: &str,
+) -> bool {
+    let mut remaining = size;
+    while remaining > 0 {
+        let chunk = match read_chunk(file, remaining.min(DOWNLOAD_CHUNK_SIZE)) {
+            Ok(chunk) if !chunk.is_empty() => chunk,
+            Ok(_) => {
+                error!("File of batch {} ended {} bytes early", batch_id, remaining);
+                let _ = tx
+                    .send(Err(Status::internal("Internal server error")))
+                    .await;
+                return false;
+            }
+            Err(e) => {
+                error!("Error reading file of batch {}. Error {}", batch_id, e);
+                let _ = tx
+                    .send(Err(Status::internal("Internal server error")))
+                    .await;
+                return false;
+            }
+        };
+
+        remaining -= chunk.len() as u64;
+        if !send_frame(tx, R::content(chunk), batch_id).await {
+            return false;
+        }
+    }
+
+    true
+}
+
+fn job_status(record: JobRecord) -> JobStatus {
+    let state = match record.state {
+        JobState::Queued => syncx::JobState::Queued,
+        JobState::Unpacking => syncx::JobState::Unpacking,
+        JobState::Uploading => syncx::JobState::Uploading,
+        JobState::Done => syncx::JobState::Done,
+        JobState::Failed => syncx::JobState::Failed,
+    };
+
+    JobStatus {
+        batch_id: record.batch_id,
+        state: state.into(),
+        reason: record.reason,
+        merkle_root: record.merkle_root,
+    }
 }
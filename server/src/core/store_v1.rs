@@ -1,8 +1,11 @@
 use super::{
     definitions::{
-        ClientObject, R2D2Pool, RedisPool, Result, Store, CACHE_POOL_TIMEOUT_SECONDS, JOB_QUEUE,
+        ClientObject, Job, JobRecord, R2D2Pool, RedisPool, Result, Store, UploadSession,
+        CACHE_POOL_TIMEOUT_SECONDS, JOB_QUEUE, UPLOAD_SESSION_EXPIRE_SECONDS,
+        UPLOAD_SESSION_PREFIX,
     },
     errors::SynxServerError,
+    utils::job_record_key,
 };
 
 use async_trait::async_trait;
@@ -48,11 +51,15 @@ impl StoreV1 {
 
     pub fn save_to_cache(&self, key: &str, value: &str) -> Result<()> {
         let mut conn = self.get_redis_connection(CACHE_POOL_TIMEOUT_SECONDS)?;
-        conn.set(key, value)
+        conn.set::<_, _, ()>(key, value)
             .map_err(|err| SynxServerError::RedisCMDError(err.to_string()))?;
 
         Ok(())
     }
+
+    fn upload_session_key(id: &str) -> String {
+        format!("{}:{}", UPLOAD_SESSION_PREFIX, id)
+    }
 }
 
 #[async_trait]
@@ -104,23 +111,82 @@ impl Store for StoreV1 {
         Ok(true)
     }
 
-    fn enqueue_job(&self, value: &str) -> Result<()> {
+    fn enqueue_job(&self, job: &Job) -> Result<()> {
         let mut conn = self.get_redis_connection(CACHE_POOL_TIMEOUT_SECONDS)?;
 
+        let json_string = serde_json::to_string(job)
+            .map_err(|err| SynxServerError::SerializationError(err.to_string()))?;
+
         let value = conn
-            .rpush::<&str, &str, String>(JOB_QUEUE, value)
+            .rpush::<&str, &str, String>(JOB_QUEUE, &json_string)
             .map_err(|err| SynxServerError::DequeueJobError(err.to_string()))?;
 
         println!("New job queued: {:?}", value);
         Ok(())
     }
+
+    fn save_upload_session(&self, session: &UploadSession) -> Result<()> {
+        let mut conn = self.get_redis_connection(CACHE_POOL_TIMEOUT_SECONDS)?;
+
+        let json_string = serde_json::to_string(session)
+            .map_err(|err| SynxServerError::SerializationError(err.to_string()))?;
+
+        conn.set_ex::<_, _, ()>(
+            Self::upload_session_key(&session.id),
+            json_string,
+            UPLOAD_SESSION_EXPIRE_SECONDS,
+        )
+        .map_err(|err| SynxServerError::RedisCMDError(err.to_string()))?;
+
+        Ok(())
+    }
+
+    fn get_upload_session(&self, id: &str) -> Result<Option<UploadSession>> {
+        match self.fetch_from_cache(&Self::upload_session_key(id))? {
+            Some(value) => {
+                let session: UploadSession = serde_json::from_str(&value)
+                    .map_err(|err| SynxServerError::DeserializationError(err.to_string()))?;
+
+                Ok(Some(session))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn delete_upload_session(&self, id: &str) -> Result<()> {
+        let mut conn = self.get_redis_connection(CACHE_POOL_TIMEOUT_SECONDS)?;
+
+        conn.del::<_, ()>(Self::upload_session_key(id))
+            .map_err(|err| SynxServerError::RedisCMDError(err.to_string()))?;
+
+        Ok(())
+    }
+
+    fn save_job_record(&self, record: &JobRecord) -> Result<()> {
+        let json_string = serde_json::to_string(record)
+            .map_err(|err| SynxServerError::SerializationError(err.to_string()))?;
+
+        self.save_to_cache(&job_record_key(&record.batch_id), &json_string)
+    }
+
+    fn get_job_record(&self, batch_id: &str) -> Result<Option<JobRecord>> {
+        match self.fetch_from_cache(&job_record_key(batch_id))? {
+            Some(value) => {
+                let record: JobRecord = serde_json::from_str(&value)
+                    .map_err(|err| SynxServerError::DeserializationError(err.to_string()))?;
+
+                Ok(Some(record))
+            }
+            None => Ok(None),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use dotenv::dotenv;
-    use r2d2_redis::redis::ConnectionLike;
+    use crate::core::utils::{connect_db, connect_redis};
+    use r2d2_redis::redis::{cmd, ConnectionLike};
     use std::env;
 
     use lazy_static::lazy_static;
@@ -1,11 +1,14 @@
 use super::{
     definitions::{
-        R2D2Pool, Result, CACHE_POOL_EXPIRE_SECONDS, CACHE_POOL_MAX_OPEN, CACHE_POOL_MIN_IDLE,
-        GCS_PARENT_DIR, TEMP_DIR,
+        R2D2Pool, Result, BATCH_FILES_PREFIX, BATCH_FILE_PREFIX, BATCH_LINEAGE_PREFIX,
+        BATCH_SYMLINKS_PREFIX, CACHE_POOL_EXPIRE_SECONDS, CACHE_POOL_MAX_OPEN, CACHE_POOL_MIN_IDLE,
+        FILE_METADATA_PREFIX, FILE_SOURCE_PREFIX, GCS_PARENT_DIR, JOB_RECORD_PREFIX,
+        LATEST_BATCH_PREFIX, LATEST_FILE_PREFIX, LATEST_LINEAGE_BATCH_PREFIX, TEMP_DIR,
     },
     errors::SynxServerError,
 };
 
+use futures_util::StreamExt;
 use hex;
 use log::{debug, error, info};
 use mongodb::{options::ClientOptions, Client};
@@ -13,9 +16,10 @@ use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
 use r2d2_redis::{r2d2, RedisConnectionManager};
 use sha2::{Digest, Sha256};
 use std::fs;
-use std::io::Write;
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 use std::time::Duration;
+use tokio_util::io::ReaderStream;
 
 /// Asynchronously creates a database client connection.
 /// Establishes a connection to the database specified by `db_url`.
@@ -75,7 +79,13 @@ pub async fn download_file(
 ) -> Result<()> {
     info!("Attempting to download file {:?} from storage", object_name);
 
-    const FRAGMENT: &AsciiSet = &CONTROLS.add(b'/');
+    const FRAGMENT: &AsciiSet = &CONTROLS
+        .add(b'/')
+        .add(b' ')
+        .add(b'"')
+        .add(b'#')
+        .add(b'%')
+        .add(b'?');
     let gcs_object_name = utf8_percent_encode(&object_name, FRAGMENT).to_string();
 
     let url = format!(
@@ -94,22 +104,39 @@ pub async fn download_file(
             SynxServerError::DownloadError
         })?;
 
+    if !response.status().is_success() {
+        error!(
+            "File {:?} download failed with status_code {}",
+            gcs_object_name,
+            response.status()
+        );
+        return Err(SynxServerError::DownloadError);
+    }
+
     debug!(
         "File {:?} downloaded successfully with status_code {}",
         gcs_object_name,
         response.status()
     );
 
-    let body = &response.bytes().await.map_err(|e| {
-        error!("Error reading downloaded bytes: Error {}", e);
-        SynxServerError::HttpReadBytesError
+    let mut file = fs::File::create(file_path).map_err(|e| {
+        error!("Error creating file {:?}: Error {}", file_path, e);
+        SynxServerError::CreateFileError
     })?;
 
-    let mut file = fs::File::create(&file_path).unwrap();
-    file.write_all(&body).map_err(|e| {
-        error!("Error creating file from downloaded bytes: Error {}", e);
-        SynxServerError::FileOpenError
-    })?;
+    // The body is written out as it arrives rather than buffered whole.
+    let mut body = response.bytes_stream();
+    while let Some(bytes) = body.next().await {
+        let bytes = bytes.map_err(|e| {
+            error!("Error reading downloaded bytes: Error {}", e);
+            SynxServerError::HttpReadBytesError
+        })?;
+
+        file.write_all(&bytes).map_err(|e| {
+            error!("Error creating file from downloaded bytes: Error {}", e);
+            SynxServerError::FileOpenError
+        })?;
+    }
 
     Ok(())
 }
@@ -123,21 +150,38 @@ pub async fn upload_file(
 ) -> Result<()> {
     info!("Attempting to upload file {:?}", file_path);
 
+    // Object names carry the file's relative path, which may contain characters
+    // that are significant in a query string.
+    const QUERY: &AsciiSet = &CONTROLS
+        .add(b' ')
+        .add(b'"')
+        .add(b'#')
+        .add(b'%')
+        .add(b'&')
+        .add(b'+')
+        .add(b'?');
+    let gcs_object_name = utf8_percent_encode(object_name, QUERY).to_string();
+
     let url = format!(
         "https://storage.googleapis.com/upload/storage/v1/b/{}/o?uploadType=media&name={}",
-        gcs_bucket_name, object_name
+        gcs_bucket_name, gcs_object_name
     );
 
+    let read_error = |err: std::io::Error| {
+        error!("File upload failed: Error {}", err);
+        SynxServerError::UploadFileRequestError(err.to_string())
+    };
+
+    // The body is read from disk as it is sent rather than loaded whole.
+    let file = tokio::fs::File::open(file_path).await.map_err(read_error)?;
+    let size = file.metadata().await.map_err(read_error)?.len();
+
     let client = reqwest::Client::new();
     let response = client
         .post(&url)
         .bearer_auth(api_key)
-        .body(reqwest::Body::from(std::fs::read(file_path).map_err(
-            |err| {
-                error!("File upload failed: Error {}", err);
-                SynxServerError::UploadFileRequestError(err.to_string())
-            },
-        )?))
+        .header(reqwest::header::CONTENT_LENGTH, size)
+        .body(reqwest::Body::wrap_stream(ReaderStream::new(file)))
         .send()
         .await
         .map_err(|err| {
@@ -145,6 +189,18 @@ pub async fn upload_file(
             SynxServerError::UploadFileRequestError(err.to_string())
         })?;
 
+    if !response.status().is_success() {
+        error!(
+            "File {:?} upload failed with status_code {}",
+            file_path,
+            response.status()
+        );
+        return Err(SynxServerError::UploadFileRequestError(format!(
+            "Storage responded with status {}",
+            response.status()
+        )));
+    }
+
     info!(
         "File {:?} uploaded successfully with status_code {}",
         file_path,
@@ -154,6 +210,30 @@ pub async fn upload_file(
     Ok(())
 }
 
+/// Checks that the storage bucket exists and that `api_key` grants access to it.
+pub async fn check_bucket(gcs_bucket_name: &str, api_key: &str) -> Result<()> {
+    let url = format!(
+        "https://storage.googleapis.com/storage/v1/b/{}",
+        gcs_bucket_name
+    );
+
+    let response = reqwest::Client::new()
+        .get(&url)
+        .bearer_auth(api_key)
+        .send()
+        .await
+        .map_err(|e| SynxServerError::StorageUnavailableError(e.to_string()))?;
+
+    if !response.status().is_success() {
+        return Err(SynxServerError::StorageUnavailableError(format!(
+            "status code {}",
+            response.status()
+        )));
+    }
+
+    Ok(())
+}
+
 pub fn extract_file_name_from_path(path: &Path) -> Option<String> {
     if let Some(file_name) = path.file_name() {
         Some(file_name.to_str().unwrap().to_string())
@@ -182,8 +262,8 @@ pub fn gcs_zip_path(id: &str) -> String {
     format!("{}/{}.zip", TEMP_DIR, id)
 }
 
-pub fn gsc_object_name(id: &str, file_name: &str) -> String {
-    format!("{}/{}/{}", GCS_PARENT_DIR, id, file_name)
+pub fn gsc_object_name(id: &str, batch_id: &str, file_name: &str) -> String {
+    format!("{}/{}/{}/{}", GCS_PARENT_DIR, id, batch_id, file_name)
 }
 
 pub fn parse_path_from_slice(tokens: &Vec<&str>) -> PathBuf {
@@ -197,6 +277,97 @@ pub fn hash_str(value: &str) -> String {
     hex::encode(hasher.finalize())
 }
 
+/// Hashes `parts` into a single key component. Each part is prefixed with its
+/// length, so different parts can never run together into the same key.
+fn hash_parts(parts: &[&str]) -> String {
+    hash_str(
+        &parts
+            .iter()
+            .map(|part| format!("{}:{}", part.len(), part))
+            .collect::<String>(),
+    )
+}
+
+/// Redis key holding the id of the latest batch client `id` uploaded `file_name` in.
+pub fn latest_file_key(id: &str, file_name: &str) -> String {
+    format!("{}:{}", LATEST_FILE_PREFIX, hash_parts(&[id, file_name]))
+}
+
+/// Redis key holding the content hash of `file_name` in batch `batch_id` of client `id`.
+pub fn batch_file_key(id: &str, batch_id: &str, file_name: &str) -> String {
+    format!(
+        "{}:{}",
+        BATCH_FILE_PREFIX,
+        hash_parts(&[id, batch_id, file_name])
+    )
+}
+
+/// Redis key holding the id of the batch client `id` uploaded most recently.
+pub fn latest_batch_key(id: &str) -> String {
+    format!("{}:{}", LATEST_BATCH_PREFIX, id)
+}
+
+/// Redis key holding the lineage batch `batch_id` of client `id` belongs to.
+pub fn batch_lineage_key(id: &str, batch_id: &str) -> String {
+    format!("{}:{}:{}", BATCH_LINEAGE_PREFIX, id, batch_id)
+}
+
+/// Redis key holding the id of the latest processed batch of lineage `lineage` of client `id`.
+pub fn latest_lineage_batch_key(id: &str, lineage: &str) -> String {
+    format!(
+        "{}:{}",
+        LATEST_LINEAGE_BATCH_PREFIX,
+        hash_parts(&[id, lineage])
+    )
+}
+
+/// Redis key holding the JSON list of the names of the files in batch `batch_id` of client `id`.
+pub fn batch_files_key(id: &str, batch_id: &str) -> String {
+    format!("{}:{}:{}", BATCH_FILES_PREFIX, id, batch_id)
+}
+
+/// Redis key holding the JSON list of the `FileMetadata` of the symbolic links in batch
+/// `batch_id` of client `id`.
+pub fn batch_symlinks_key(id: &str, batch_id: &str) -> String {
+    format!("{}:{}:{}", BATCH_SYMLINKS_PREFIX, id, batch_id)
+}
+
+/// Redis key holding the JSON `FileMetadata` of `file_name` in batch `batch_id` of client `id`.
+pub fn file_metadata_key(id: &str, batch_id: &str, file_name: &str) -> String {
+    format!(
+        "{}:{}",
+        FILE_METADATA_PREFIX,
+        hash_parts(&[id, batch_id, file_name])
+    )
+}
+
+/// Redis key holding the batch whose storage holds the content of `file_name` in
+/// batch `batch_id` of client `id`. Only set for files an incremental upload
+/// carried over from an earlier batch.
+pub fn file_source_key(id: &str, batch_id: &str, file_name: &str) -> String {
+    format!(
+        "{}:{}",
+        FILE_SOURCE_PREFIX,
+        hash_parts(&[id, batch_id, file_name])
+    )
+}
+
+/// Redis key holding the `JobRecord` of the job processing batch `batch_id`.
+pub fn job_record_key(batch_id: &str) -> String {
+    format!("{}:{}", JOB_RECORD_PREFIX, batch_id)
+}
+
+/// Hashes the first `len` bytes of `file` and returns the hasher, ready to be
+/// fed whatever is written after them.
+pub fn sha256_of_prefix(file: &mut fs::File, len: u64) -> std::io::Result<Sha256> {
+    let mut hasher = Sha256::new();
+
+    file.seek(SeekFrom::Start(0))?;
+    std::io::copy(&mut Read::by_ref(file).take(len), &mut hasher)?;
+
+    Ok(hasher)
+}
+
 pub fn ensure_directory_exists(path: &PathBuf) -> Result<()> {
     if !path.exists() {
         fs::create_dir_all(path).map_err(|_| SynxServerError::CreateDirectoryError)?;
@@ -210,3 +381,32 @@ pub fn get_file_name_from_path(path: &Path) -> Option<String> {
         .and_then(|file_name| file_name.to_str())
         .map(|file_name_str| file_name_str.to_owned())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_keys_test() {
+        // Parts that concatenate to the same string still make different keys.
+        assert_ne!(
+            batch_file_key("id", "ab", "c"),
+            batch_file_key("id", "a", "bc")
+        );
+        assert_ne!(latest_file_key("ida", "b"), latest_file_key("id", "ab"));
+        assert_ne!(
+            file_metadata_key("id", "ab", "c"),
+            file_metadata_key("id", "a", "bc")
+        );
+        assert_ne!(
+            file_source_key("id", "ab", "c"),
+            file_source_key("id", "a", "bc")
+        );
+
+        // Each kind of key has its own prefix.
+        assert_ne!(
+            batch_file_key("id", "a", "b"),
+            file_metadata_key("id", "a", "b")
+        );
+    }
+}
@@ -1,17 +1,25 @@
 use super::{
     definitions::{
-        R2D2Pool, RedisPool, Result, CACHE_POOL_TIMEOUT_SECONDS, JOB_QUEUE, MERKLE_DIR, TEMP_DIR,
-        WIP_UPLOADS_DIR,
+        Job, JobRecord, JobState, R2D2Pool, RedisPool, Result, CACHE_POOL_TIMEOUT_SECONDS,
+        JOB_QUEUE,
     },
     errors::SynxServerError,
     path_resolver::*,
     utils::*,
 };
-use common::common::{generate_merkle_tree, list_files_in_dir, unzip_file};
+use common::{
+    common::{
+        file_to_bytes, list_files_recursive, normalize_relative_path, relative_path_name,
+        unzip_file, METADATA_MANIFEST,
+    },
+    syncx::FileMetadata,
+};
 use log::{debug, error, info};
+use merkle_tree::{merkle_tree::MerkleTree, utils::hash_bytes};
 
 use r2d2_redis::redis::Commands;
 
+use std::collections::HashMap;
 use std::fs;
 use std::io::Write;
 use std::path::{Path, PathBuf};
@@ -21,6 +29,15 @@ pub struct Worker {
     redis_pool: Arc<R2D2Pool>,
 }
 
+/// A file of the base batch that an incremental upload keeps unchanged.
+struct CarriedOverFile {
+    file_name: String,
+    leaf: String,
+    /// The batch whose storage holds the file's content.
+    source: String,
+    metadata: Option<FileMetadata>,
+}
+
 impl RedisPool for Worker {
     fn get_pool(&self) -> &R2D2Pool {
         &self.redis_pool
@@ -32,36 +49,80 @@ impl Worker {
         Worker { redis_pool }
     }
 
-    pub fn dequeue_job(&self) -> Result<String> {
+    pub fn dequeue_job(&self) -> Result<Job> {
         let mut conn = self.get_redis_connection(CACHE_POOL_TIMEOUT_SECONDS)?;
 
         let (_, v) = conn
             .blpop::<&str, (String, String)>(JOB_QUEUE, 0)
             .map_err(|err| SynxServerError::DequeueJobError(err.to_string()))?;
 
-        Ok(v)
+        serde_json::from_str(&v)
+            .map_err(|err| SynxServerError::DeserializationError(err.to_string()))
+    }
+
+    fn fetch_from_cache(&self, key: &str) -> Result<Option<String>> {
+        let mut conn = self.get_redis_connection(CACHE_POOL_TIMEOUT_SECONDS)?;
+
+        conn.get::<&str, Option<String>>(key)
+            .map_err(|err| SynxServerError::RedisCMDError(err.to_string()))
     }
 
-    fn cache_file_name(&self, key: &str) -> Result<()> {
+    fn save_to_cache(&self, key: &str, value: &str) -> Result<()> {
         let mut conn = self.get_redis_connection(CACHE_POOL_TIMEOUT_SECONDS)?;
 
-        let _x = conn.set::<&str, bool, String>(key, true).unwrap();
+        conn.set::<&str, &str, ()>(key, value)
+            .map_err(|err| SynxServerError::RedisCMDError(err.to_string()))?;
 
-        info!("Saved filename {} to redis.", key);
+        info!("Saved key {} to redis.", key);
 
         Ok(())
     }
 
+    /// Records the progress of a job so clients can follow it with `GetJobStatus` and `WatchJob`.
+    /// Failing to do so is logged but doesn't stop the job.
+    fn update_job(&self, record: &JobRecord) {
+        let result = self
+            .get_redis_connection(CACHE_POOL_TIMEOUT_SECONDS)
+            .and_then(|mut conn| {
+                let json_string = serde_json::to_string(record)
+                    .map_err(|err| SynxServerError::SerializationError(err.to_string()))?;
+
+                conn.set::<_, _, ()>(job_record_key(&record.batch_id), json_string)
+                    .map_err(|err| SynxServerError::RedisCMDError(err.to_string()))
+            });
+
+        if let Err(e) = result {
+            error!(
+                "Error updating job <{}:{}> to {:?}: {}",
+                record.client_id, record.batch_id, record.state, e
+            );
+        }
+    }
+
     pub async fn run_workers(self: Arc<Self>) {
         info!("Waiting on new jobs in redis queue");
 
         loop {
             match self.dequeue_job() {
                 Ok(job) => {
-                    debug!("Processing new job: <{}>", job);
+                    debug!("Processing new job: <{}:{}>", job.client_id, job.batch_id);
                     let worker_clone = self.clone();
                     tokio::spawn(async move {
-                        worker_clone.process_job(job).await;
+                        let record = match worker_clone.process_job(&job).await {
+                            Ok(merkle_root) => {
+                                let mut record = JobRecord::new(&job, JobState::Done);
+                                record.merkle_root = merkle_root;
+                                record
+                            }
+                            Err(e) => {
+                                error!("Error processing job: {}", e);
+                                let mut record = JobRecord::new(&job, JobState::Failed);
+                                record.reason = e.to_string();
+                                record
+                            }
+                        };
+
+                        worker_clone.update_job(&record);
                     });
                 }
                 Err(e) => {
@@ -72,9 +133,8 @@ impl Worker {
         }
     }
 
-    async fn process_job(&self, job_data: String) -> Result<()> {
-        let id = &job_data;
-
+    /// Unpacks and stores the files of a batch. Returns the merkle root computed for it.
+    async fn process_job(&self, job: &Job) -> Result<String> {
         // It's safe to use `unwrap` here
         let bucket_name = std::env::var("GCS_BUCKET_NAME").unwrap();
         let oauth2_token = std::env::var("GOOGLE_STORAGE_API_KEY").unwrap();
@@ -85,58 +145,40 @@ impl Worker {
         let zip_dir = local_zip_dir();
         let path = Path::new(&zip_dir);
 
-        ensure_directory_exists(&path.to_path_buf()).map_err(|err| {
+        ensure_directory_exists(&path.to_path_buf()).map_err(|_| {
             error!("Error creating local zip path");
             SynxServerError::CreateDirectoryError
         })?;
 
-        let mut zip_file_path = path.join(format!("{}.zip", job_data));
-        let wip_uploads_dir = wip_uploads_dir(&job_data);
+        let zip_file_path = path.join(format!("{}.zip", job.batch_id));
+        let wip_uploads_dir = wip_uploads_dir(&job.client_id, &job.batch_id);
         let output_zip_dir = Path::new(&wip_uploads_dir);
 
-        ensure_directory_exists(&output_zip_dir.to_path_buf()).map_err(|err| {
+        ensure_directory_exists(&output_zip_dir.to_path_buf()).map_err(|_| {
             error!("Error creating output zip path");
             SynxServerError::CreateDirectoryError
         })?;
 
-        if zip_file_path.exists() {
-            self.unzip_and_upload(
-                &zip_file_path,
-                &output_zip_dir,
-                id,
-                &oauth2_token,
-                &bucket_name,
-            )
-            .await
-            .map_err(|_| SynxServerError::UnzipError)?
-        } else {
-            let object_name = gcs_zip_file_object_name(&job_data);
-            let output_path_dir = Path::new(&wip_uploads_dir);
-
-            ensure_directory_exists(&output_path_dir.to_path_buf()).map_err(|err| {
-                error!("Error creating pending uploads path");
-                SynxServerError::CreateDirectoryError
-            })?;
-
-            zip_file_path = output_path_dir.join(format!("{}.zip", job_data));
-
-            match download_file(&object_name, &bucket_name, &oauth2_token, &zip_file_path).await {
-                Ok(()) => {
-                    self.unzip_and_upload(
-                        &zip_file_path,
-                        &output_zip_dir,
-                        &id,
-                        &oauth2_token,
-                        &bucket_name,
-                    )
-                    .await
-                    .unwrap();
-                }
-                Err(_) => println!("Download of {:?} failed...", job_data),
-            };
+        if !zip_file_path.exists() {
+            let object_name = gcs_zip_file_object_name(&job.client_id, &job.batch_id);
+
+            if download_file(&object_name, &bucket_name, &oauth2_token, &zip_file_path)
+                .await
+                .is_err()
+            {
+                error!("Download of {:?} failed...", object_name);
+                return Err(SynxServerError::DownloadError);
+            }
         }
 
-        Ok(())
+        self.unzip_and_upload(
+            &zip_file_path,
+            output_zip_dir,
+            job,
+            &oauth2_token,
+            &bucket_name,
+        )
+        .await
     }
 
     /// Unzips the contents of a ZIP file.
@@ -159,28 +201,76 @@ impl Worker {
         Ok(())
     }
 
-    /// Generates a Merkle tree from a list of files and writes it to a file.
+    /// Reads and removes the metadata manifest from an unpacked batch, so it is not
+    /// stored as one of the batch's files. Returns the metadata of the batch's files,
+    /// keyed by path, and of its symbolic links, which are only ever kept as records.
+    fn take_metadata_manifest(
+        unzip_output_path: &Path,
+    ) -> Result<(HashMap<String, FileMetadata>, Vec<FileMetadata>)> {
+        let manifest_path = unzip_output_path.join(METADATA_MANIFEST);
+        if !manifest_path.is_file() {
+            return Ok((HashMap::new(), Vec::new()));
+        }
+
+        let manifest = file_to_bytes(&manifest_path).map_err(|e| {
+            error!("Error reading metadata manifest: Error {}", e);
+            SynxServerError::ConvertFileToBytesError
+        })?;
+        let _ = fs::remove_file(&manifest_path);
+
+        let entries: Vec<FileMetadata> = serde_json::from_slice(&manifest)
+            .map_err(|e| SynxServerError::DeserializationError(e.to_string()))?;
+
+        let mut file_metadata = HashMap::new();
+        let mut symlinks = Vec::new();
+        for mut metadata in entries {
+            let Some(path) = normalize_relative_path(&metadata.path) else {
+                error!("Skipping metadata of invalid path {}", metadata.path);
+                continue;
+            };
+            metadata.path = path;
+
+            match metadata.symlink_target.is_empty() {
+                true => {
+                    file_metadata.insert(metadata.path.clone(), metadata);
+                }
+                false => symlinks.push(metadata),
+            }
+        }
+
+        Ok((file_metadata, symlinks))
+    }
+
+    /// Generates a Merkle tree from the leaves of a batch's files and writes it to a file.
     ///
-    /// This function takes an identifier and a list of file paths, generates a Merkle tree from these files,
-    /// serializes the Merkle tree, and writes the serialized data to a file.
+    /// This function takes an identifier and the content hashes of the batch's files, generates a Merkle
+    /// tree from them, serializes the Merkle tree, and writes the serialized data to a file.
     ///
     /// # Arguments
     ///
     /// * `id` - A string slice that serves as an identifier for the Merkle tree file.
-    /// * `files` - A reference to a vector of `PathBuf`, representing the paths to the files used to generate the Merkle tree.
+    /// * `batch_id` - The batch the files were uploaded in. Each batch gets its own Merkle tree.
+    /// * `leaves` - The content hashes of the files, which become the leaves of the Merkle tree.
     ///
     /// # Returns
     ///
-    /// Returns `Ok(PathBuf)` containing the path to the created file if the operation is successful.
+    /// Returns `Ok((PathBuf, String))` containing the path to the created file and the Merkle root
+    /// if the operation is successful.
     /// Returns `Err(SynxServerError)` in case of an error during any of the steps: generating the Merkle tree,
     /// serializing it, creating the output directory, creating the file, or writing to the file.
     ///
     /// #
-    fn write_merkle_tree_to_file(id: &str, files: &Vec<PathBuf>) -> Result<PathBuf> {
-        let merkle_tree = generate_merkle_tree(files).map_err(|e| {
-            error!("Error generating merkle tree: Error {}", e);
-            SynxServerError::MerkleTreeGenerationError
-        })?;
+    fn write_merkle_tree_to_file(
+        id: &str,
+        batch_id: &str,
+        leaves: Vec<String>,
+    ) -> Result<(PathBuf, String)> {
+        // A tree without leaves has no root.
+        if leaves.is_empty() {
+            return Err(SynxServerError::EmptyBatch);
+        }
+
+        let merkle_tree = MerkleTree::from_leaf_hashes(leaves);
 
         // Serialize the merkle tree
         let merkle_tree_str = merkle_tree.serialize().map_err(|e| {
@@ -188,7 +278,7 @@ impl Worker {
             SynxServerError::SerializeTreeError
         })?;
 
-        // Write the serialized merkle tree to a file `temp/merkle_trees/{id}.txt`
+        // Write the serialized merkle tree to a file `temp/merkle_trees/{id}_{batch_id}_mtree.txt`
         let merkle_tree_path = local_merkle_tree_path();
         let merkle_dir_path = Path::new(&merkle_tree_path);
         let _ = ensure_directory_exists(&merkle_dir_path.to_path_buf()).map_err(|err| {
@@ -196,9 +286,9 @@ impl Worker {
             SynxServerError::CreateDirectoryError
         });
 
-        let merkle_file_path = merkle_dir_path.join(local_merkle_tree_file(id));
+        let merkle_file_path = merkle_dir_path.join(local_merkle_tree_file(id, batch_id));
 
-        // Create the merkle tree file. The file path is in the format `temp/merkle_trees/{id}_mtree.txt`
+        // Create the merkle tree file. The file path is in the format `temp/merkle_trees/{id}_{batch_id}_mtree.txt`
         let mut file = fs::File::create(&merkle_file_path).map_err(|e| {
             error!("Error creating merkle tree file: Error {}", e);
             SynxServerError::CreateFileError
@@ -209,47 +299,205 @@ impl Worker {
             SynxServerError::WriteAllError
         })?;
 
-        Ok(merkle_file_path)
+        Ok((merkle_file_path, merkle_tree.root().to_string()))
     }
 
     async fn unzip_and_upload(
         &self,
         zip_file_path: &Path,
         unzip_output_path: &Path,
-        id: &str,
+        job: &Job,
         api_key: &str,
         bucket_name: &str,
-    ) -> Result<()> {
+    ) -> Result<String> {
+        let Job {
+            client_id: id,
+            batch_id,
+            base_batch_id,
+            unchanged_files,
+            lineage,
+        } = job;
+
+        self.update_job(&JobRecord::new(job, JobState::Unpacking));
+
         Self::unzip_file(&zip_file_path, &unzip_output_path)
             .map_err(|_| SynxServerError::UnzipError)?;
 
-        let mut files_to_upload = list_files_in_dir(&unzip_output_path.to_path_buf())
-            .map_err(|_| SynxServerError::ListFilesError)?;
+        let (mut file_metadata, symlinks) = Self::take_metadata_manifest(unzip_output_path)?;
+
+        let batch_files =
+            list_files_recursive(unzip_output_path).map_err(|_| SynxServerError::ListFilesError)?;
 
-        info!("Files to upload: {:?}", files_to_upload);
+        info!("Files to upload: {:?}", batch_files);
+
+        // Files keep their path relative to the archive root, so nested directories
+        // don't collide. Each file's leaf is its content hash.
+        let mut files_to_upload = Vec::with_capacity(batch_files.len() + 1);
+        let mut leaves = HashMap::new();
+        for path in batch_files {
+            let Some(file_name) = relative_path_name(unzip_output_path, &path) else {
+                error!("Invalid file path {:?} in batch {}", path, batch_id);
+                return Err(SynxServerError::ListFilesError);
+            };
 
-        let merkle_file_path = Self::write_merkle_tree_to_file(id, &files_to_upload)?;
+            let leaf = hash_bytes(&file_to_bytes(&path).map_err(|e| {
+                error!("Error reading file {:?}: Error {}", path, e);
+                SynxServerError::ConvertFileToBytesError
+            })?);
 
+            leaves.insert(file_name.clone(), leaf);
+            files_to_upload.push((path, file_name));
+        }
+
+        // Files an incremental upload left out of the archive keep their leaf and
+        // their stored content from the base batch.
+        let carried_over = match base_batch_id {
+            Some(base_batch_id) => {
+                self.carried_over_files(id, base_batch_id, unchanged_files, &leaves)?
+            }
+            None => Vec::new(),
+        };
+
+        let (merkle_file_path, merkle_root) = Self::write_merkle_tree_to_file(
+            id,
+            batch_id,
+            leaves
+                .values()
+                .chain(carried_over.iter().map(|file| &file.leaf))
+                .cloned()
+                .collect(),
+        )?;
+
+        // Add the merkle tree file to the files to be uploaded.
         let merkle_name = get_file_name_from_path(&merkle_file_path).unwrap();
+        files_to_upload.push((merkle_file_path, merkle_name.clone()));
 
-        // Add the merkle tree file to the files to be uploaded
-        files_to_upload.push(merkle_file_path);
+        self.update_job(&JobRecord::new(job, JobState::Uploading));
 
         let mut count = 0;
-        for (_i, path) in files_to_upload.iter().enumerate() {
-            let file_name = path.as_path().file_name().unwrap().to_string_lossy();
-            let object_name = gsc_object_name(&id, &file_name);
+        let mut file_names = Vec::new();
+        for (path, file_name) in files_to_upload.iter() {
+            let object_name = gsc_object_name(id, batch_id, file_name);
 
-            upload_file(&path.as_path(), &id, api_key, bucket_name, &object_name).await?;
+            upload_file(path.as_path(), id, api_key, bucket_name, &object_name).await?;
             count += 1;
 
-            // We cache the file name to redis for fast lookup. Excluding the "merkletree.txt" file
-            if file_name != merkle_name {
-                let key = hash_str(&format!("{}{}", id, file_name));
-                let _ = self.cache_file_name(&key);
+            // We cache the file name to redis for fast lookup. Excluding the "merkletree.txt" file.
+            // The batch key holds the file's leaf so proofs can be served without the file,
+            // and the latest key points downloads without a batch id at this batch.
+            if let Some(leaf) = leaves.get(file_name) {
+                let _ = self.save_to_cache(&batch_file_key(id, batch_id, file_name), leaf);
+                let _ = self.save_to_cache(&latest_file_key(id, file_name), batch_id);
+                file_names.push(file_name.to_string());
+
+                if let Some(metadata) = file_metadata.remove(file_name.as_str()) {
+                    self.save_file_metadata(id, batch_id, file_name, &metadata)?;
+                }
             }
         }
         info!("{} files uploaded", count);
+
+        for file in &carried_over {
+            let file_name = &file.file_name;
+            let _ = self.save_to_cache(&batch_file_key(id, batch_id, file_name), &file.leaf);
+            let _ = self.save_to_cache(&file_source_key(id, batch_id, file_name), &file.source);
+            let _ = self.save_to_cache(&latest_file_key(id, file_name), batch_id);
+            file_names.push(file_name.to_string());
+
+            let metadata = file_metadata
+                .remove(file_name.as_str())
+                .or_else(|| file.metadata.clone());
+            if let Some(metadata) = metadata {
+                self.save_file_metadata(id, batch_id, file_name, &metadata)?;
+            }
+        }
+        info!("{} files carried over", carried_over.len());
+
+        let file_names = serde_json::to_string(&file_names)
+            .map_err(|e| SynxServerError::SerializationError(e.to_string()))?;
+        let _ = self.save_to_cache(&batch_files_key(id, batch_id), &file_names);
+
+        if !symlinks.is_empty() {
+            let symlinks = serde_json::to_string(&symlinks)
+                .map_err(|e| SynxServerError::SerializationError(e.to_string()))?;
+            let _ = self.save_to_cache(&batch_symlinks_key(id, batch_id), &symlinks);
+        }
+
+        // Jobs queued before lineages were recorded have none.
+        if !lineage.is_empty() {
+            let _ = self.save_to_cache(&batch_lineage_key(id, batch_id), lineage);
+            let _ = self.save_to_cache(&latest_lineage_batch_key(id, lineage), batch_id);
+        }
+
+        let _ = self.save_to_cache(&latest_batch_key(id), batch_id);
+        Ok(merkle_root)
+    }
+
+    fn save_file_metadata(
+        &self,
+        id: &str,
+        batch_id: &str,
+        file_name: &str,
+        metadata: &FileMetadata,
+    ) -> Result<()> {
+        let metadata = serde_json::to_string(metadata)
+            .map_err(|e| SynxServerError::SerializationError(e.to_string()))?;
+        let _ = self.save_to_cache(&file_metadata_key(id, batch_id, file_name), &metadata);
+
         Ok(())
     }
+
+    /// Looks up the files of batch `base_batch_id` that an incremental upload
+    /// carries over, skipping any the archive holds a new version of.
+    fn carried_over_files(
+        &self,
+        id: &str,
+        base_batch_id: &str,
+        unchanged_files: &[String],
+        uploaded: &HashMap<String, String>,
+    ) -> Result<Vec<CarriedOverFile>> {
+        let mut files = Vec::with_capacity(unchanged_files.len());
+        for file_name in unchanged_files {
+            if uploaded.contains_key(file_name) {
+                continue;
+            }
+
+            let leaf = self
+                .fetch_from_cache(&batch_file_key(id, base_batch_id, file_name))?
+                .ok_or_else(|| SynxServerError::FileNotFound(file_name.to_string()))?;
+
+            // A file carried over more than once still points at the batch that
+            // first stored it.
+            let source = self
+                .fetch_from_cache(&file_source_key(id, base_batch_id, file_name))?
+                .unwrap_or_else(|| base_batch_id.to_string());
+
+            let metadata = self
+                .fetch_from_cache(&file_metadata_key(id, base_batch_id, file_name))?
+                .and_then(|metadata| serde_json::from_str(&metadata).ok());
+
+            files.push(CarriedOverFile {
+                file_name: file_name.to_string(),
+                leaf,
+                source,
+                metadata,
+            });
+        }
+
+        Ok(files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_batch_test() {
+        // A batch without files fails its job instead of panicking the worker.
+        assert!(matches!(
+            Worker::write_merkle_tree_to_file("id", "batch", Vec::new()),
+            Err(SynxServerError::EmptyBatch)
+        ));
+    }
 }
@@ -1,7 +1,13 @@
 mod core;
 
-use common::{common::logger_init, syncx::syncx_server::SyncxServer};
-use core::{config::Config, server::Server as InnerServer, store_v1::StoreV1, utils::*};
+use common::{
+    common::{logger_init, MAX_REQUEST_SIZE},
+    syncx::{syncx_server::SyncxServer, FILE_DESCRIPTOR_SET},
+};
+use core::{
+    config::Config, health::HealthChecker, server::Server as InnerServer, store_v1::StoreV1,
+    utils::*,
+};
 use log::{error, info};
 
 use tonic::transport::Server;
@@ -18,11 +24,23 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let db_client = connect_db(&config.database_url).await?;
     let redis_client = connect_redis(&config.redis_url)?;
 
+    let health_checker = HealthChecker::new(
+        db_client.clone(),
+        &config.db_name,
+        redis_client.clone(),
+        &config.gcs_bucket_name,
+        &config.api_key,
+    );
+
     // Initialize StoreV1, panic on failure
     let store_v1 = StoreV1::new(db_client, redis_client, &config.db_name)
         .await
         .unwrap_or_else(|e| panic!("Failed to initialize StoreV1: {}", e));
 
+    let tls_config = config
+        .tls_config()
+        .unwrap_or_else(|e| panic!("Failed to load TLS configuration: {}", e));
+
     // Set up the server
     let server = InnerServer::new(store_v1, config).await;
 
@@ -31,10 +49,37 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .parse::<std::net::SocketAddr>()
         .expect("Failed to parse server address");
 
-    let synx_server = SyncxServer::new(server);
+    let synx_server = SyncxServer::new(server).max_decoding_message_size(MAX_REQUEST_SIZE);
+
+    // Reported as not serving until the first round of checks has passed.
+    let (mut health_reporter, health_server) = tonic_health::server::health_reporter();
+    health_reporter
+        .set_not_serving::<SyncxServer<InnerServer<StoreV1>>>()
+        .await;
+    health_reporter
+        .set_service_status("", tonic_health::ServingStatus::NotServing)
+        .await;
+    tokio::spawn(health_checker.run::<SyncxServer<InnerServer<StoreV1>>>(health_reporter));
+
+    let reflection_server = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+        .build()?;
+
+    let mut builder = Server::builder();
+    if let Some(tls_config) = tls_config {
+        info!("Serving over TLS");
+        builder = builder.tls_config(tls_config)?;
+    }
 
     info!("Server is running on address {}", addr);
-    if let Err(e) = Server::builder().add_service(synx_server).serve(addr).await {
+    if let Err(e) = builder
+        .add_service(health_server)
+        .add_service(reflection_server)
+        .add_service(synx_server)
+        .serve(addr)
+        .await
+    {
         error!("Server failed to start due to {}", e)
     }
 
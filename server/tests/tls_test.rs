@@ -0,0 +1,228 @@
+use async_trait::async_trait;
+use common::syncx::{syncx_client::SyncxClient, syncx_server::SyncxServer, CreateClientRequest};
+use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa};
+use server::core::{
+    config::Config,
+    definitions::{ClientObject, Job, JobRecord, Result, Store, UploadSession},
+    server::Server as InnerServer,
+};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use tempfile::TempDir;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::{
+    Certificate as TlsCertificate, Channel, ClientTlsConfig, Endpoint, Identity, Server,
+};
+
+/// In-memory `Store`, enough to serve `RegisterClient` without MongoDB or Redis.
+#[derive(Default)]
+struct MockStore {
+    clients: Mutex<HashMap<String, ClientObject>>,
+}
+
+#[async_trait]
+impl Store for MockStore {
+    async fn get_client_object(&self, id: &str) -> Result<Option<ClientObject>> {
+        Ok(self
+            .clients
+            .lock()
+            .unwrap()
+            .get(id)
+            .map(|client| ClientObject {
+                id: client.id.clone(),
+                password: client.password.clone(),
+            }))
+    }
+
+    fn fetch_from_cache(&self, _key: &str) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    async fn save_client_object(&self, client_object: ClientObject) -> Result<bool> {
+        self.clients
+            .lock()
+            .unwrap()
+            .insert(client_object.id.clone(), client_object);
+        Ok(true)
+    }
+
+    fn enqueue_job(&self, _job: &Job) -> Result<()> {
+        Ok(())
+    }
+
+    fn save_upload_session(&self, _session: &UploadSession) -> Result<()> {
+        Ok(())
+    }
+
+    fn get_upload_session(&self, _id: &str) -> Result<Option<UploadSession>> {
+        Ok(None)
+    }
+
+    fn delete_upload_session(&self, _id: &str) -> Result<()> {
+        Ok(())
+    }
+
+    fn save_job_record(&self, _record: &JobRecord) -> Result<()> {
+        Ok(())
+    }
+
+    fn get_job_record(&self, _batch_id: &str) -> Result<Option<JobRecord>> {
+        Ok(None)
+    }
+}
+
+/// A self-signed CA, and server and client certificates signed by it, written as
+/// PEM files to a temporary directory.
+struct TestCertificates {
+    dir: TempDir,
+}
+
+impl TestCertificates {
+    fn generate() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut ca_params = CertificateParams::new(vec![]);
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca_params
+            .distinguished_name
+            .push(DnType::CommonName, "syncx test ca");
+        let ca = Certificate::from_params(ca_params).unwrap();
+        fs::write(dir.path().join("ca.pem"), ca.serialize_pem().unwrap()).unwrap();
+
+        for (name, subject_alt_names) in [
+            ("server", vec!["localhost".to_string()]),
+            ("client", vec![]),
+        ] {
+            let cert = Certificate::from_params(CertificateParams::new(subject_alt_names)).unwrap();
+            fs::write(
+                dir.path().join(format!("{}.pem", name)),
+                cert.serialize_pem_with_signer(&ca).unwrap(),
+            )
+            .unwrap();
+            fs::write(
+                dir.path().join(format!("{}.key", name)),
+                cert.serialize_private_key_pem(),
+            )
+            .unwrap();
+        }
+
+        Self { dir }
+    }
+
+    fn path(&self, file_name: &str) -> String {
+        self.dir
+            .path()
+            .join(file_name)
+            .to_string_lossy()
+            .to_string()
+    }
+
+    fn read(&self, file_name: &str) -> Vec<u8> {
+        fs::read(Path::new(&self.path(file_name))).unwrap()
+    }
+}
+
+fn test_config(certs: &TestCertificates, require_client_cert: bool) -> Config {
+    Config {
+        database_url: String::new(),
+        redis_url: String::new(),
+        jwt_secret: "secret".to_string(),
+        jwt_exp: 3600,
+        db_name: String::new(),
+        gcs_bucket_name: String::new(),
+        api_key: String::new(),
+        tls_cert_path: Some(certs.path("server.pem")),
+        tls_key_path: Some(certs.path("server.key")),
+        tls_client_ca_path: require_client_cert.then(|| certs.path("ca.pem")),
+    }
+}
+
+/// Serves the syncx service with the TLS settings of `config` on a free local
+/// port, and returns the port.
+async fn start_server(config: Config) -> u16 {
+    let tls_config = config.tls_config().unwrap().unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let server = InnerServer::new(MockStore::default(), config).await;
+    let mut builder = Server::builder().tls_config(tls_config).unwrap();
+    tokio::spawn(
+        builder
+            .add_service(SyncxServer::new(server))
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+
+    port
+}
+
+async fn connect(port: u16, tls_config: Option<ClientTlsConfig>) -> Option<SyncxClient<Channel>> {
+    let endpoint = match tls_config {
+        Some(tls_config) => Endpoint::from_shared(format!("https://localhost:{}", port))
+            .unwrap()
+            .tls_config(tls_config)
+            .unwrap(),
+        None => Endpoint::from_shared(format!("http://localhost:{}", port)).unwrap(),
+    };
+
+    endpoint.connect().await.ok().map(SyncxClient::new)
+}
+
+/// Whether a client connecting with `tls_config` can register an account.
+async fn can_register(port: u16, tls_config: Option<ClientTlsConfig>) -> bool {
+    let mut client = match connect(port, tls_config).await {
+        Some(client) => client,
+        None => return false,
+    };
+
+    client
+        .register_client(CreateClientRequest {
+            password: "password".to_string(),
+        })
+        .await
+        .is_ok()
+}
+
+fn client_tls_config(certs: &TestCertificates, with_identity: bool) -> ClientTlsConfig {
+    let tls_config = ClientTlsConfig::new()
+        .ca_certificate(TlsCertificate::from_pem(certs.read("ca.pem")))
+        .domain_name("localhost");
+
+    if with_identity {
+        tls_config.identity(Identity::from_pem(
+            certs.read("client.pem"),
+            certs.read("client.key"),
+        ))
+    } else {
+        tls_config
+    }
+}
+
+#[tokio::test]
+async fn should_serve_over_tls() {
+    let certs = TestCertificates::generate();
+    let port = start_server(test_config(&certs, false)).await;
+
+    assert!(can_register(port, Some(client_tls_config(&certs, false))).await);
+    assert!(!can_register(port, None).await);
+}
+
+#[tokio::test]
+async fn should_reject_untrusted_server_certificate() {
+    let certs = TestCertificates::generate();
+    let other_certs = TestCertificates::generate();
+    let port = start_server(test_config(&certs, false)).await;
+
+    assert!(!can_register(port, Some(client_tls_config(&other_certs, false))).await);
+}
+
+#[tokio::test]
+async fn should_require_client_certificate_for_mutual_tls() {
+    let certs = TestCertificates::generate();
+    let port = start_server(test_config(&certs, true)).await;
+
+    assert!(can_register(port, Some(client_tls_config(&certs, true))).await);
+    assert!(!can_register(port, Some(client_tls_config(&certs, false))).await);
+}